@@ -1,15 +1,20 @@
+use crate::command::{self, Command, GroupCommand, Input};
 use crate::config::Config;
 use crate::cursor::Cursor;
+use crate::outbox::{Outbox, OutboxItem, SendOutcome};
+use crate::search::{SearchIndex, SearchResult, MESSAGE_SEARCH_PREFIX, SEARCH_RESULTS_LIMIT};
 use crate::signal::{
-    self, Attachment, GroupIdentifierBytes, GroupMasterKeyBytes, ResolvedGroup, SignalManager,
+    self, Attachment, Contact, GroupChange, GroupIdentifierBytes, GroupMasterKeyBytes, Mention,
+    ResolvedGroup, SafetyNumber, SendFuture, SignalManager, Sticker, MENTION_PLACEHOLDER,
 };
-use crate::storage::Storage;
+use crate::storage::{Storage, MESSAGES_PAGE_SIZE};
 use crate::util::{
     self, FilteredStatefulList, LazyRegex, StatefulList, ATTACHMENT_REGEX, URL_REGEX,
 };
 
-use anyhow::{anyhow, Context as _};
+use anyhow::{anyhow, bail, Context as _};
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers, MouseEvent};
+use indexmap::IndexSet;
 use itertools::FoldWhile::{Continue, Done};
 use itertools::Itertools;
 use notify_rust::Notification;
@@ -18,21 +23,23 @@ use presage::prelude::proto::{AttachmentPointer, ReceiptMessage, TypingMessage};
 use presage::prelude::{
     content::{ContentBody, DataMessage, Metadata, SyncMessage},
     proto::{
-        data_message::{Quote, Reaction},
-        sync_message::Sent,
+        data_message::{self, Delete, Flags, Quote, Reaction},
+        sync_message::{Blocked, Groups, Read, Sent},
         GroupContextV2,
     },
-    AttachmentSpec, Content, GroupMasterKey, GroupSecretParams, ServiceAddress,
+    Content, GroupMasterKey, GroupSecretParams, ServiceAddress,
 };
 use regex_automata::Regex;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use std::borrow::Cow;
-use std::collections::{hash_map::Entry, HashMap, HashSet};
-use std::convert::{TryFrom, TryInto};
-use std::path::Path;
-use std::str::FromStr;
+use std::cmp::Reverse;
+use std::collections::{hash_map::Entry, BTreeMap, BTreeSet, HashMap, HashSet};
+use std::convert::TryInto;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::UnboundedSender;
 
 pub struct App {
     pub config: Config,
@@ -47,6 +54,105 @@ pub struct App {
     pub is_searching: bool,
     pub channel_text_width: usize,
     receipt_handler: ReceiptHandler,
+    pending_changes: IndexSet<PendingChange>,
+    /// Number of failed flushes per pending change
+    failed_changes: HashMap<PendingChange, u32>,
+    /// Number of changes which were dropped after failing to be persisted; reported in the UI
+    /// until restart
+    dropped_changes: usize,
+    /// Changed messages which are not loaded, until they are persisted
+    unloaded_messages: HashMap<(ChannelId, u64), Message>,
+    flush_notifier: Option<UnboundedSender<()>>,
+    /// Error of the last failed flush, shown in the UI
+    pub storage_error: Option<String>,
+    /// Contacts synced from our primary device; refreshed from the signal manager on sync
+    contacts: HashMap<Uuid, Contact>,
+    search_index: SearchIndex,
+    /// Query of the current search results; `None` if the results are outdated
+    search_query: Option<String>,
+    /// Results of the storage search per query, which only change when messages are removed
+    stored_search_results: HashMap<String, Vec<SearchResult>>,
+    pub search_results: StatefulList<SearchResult>,
+    /// Whether the Signal servers are reachable; the outbox is sent only when online
+    pub is_online: bool,
+    /// Sending of the in-flight outbox item, which is not spawned yet
+    outgoing: Option<(u64, SendFuture)>,
+    /// Error of the last invalid command, shown in the UI
+    pub command_error: Option<String>,
+    own_typing: OwnTyping,
+    /// Sendings of our typing indicators, which are not spawned yet
+    outgoing_typing: Vec<SendFuture>,
+    /// Safety number with the contact of the selected channel, shown instead of the channels
+    safety_number: Option<(Uuid, SafetyNumber)>,
+    /// Directory of the saved attachments of received messages
+    attachments_dir: Option<PathBuf>,
+}
+
+/// Change of the app data which is not persisted yet
+///
+/// A change only identifies the changed part of the app data; the current state of it is
+/// persisted on flush.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PendingChange {
+    Message {
+        channel_id: ChannelId,
+        arrived_at: u64,
+    },
+    Receipt {
+        channel_id: ChannelId,
+        arrived_at: u64,
+    },
+    Reaction {
+        channel_id: ChannelId,
+        arrived_at: u64,
+        from_id: Uuid,
+    },
+    /// Message which is not loaded, but was changed
+    UnloadedMessage {
+        channel_id: ChannelId,
+        arrived_at: u64,
+    },
+    /// Message which was removed from its channel
+    DeletedMessage {
+        channel_id: ChannelId,
+        arrived_at: u64,
+    },
+    /// Channel metadata without messages
+    Channel(ChannelId),
+    ChannelOrder,
+    Name(Uuid),
+    UsedWords,
+    Outbox,
+    /// Disappearing messages expired, also ones which are not loaded
+    ExpiredMessages,
+    /// Blocked contacts and groups
+    Blocked,
+    Identities,
+    /// Channel which was removed with all its messages
+    DeletedChannel(ChannelId),
+}
+
+/// Number of flushes after which a change which can't be persisted is dropped
+const MAX_PERSIST_ATTEMPTS: u32 = 5;
+
+/// Prefix of the text of system lines which are warnings, e.g. about a changed safety number
+const WARNING_PREFIX: &str = "⚠ ";
+
+/// Typing of others is shown for this duration after it was received, unless it is stopped or
+/// started again.
+pub const TYPING_TIMEOUT: Duration = Duration::from_secs(15);
+/// Our typing stops, when the input box doesn't change for this duration.
+pub const TYPING_IDLE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Our ongoing typing is sent again after this duration, but not more often.
+pub const TYPING_REFRESH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Our typing in the input box, which is sent to the selected channel
+#[derive(Debug, Default)]
+struct OwnTyping {
+    /// Content of the input box, when it was checked last
+    input: String,
+    /// Channel we are typing in, when our typing was sent, and when the input changed last
+    typing: Option<(ChannelId, Instant, Instant)>,
 }
 
 #[derive(Debug, Default, PartialEq, Eq)]
@@ -78,12 +184,14 @@ impl ReceiptHandler {
         true
     }
 
-    pub fn step(&mut self, signal_manager: &dyn SignalManager) -> bool {
+    /// Takes the next receipts to send: the sender, the timestamps of the messages and the
+    /// receipt type.
+    pub fn step(&mut self) -> Option<(Uuid, Vec<u64>, Receipt)> {
         if !self.do_tick() {
-            return false;
+            return None;
         }
         if self.receipt_set.is_empty() {
-            return false;
+            return None;
         }
 
         // Get any key
@@ -93,17 +201,13 @@ impl ReceiptHandler {
         match j {
             Entry::Occupied(mut e) => {
                 let u = e.get_mut();
-                if let Some((timestamps, receipt)) = u.get_data() {
-                    signal_manager.send_receipt(uuid, timestamps, receipt);
-                    if u.is_empty() {
-                        e.remove_entry();
-                    }
-                    true
-                } else {
-                    false
+                let (timestamps, receipt) = u.get_data()?;
+                if u.is_empty() {
+                    e.remove_entry();
                 }
+                Some((uuid, timestamps, receipt))
             }
-            Entry::Vacant(_) => false,
+            Entry::Vacant(_) => None,
         }
     }
 }
@@ -250,6 +354,18 @@ impl BoxData {
         self.cursor = Default::default();
         std::mem::take(&mut self.data)
     }
+
+    /// Replaces the content, and moves the cursor to its end.
+    fn set(&mut self, text: &str) {
+        self.take();
+        for c in text.chars() {
+            if c == '\n' {
+                self.new_line();
+            } else {
+                self.put_char(c);
+            }
+        }
+    }
 }
 
 #[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
@@ -257,6 +373,13 @@ pub struct AppData {
     pub channels: FilteredStatefulList<Channel>,
     pub names: HashMap<Uuid, String>,
     pub used_words: HashSet<String>,
+    pub outbox: Outbox,
+    /// Blocked contacts and groups: nothing is received from them
+    pub blocked: BTreeSet<ChannelId>,
+    /// Blocklist of our primary device as of its last sync
+    pub synced_blocked: BTreeSet<ChannelId>,
+    /// Identities of contacts as of their last message
+    pub identities: BTreeMap<Uuid, Identity>,
     #[serde(skip)] // ! We may want to save it
     pub input: BoxData,
     #[serde(skip)]
@@ -265,72 +388,77 @@ pub struct AppData {
     pub is_multiline_input: bool,
 }
 
+/// Identity key of a contact, which determines the safety number with them
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Identity {
+    /// Serialized public identity key
+    pub key: Vec<u8>,
+    /// Whether we compared the safety number with the contact; reset when the key changes
+    pub is_verified: bool,
+}
+
+/// Stored channel
+///
+/// Changing the stored fields requires a migration step in `storage::migration`.
 #[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
-#[serde(try_from = "JsonChannel")]
 pub struct Channel {
     pub id: ChannelId,
     pub name: String,
     pub group_data: Option<GroupData>,
-    #[serde(serialize_with = "Channel::serialize_msgs")]
+    #[serde(
+        serialize_with = "Channel::serialize_msgs",
+        deserialize_with = "Channel::deserialize_msgs"
+    )]
     pub messages: StatefulList<Message>,
     pub unread_messages: usize,
+    /// Expiration timer of disappearing messages in seconds; `None` if messages don't expire
+    pub expire_timer: Option<u32>,
+    /// Whether the channel was started by an unknown sender and is not accepted yet
+    ///
+    /// Neither receipts nor notifications are sent for messages in a message request.
+    pub is_message_request: bool,
+    #[serde(skip)]
     pub typing: TypingSet,
 }
 
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
-pub enum TypingSet {
-    SingleTyping(bool),
-    GroupTyping(HashSet<Uuid>),
-}
-
-/// Proxy type which allows us to apply post-deserialization conversion.
+/// Users typing in a channel, with the time when their typing was received last
 ///
-/// Used to migrate the schema. Change this type only in backwards-compatible way.
-#[derive(Deserialize)]
-pub struct JsonChannel {
-    pub id: ChannelId,
-    pub name: String,
-    #[serde(default)]
-    pub group_data: Option<GroupData>,
-    #[serde(deserialize_with = "Channel::deserialize_msgs")]
-    pub messages: StatefulList<Message>,
-    #[serde(default)]
-    pub unread_messages: usize,
+/// Typing is not persisted, so that stale typing does not outlive a restart.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct TypingSet {
+    received_at: HashMap<Uuid, Instant>,
 }
 
-impl TryFrom<JsonChannel> for Channel {
-    type Error = anyhow::Error;
-    fn try_from(channel: JsonChannel) -> anyhow::Result<Self> {
-        let is_group = channel.group_data.is_some();
-        let mut channel = Channel {
-            id: channel.id,
-            name: channel.name,
-            group_data: channel.group_data,
-            messages: channel.messages,
-            unread_messages: channel.unread_messages,
-            typing: {
-                if is_group {
-                    TypingSet::GroupTyping(HashSet::new())
-                } else {
-                    TypingSet::SingleTyping(false)
-                }
-            },
-        };
+impl TypingSet {
+    pub fn start(&mut self, id: Uuid, now: Instant) {
+        self.received_at.insert(id, now);
+    }
 
-        // 1. The master key in ChannelId::Group was replaced by group identifier,
-        // the former was stored in group_data.
-        match (channel.id, channel.group_data.as_mut()) {
-            (ChannelId::Group(id), Some(group_data)) if group_data.master_key_bytes == [0; 32] => {
-                group_data.master_key_bytes = id;
-                channel.id = ChannelId::from_master_key_bytes(id)?;
-            }
-            _ => (),
-        }
-        Ok(channel)
+    pub fn stop(&mut self, id: Uuid) {
+        self.received_at.remove(&id);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.received_at.is_empty()
+    }
+
+    /// Typing users, ordered by the time their typing was received
+    pub fn typists(&self) -> Vec<Uuid> {
+        self.received_at
+            .iter()
+            .sorted_by_key(|(_, &received_at)| received_at)
+            .map(|(&id, _)| id)
+            .collect()
+    }
+
+    /// Removes the typing which was received `TYPING_TIMEOUT` or longer before `now`
+    fn expire(&mut self, now: Instant) {
+        self.received_at
+            .retain(|_, received_at| now.duration_since(*received_at) < TYPING_TIMEOUT);
     }
 }
 
-#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct GroupData {
     #[serde(default)]
     pub master_key_bytes: GroupMasterKeyBytes,
@@ -338,6 +466,17 @@ pub struct GroupData {
     pub revision: u32,
 }
 
+impl GroupData {
+    /// Members with a known name
+    fn member_names<'a>(&self, names: &'a HashMap<Uuid, String>) -> Vec<(Uuid, &'a str)> {
+        self.members
+            .iter()
+            .filter_map(|uuid| Some((*uuid, names.get(uuid)?.as_str())))
+            .filter(|(_, name)| !name.is_empty())
+            .collect()
+    }
+}
+
 impl Channel {
     pub fn contains_user(&self, name: &str, hm: &HashMap<Uuid, String>) -> bool {
         match self.group_data {
@@ -352,26 +491,18 @@ impl Channel {
         }
         match pattern.chars().next().unwrap() {
             '@' => self.contains_user(&pattern[1..], hm),
+            // messages are searched instead of channels
+            MESSAGE_SEARCH_PREFIX => true,
             _ => self.name.contains(pattern),
         }
     }
 
     pub fn reset_writing(&mut self, user: Uuid) {
-        match &mut self.typing {
-            TypingSet::GroupTyping(ref mut hash_set) => {
-                hash_set.remove(&user);
-            }
-            TypingSet::SingleTyping(_) => {
-                self.typing = TypingSet::SingleTyping(false);
-            }
-        }
+        self.typing.stop(user);
     }
 
     pub fn is_writing(&self) -> bool {
-        match &self.typing {
-            TypingSet::GroupTyping(a) => !a.is_empty(),
-            TypingSet::SingleTyping(a) => *a,
-        }
+        !self.typing.is_empty()
     }
 
     fn user_id(&self) -> Option<Uuid> {
@@ -407,7 +538,7 @@ impl Channel {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum ChannelId {
     User(Uuid),
     Group(GroupIdentifierBytes),
@@ -420,7 +551,7 @@ impl From<Uuid> for ChannelId {
 }
 
 impl ChannelId {
-    fn from_master_key_bytes(bytes: impl AsRef<[u8]>) -> anyhow::Result<Self> {
+    pub fn from_master_key_bytes(bytes: impl AsRef<[u8]>) -> anyhow::Result<Self> {
         let master_key_ar = bytes
             .as_ref()
             .try_into()
@@ -449,11 +580,24 @@ impl TypingAction {
             }
         }
     }
+
+    pub fn to_i32(self) -> i32 {
+        match self {
+            Self::Started => 0,
+            Self::Stopped => 1,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum Receipt {
     Nothing, // Do not do anything to these receipts in order to avoid spamming receipt messages when an old database is loaded
+    /// Own message waiting in the outbox to be sent
+    Queued,
+    /// Own message which is being sent
+    Pending,
+    /// Own message which could not be sent; it can be retried or discarded
+    Failed,
     Sent,
     Received,
     Delivered,
@@ -469,6 +613,9 @@ impl Receipt {
     pub fn write(&self) -> &'static str {
         match self {
             Self::Nothing => "",
+            Self::Queued => "(queued)",
+            Self::Pending => "(.)",
+            Self::Failed => "(failed)",
             Self::Sent => "(x)",
             Self::Received => "(xx)",
             Self::Delivered => "(xxx)",
@@ -508,6 +655,28 @@ pub struct Message {
     pub reactions: Vec<(Uuid, String)>,
     #[serde(default)]
     pub receipt: Receipt,
+    /// Whether the message was deleted for everyone; the content of a deleted message is removed
+    #[serde(default)]
+    pub is_deleted: bool,
+    /// Expiration timer of the disappearing message in seconds, counted from when it was read
+    #[serde(default)]
+    pub expires_in: Option<u32>,
+    /// Timestamp in milliseconds at which the expiration timer of the disappearing message
+    /// started, i.e. when it was sent by us or read; `None` while it is unread
+    #[serde(default)]
+    pub expire_started_at: Option<u64>,
+    /// Whether the message is a system line, e.g. about a changed setting of the channel
+    #[serde(default)]
+    pub is_system: bool,
+    /// Whether the system line is a warning, e.g. about a changed safety number
+    #[serde(default)]
+    pub is_warning: bool,
+    /// Mentioned users, whose names replace the placeholders in the body
+    #[serde(default)]
+    pub mentions: Vec<Mention>,
+    /// Sticker sent instead of a body; its image is one of the attachments
+    #[serde(default)]
+    pub sticker: Option<Sticker>,
 }
 
 impl Message {
@@ -525,6 +694,35 @@ impl Message {
             attachments,
             reactions: Default::default(),
             receipt: Receipt::Sent,
+            is_deleted: false,
+            expires_in: None,
+            expire_started_at: None,
+            is_system: false,
+            is_warning: false,
+            mentions: Default::default(),
+            sticker: None,
+        }
+    }
+
+    /// Creates a sent text message without anything else, to be adjusted by the tests.
+    #[cfg(test)]
+    pub fn text(from_id: Uuid, text: &str, arrived_at: u64) -> Self {
+        Self::new(from_id, Some(text.to_string()), arrived_at, Vec::new())
+    }
+
+    /// Creates a system line caused by the given user.
+    pub fn system(from_id: Uuid, text: String, arrived_at: u64) -> Self {
+        Self {
+            is_system: true,
+            ..Self::new(from_id, Some(text), arrived_at, Vec::new())
+        }
+    }
+
+    /// Creates a system line with a warning caused by the given user.
+    pub fn warning(from_id: Uuid, text: String, arrived_at: u64) -> Self {
+        Self {
+            is_warning: true,
+            ..Self::system(from_id, format!("{}{}", WARNING_PREFIX, text), arrived_at)
         }
     }
 
@@ -537,11 +735,74 @@ impl Message {
             attachments: Default::default(),
             reactions: Default::default(),
             receipt: Receipt::Sent,
+            is_deleted: false,
+            expires_in: None,
+            expire_started_at: None,
+            is_system: false,
+            is_warning: false,
+            mentions: Mention::from_body_ranges(quote.body_ranges),
+            sticker: None,
         })
     }
 
     pub fn is_empty(&self) -> bool {
-        self.message.is_none() && self.attachments.is_empty() && self.reactions.is_empty()
+        self.message.is_none()
+            && self.sticker.is_none()
+            && self.attachments.is_empty()
+            && self.reactions.is_empty()
+    }
+
+    /// Timestamp in milliseconds at which the disappearing message expires, if its timer
+    /// started
+    pub fn expires_at(&self) -> Option<u64> {
+        Some(self.expire_started_at? + u64::from(self.expires_in?) * 1000)
+    }
+
+    /// Starts the expiration timer of the disappearing message at `now`, unless it started
+    /// already.
+    ///
+    /// Returns whether the timer was started.
+    pub fn start_expire_timer(&mut self, now: u64) -> bool {
+        if self.expires_in.is_none() || self.expire_started_at.is_some() {
+            return false;
+        }
+        self.expire_started_at = Some(now);
+        true
+    }
+
+    /// Removes the content of the message, and only keeps it as tombstone.
+    pub fn delete(&mut self) {
+        self.message = None;
+        self.quote = None;
+        self.attachments.clear();
+        self.reactions.clear();
+        self.mentions.clear();
+        self.sticker = None;
+        self.is_deleted = true;
+    }
+
+    /// Body with the mention placeholders replaced by `@` and the name of the mentioned user.
+    ///
+    /// Placeholders without mention are kept.
+    pub fn body_with_mentions<'a>(&self, name_by_id: impl Fn(Uuid) -> &'a str) -> Option<String> {
+        let body = self.message.as_ref()?;
+        if self.mentions.is_empty() {
+            return Some(body.clone());
+        }
+        let mut text = String::with_capacity(body.len());
+        let mut offset = 0;
+        for c in body.chars() {
+            let mention = self.mentions.iter().find(|mention| mention.start == offset);
+            match mention {
+                Some(mention) if c == MENTION_PLACEHOLDER => {
+                    text.push('@');
+                    text.push_str(name_by_id(mention.uuid));
+                }
+                _ => text.push(c),
+            }
+            offset += c.len_utf16() as u32;
+        }
+        Some(text)
     }
 }
 
@@ -552,9 +813,20 @@ pub enum Event {
     Click(MouseEvent),
     Input(KeyEvent),
     Message(Content),
-    Resize { cols: u16, rows: u16 },
+    Resize {
+        cols: u16,
+        rows: u16,
+    },
     Quit(Option<anyhow::Error>),
     Tick,
+    Flush,
+    /// Result of sending the outbox item with the given id
+    SendResult {
+        id: u64,
+        result: anyhow::Result<()>,
+    },
+    /// The Signal servers became reachable or unreachable
+    Online(bool),
 }
 
 impl App {
@@ -564,8 +836,27 @@ impl App {
         storage: Box<dyn Storage>,
     ) -> anyhow::Result<Self> {
         let user_id = signal_manager.user_id();
+        let now = util::utc_now_timestamp_msec();
+        let attachments_dir = signal::attachments_dir().ok();
+        match storage.delete_expired_messages(now) {
+            Ok(expired) => {
+                remove_attachment_files(attachments_dir.as_deref(), expired.iter().map(|(_, m)| m))
+            }
+            Err(e) => log::error!("failed to delete expired messages: {}", e),
+        }
         let data = storage.load_app_data(user_id, config.user.name.clone())?;
-        Ok(Self {
+        let mut search_index = SearchIndex::default();
+        for channel in &data.channels.items {
+            for message in &channel.messages.items {
+                search_index.add(channel.id, message);
+            }
+        }
+        let contacts = signal_manager
+            .contacts()
+            .into_iter()
+            .map(|contact| (contact.uuid, contact))
+            .collect();
+        let mut app = Self {
             config,
             signal_manager,
             storage,
@@ -578,7 +869,28 @@ impl App {
             is_searching: false,
             channel_text_width: 0,
             receipt_handler: ReceiptHandler::new(),
-        })
+            pending_changes: IndexSet::new(),
+            failed_changes: HashMap::new(),
+            dropped_changes: 0,
+            unloaded_messages: HashMap::new(),
+            flush_notifier: None,
+            storage_error: None,
+            contacts,
+            search_index,
+            search_query: None,
+            stored_search_results: HashMap::new(),
+            search_results: StatefulList::with_items(Vec::new()),
+            is_online: false,
+            outgoing: None,
+            command_error: None,
+            own_typing: Default::default(),
+            outgoing_typing: Vec::new(),
+            safety_number: None,
+            attachments_dir,
+        };
+        app.reset_unsent_messages();
+        app.expire_messages(now);
+        Ok(app)
     }
 
     pub fn get_input(&mut self) -> &mut BoxData {
@@ -593,19 +905,11 @@ impl App {
         if !channel.is_writing() {
             return String::from("");
         }
-        let uuids: Vec<Uuid> = match &channel.typing {
-            TypingSet::GroupTyping(hash_set) => hash_set.clone().into_iter().collect(),
-            TypingSet::SingleTyping(a) => {
-                if *a {
-                    vec![channel.user_id().unwrap()]
-                } else {
-                    Vec::new()
-                }
-            }
-        };
         format!(
             "{:?} writing...",
-            uuids
+            channel
+                .typing
+                .typists()
                 .into_iter()
                 .map(|u| self.name_by_id(u))
                 .collect::<Vec<&str>>()
@@ -616,6 +920,242 @@ impl App {
         self.storage.save_app_data(&self.data)
     }
 
+    /// Sets the sender which is notified when the app data becomes dirty.
+    ///
+    /// The receiver is expected to trigger a `flush` after a delay, s.t. a burst of changes is
+    /// persisted at once.
+    pub fn set_flush_notifier(&mut self, notifier: UnboundedSender<()>) {
+        self.flush_notifier = Some(notifier);
+        if !self.pending_changes.is_empty() {
+            self.notify_flush();
+        }
+    }
+
+    /// Marks a change of the app data to be persisted with the next flush.
+    pub fn mark_dirty(&mut self, change: PendingChange) {
+        if self.pending_changes.is_empty() {
+            self.notify_flush();
+        }
+        self.pending_changes.insert(change);
+    }
+
+    /// Marks the channel at the given index and the order of all channels as changed.
+    fn mark_channel_dirty(&mut self, channel_idx: usize) {
+        let channel_id = self.data.channels.items[channel_idx].id;
+        self.mark_dirty(PendingChange::Channel(channel_id));
+        self.mark_dirty(PendingChange::ChannelOrder);
+    }
+
+    fn notify_flush(&self) {
+        if let Some(notifier) = self.flush_notifier.as_ref() {
+            // the receiver is gone only on shutdown, where the app is flushed explicitly
+            let _ = notifier.send(());
+        }
+    }
+
+    /// Persists the pending changes.
+    ///
+    /// If the storage supports granular operations, only the changed parts are persisted,
+    /// otherwise the full app data is saved. On failure, the changes which were not persisted are
+    /// kept pending for the next flush, and the error is shown in the UI. A single change which
+    /// fails to be persisted `MAX_PERSIST_ATTEMPTS` times is dropped, which is shown in the UI until
+    /// restart.
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        if self.pending_changes.is_empty() {
+            return Ok(());
+        }
+        let res = if self.storage.is_incremental() {
+            let mut res = Ok(());
+            for change in std::mem::take(&mut self.pending_changes) {
+                if let Err(e) = self.persist_change(change) {
+                    let attempts = self.failed_changes.entry(change).or_default();
+                    *attempts += 1;
+                    if *attempts < MAX_PERSIST_ATTEMPTS {
+                        self.pending_changes.insert(change);
+                    } else {
+                        log::error!(
+                            "dropping {:?} after {} failed attempts: {}",
+                            change,
+                            attempts,
+                            e
+                        );
+                        self.failed_changes.remove(&change);
+                        self.dropped_changes += 1;
+                    }
+                    res = res.and(Err(e));
+                } else {
+                    self.failed_changes.remove(&change);
+                }
+            }
+            res
+        } else {
+            let is_expired = self
+                .pending_changes
+                .contains(&PendingChange::ExpiredMessages);
+            let mut res = self.save();
+            if res.is_ok() && is_expired {
+                // also removes the expired messages from the backups
+                res = self.delete_expired_messages();
+            }
+            if res.is_ok() {
+                self.pending_changes.clear();
+            }
+            res
+        };
+        let pending_changes = &self.pending_changes;
+        self.unloaded_messages
+            .retain(|&(channel_id, arrived_at), _| {
+                pending_changes.contains(&PendingChange::UnloadedMessage {
+                    channel_id,
+                    arrived_at,
+                })
+            });
+        match res {
+            Ok(()) => {
+                self.storage_error = (self.dropped_changes > 0).then(|| {
+                    format!(
+                        "Failed to save {} changes, which are lost",
+                        self.dropped_changes
+                    )
+                });
+                Ok(())
+            }
+            Err(e) => {
+                self.storage_error = Some(format!("Failed to save: {}", e));
+                if !self.pending_changes.is_empty() {
+                    self.notify_flush();
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn persist_change(&self, change: PendingChange) -> anyhow::Result<()> {
+        match change {
+            PendingChange::Message {
+                channel_id,
+                arrived_at,
+            } => {
+                if let Some(channel) = self.find_channel(channel_id) {
+                    if let Some(message) = self.find_message(channel_id, arrived_at) {
+                        self.storage.append_message(channel, message)?;
+                    }
+                }
+            }
+            PendingChange::Receipt {
+                channel_id,
+                arrived_at,
+            } => {
+                if let Some(message) = self.find_message(channel_id, arrived_at) {
+                    self.storage
+                        .update_receipt(channel_id, arrived_at, message.receipt)?;
+                }
+            }
+            PendingChange::Reaction {
+                channel_id,
+                arrived_at,
+                from_id,
+            } => {
+                if let Some(message) = self.find_message(channel_id, arrived_at) {
+                    let emoji = message
+                        .reactions
+                        .iter()
+                        .find(|(id, _)| *id == from_id)
+                        .map(|(_, emoji)| emoji.as_str());
+                    self.storage
+                        .upsert_reaction(channel_id, arrived_at, from_id, emoji)?;
+                }
+            }
+            PendingChange::UnloadedMessage {
+                channel_id,
+                arrived_at,
+            } => {
+                if let Some(channel) = self.find_channel(channel_id) {
+                    if let Some(message) = self.unloaded_messages.get(&(channel_id, arrived_at)) {
+                        self.storage.append_message(channel, message)?;
+                    }
+                }
+            }
+            PendingChange::DeletedMessage {
+                channel_id,
+                arrived_at,
+            } => {
+                self.storage.delete_message(channel_id, arrived_at)?;
+            }
+            PendingChange::Channel(channel_id) => {
+                if let Some(channel) = self.find_channel(channel_id) {
+                    self.storage.update_channel_meta(channel)?;
+                }
+            }
+            PendingChange::ChannelOrder => {
+                let channel_ids: Vec<ChannelId> =
+                    self.data.channels.items.iter().map(|c| c.id).collect();
+                self.storage.update_channel_order(&channel_ids)?;
+            }
+            PendingChange::Name(id) => {
+                if let Some(name) = self.data.names.get(&id) {
+                    self.storage.upsert_name(id, name)?;
+                }
+            }
+            PendingChange::UsedWords => {
+                self.storage.update_used_words(&self.data.used_words)?;
+            }
+            PendingChange::Outbox => {
+                self.storage.update_outbox(&self.data.outbox)?;
+            }
+            PendingChange::ExpiredMessages => self.delete_expired_messages()?,
+            PendingChange::Blocked => {
+                self.storage
+                    .update_blocked(&self.data.blocked, &self.data.synced_blocked)?;
+            }
+            PendingChange::Identities => {
+                self.storage.update_identities(&self.data.identities)?;
+            }
+            PendingChange::DeletedChannel(channel_id) => {
+                self.storage.delete_channel(channel_id)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn channel_idx(&self, channel_id: ChannelId) -> Option<usize> {
+        self.data
+            .channels
+            .items
+            .iter()
+            .position(|channel| channel.id == channel_id)
+    }
+
+    fn find_channel(&self, channel_id: ChannelId) -> Option<&Channel> {
+        self.data
+            .channels
+            .items
+            .iter()
+            .find(|channel| channel.id == channel_id)
+    }
+
+    pub fn find_message(&self, channel_id: ChannelId, arrived_at: u64) -> Option<&Message> {
+        self.find_channel(channel_id)?
+            .messages
+            .items
+            .iter()
+            .rev()
+            .find(|message| message.arrived_at == arrived_at)
+    }
+
+    fn find_message_mut(&mut self, channel_id: ChannelId, arrived_at: u64) -> Option<&mut Message> {
+        self.data
+            .channels
+            .items
+            .iter_mut()
+            .find(|channel| channel.id == channel_id)?
+            .messages
+            .items
+            .iter_mut()
+            .rev()
+            .find(|message| message.arrived_at == arrived_at)
+    }
+
     pub fn name_by_id(&self, id: Uuid) -> &str {
         name_by_id(&self.data.names, id)
     }
@@ -634,6 +1174,9 @@ impl App {
                     self.send_input(self.data.channels.filtered_items[idx])?;
                 }
             }
+            KeyCode::Enter if self.is_message_search() => {
+                self.jump_to_search_result();
+            }
             KeyCode::Enter => {
                 // input is empty
                 self.try_open_url();
@@ -654,16 +1197,26 @@ impl App {
                 self.get_input().on_backspace();
             }
             KeyCode::Esc => self.reset_message_selection(),
+            KeyCode::Char('r') if key.modifiers.contains(KeyModifiers::ALT) => {
+                self.retry_selected_message();
+            }
+            KeyCode::Char('d') if key.modifiers.contains(KeyModifiers::ALT) => {
+                self.discard_selected_message();
+            }
+            KeyCode::Char('x') if key.modifiers.contains(KeyModifiers::ALT) => {
+                self.delete_selected_message();
+            }
             KeyCode::Char(c) => self.get_input().put_char(c),
             KeyCode::Tab => {
                 if let Some(idx) = self.data.channels.state.selected() {
-                    self.add_reaction(idx);
+                    let channel_idx = self.data.channels.filtered_items[idx];
+                    if self.complete_mention(channel_idx).is_none() {
+                        self.add_reaction(idx);
+                    }
                 }
             }
             KeyCode::BackTab => {
-                if let Some(idx) = self.data.channels.state.selected() {
-                    self.complete(idx)?;
-                }
+                self.complete();
             }
             _ => {}
         }
@@ -697,27 +1250,97 @@ impl App {
         }
     }
 
-    pub fn get_completion(&mut self, input: String) -> Option<String> {
-        let fragment = input.split(" ").last();
-        for word in self.data.used_words {
-            // returns None if it's not a prefix
-            let maybe_addition = word.strip_prefix(fragment);
-            match addition {
-                Some(extra) => return Some(format!("{}{}", input, extra)),
-                None => (),
-            }
+    /// Returns the shortest extension of the word in front of the cursor to a previously used
+    /// word, or `None` if there is nothing to complete.
+    fn get_completion(&self) -> Option<String> {
+        let input = &self.data.input;
+        let fragment = input.data[..input.cursor.idx]
+            .rsplit(char::is_whitespace)
+            .next()?;
+        if fragment.is_empty() {
+            return None;
         }
-        // instead of immediately returning, you could keep
-        // the set of extensions and use a heuristic for
-        // selecting the best one. maybe used_words is a
-        // counter for frequency, or there's some context
-        return None()
+        self.data
+            .used_words
+            .iter()
+            .filter_map(|word| word.strip_prefix(fragment))
+            .filter(|extension| !extension.is_empty())
+            .min_by(|a, b| a.len().cmp(&b.len()).then_with(|| a.cmp(b)))
+            .map(str::to_string)
     }
-    pub fn complete(&mut self, channel_idx: usize) -> Option<()> {
-        let input = self.take_input();
-        for completion_char in get_completion(self, input).chars() {
-            self.put_char(completion_char)
+
+    /// Completes the word in front of the cursor to a previously used word.
+    fn complete(&mut self) -> Option<()> {
+        if self.is_searching {
+            return None;
+        }
+        let completion = self.get_completion()?;
+        for c in completion.chars() {
+            self.data.input.put_char(c);
         }
+        Some(())
+    }
+
+    /// Completes the name of a group member after `@` in front of the cursor.
+    ///
+    /// If several names match, completes their common prefix. Returns `None`, if there is
+    /// nothing to complete.
+    fn complete_mention(&mut self, channel_idx: usize) -> Option<()> {
+        if self.is_searching {
+            return None;
+        }
+        let group_data = self.data.channels.items[channel_idx].group_data.as_ref()?;
+        let input = &self.data.input;
+        let before_cursor = &input.data[..input.cursor.idx];
+        let at = before_cursor.rfind('@')?;
+        if !before_cursor[..at]
+            .chars()
+            .last()
+            .map_or(true, char::is_whitespace)
+        {
+            return None; // e.g. an email address
+        }
+        let fragment = &before_cursor[at + 1..];
+        if fragment.contains('\n') {
+            return None;
+        }
+
+        let fragment_lowercase = fragment.to_lowercase();
+        let mut candidates: Vec<&str> = group_data
+            .member_names(&self.data.names)
+            .into_iter()
+            .map(|(_, name)| name)
+            .filter(|name| name.to_lowercase().starts_with(&fragment_lowercase))
+            .collect();
+        candidates.sort_unstable();
+        candidates.dedup();
+        let completion = match candidates[..] {
+            [] => return None,
+            [name] => format!("{} ", name),
+            // names are sorted => common prefix of all is the common prefix of the first and last
+            [first, .., last] => {
+                let common_len = first
+                    .char_indices()
+                    .zip(last.chars())
+                    .find(|((_, a), b)| a != b)
+                    .map_or(first.len(), |((idx, _), _)| idx);
+                first[..common_len].to_string()
+            }
+        };
+        let fragment_len = fragment.chars().count();
+        if completion.chars().count() <= fragment_len {
+            return None; // nothing to add
+        }
+
+        // replace the fragment, s.t. the case of the name is kept
+        let input = &mut self.data.input;
+        for _ in 0..fragment_len {
+            input.on_backspace();
+        }
+        for c in completion.chars() {
+            input.put_char(c);
+        }
+        Some(())
     }
 
     pub fn add_reaction(&mut self, channel_idx: usize) -> Option<()> {
@@ -737,11 +1360,16 @@ impl App {
             })
         })?;
 
-        self.signal_manager
-            .send_reaction(channel, message, emoji.clone(), remove);
-
         let channel_id = channel.id;
         let arrived_at = message.arrived_at;
+        self.enqueue(OutboxItem::Reaction {
+            channel_id,
+            target_author: message.from_id,
+            target_arrived_at: arrived_at,
+            emoji: emoji.clone(),
+            remove,
+        });
+
         self.handle_reaction(
             channel_id,
             arrived_at,
@@ -755,7 +1383,7 @@ impl App {
         self.bubble_up_channel(channel_idx);
         self.reset_message_selection();
 
-        self.save().unwrap();
+        self.mark_channel_dirty(0);
         Some(())
     }
 
@@ -773,55 +1401,669 @@ impl App {
 
     fn send_input(&mut self, channel_idx: usize) -> anyhow::Result<()> {
         let input = self.take_input();
-        self.used_words.extend(HashSet::from_iter(input.split(" ").iter()))
+        self.command_error = None;
+        let input = match command::parse(&input) {
+            Ok(Input::Message(_)) if self.data.channels.items[channel_idx].is_message_request => {
+                self.command_error = Some("accept the message request first: /accept".to_string());
+                self.data.input.set(&input);
+                return Ok(());
+            }
+            Ok(Input::Message(message)) => message.to_string(),
+            Ok(Input::Command(command)) => {
+                if let Err(e) = self.run_command(channel_idx, command) {
+                    self.command_error = Some(e.to_string());
+                    self.data.input.set(&input);
+                }
+                return Ok(());
+            }
+            Err(e) => {
+                // keep the input to correct the command
+                self.command_error = Some(e.to_string());
+                self.data.input.set(&input);
+                return Ok(());
+            }
+        };
+        let used_words_len = self.data.used_words.len();
+        self.data
+            .used_words
+            .extend(input.split_whitespace().map(str::to_string));
+        if self.data.used_words.len() != used_words_len {
+            self.mark_dirty(PendingChange::UsedWords);
+        }
         let (input, attachments) = self.extract_attachments(&input);
-        let channel = &mut self.data.channels.items[channel_idx];
+        let channel = &self.data.channels.items[channel_idx];
+        let channel_id = channel.id;
         let quote = channel.selected_message();
-        let sent_message = self
-            .signal_manager
-            .send_text(channel, input, quote, attachments);
-
-        let sent_with_quote = sent_message.quote.is_some();
-        channel.messages.items.push(sent_message);
+        let mut message = self.signal_manager.create_text(input, quote, attachments);
+        message.expires_in = channel.expire_timer;
+        message.start_expire_timer(message.arrived_at);
+        if let (Some(group_data), Some(body)) = (channel.group_data.as_ref(), &message.message) {
+            let members = group_data.member_names(&self.data.names);
+            let (body, mentions) = extract_mentions(body, &members);
+            message.message = Some(body);
+            message.mentions = mentions;
+        }
 
-        self.reset_unread_messages();
-        if sent_with_quote {
+        if message.quote.is_some() {
             self.reset_message_selection();
         }
+        let arrived_at = message.arrived_at;
+        self.add_sent_message(channel_idx, message);
+        self.enqueue(OutboxItem::Message {
+            channel_id,
+            arrived_at,
+        });
+
+        Ok(())
+    }
+
+    /// Runs the command in the channel; fails if the command can't be applied to the channel.
+    fn run_command(&mut self, channel_idx: usize, command: Command) -> anyhow::Result<()> {
+        let channel = &self.data.channels.items[channel_idx];
+        let channel_id = channel.id;
+        match command {
+            Command::ExpireTimer(timer) => {
+                let arrived_at = util::utc_now_timestamp_msec();
+                if self.handle_expire_timer(channel_idx, self.user_id, timer, arrived_at) {
+                    self.enqueue(OutboxItem::ExpireTimer { channel_id, timer });
+                }
+            }
+            Command::Group(GroupCommand::Create(name, member_names)) => {
+                self.create_group(name, &member_names)?;
+            }
+            Command::Group(command) => {
+                let group_data = channel
+                    .group_data
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("not a group"))?;
+                let change = match command {
+                    GroupCommand::Create(..) => unreachable!("groups are created above"),
+                    GroupCommand::Rename(title) => GroupChange::Rename(title),
+                    GroupCommand::Describe(description) => GroupChange::Describe(description),
+                    GroupCommand::Add(name) => {
+                        let uuid = self
+                            .find_contact(&name)
+                            .ok_or_else(|| anyhow!("unknown contact: {}", name))?;
+                        if group_data.members.contains(&uuid) {
+                            bail!("already a member: {}", name);
+                        }
+                        GroupChange::AddMember(uuid)
+                    }
+                    GroupCommand::Remove(name) => {
+                        let (uuid, _) = group_data
+                            .member_names(&self.data.names)
+                            .into_iter()
+                            .find(|(_, member_name)| *member_name == name)
+                            .ok_or_else(|| anyhow!("not a member: {}", name))?;
+                        GroupChange::RemoveMember(uuid)
+                    }
+                    GroupCommand::Leave => GroupChange::Leave,
+                };
+                // applied locally after the group server accepted it
+                self.enqueue(OutboxItem::GroupChange { channel_id, change });
+            }
+            Command::Accept => {
+                if !channel.is_message_request {
+                    bail!("not a message request");
+                }
+                self.data.channels.items[channel_idx].is_message_request = false;
+                self.mark_channel_dirty(channel_idx);
+                // the sender sees our name only with our profile key
+                self.enqueue(OutboxItem::ProfileKey { channel_id });
+            }
+            Command::DeleteChannel => self.delete_channel(channel_idx),
+            Command::Chat(contact) => {
+                let uuid = self
+                    .find_contact(&contact)
+                    .ok_or_else(|| anyhow!("unknown contact: {}", contact))?;
+                let name = self.name_by_id(uuid).to_string();
+                let channel_idx = self.ensure_contact_channel_exists(uuid, &name);
+                self.mark_channel_dirty(channel_idx);
+                self.data.search_box.take();
+                self.data.channels.filter_channels("", &self.data.names);
+                self.data.channels.state.select(Some(channel_idx));
+                self.reset_unread_messages();
+            }
+            Command::SyncContacts => self.enqueue(OutboxItem::ContactsSyncRequest),
+            Command::Verify => {
+                let uuid = channel
+                    .user_id()
+                    .filter(|&uuid| uuid != self.user_id)
+                    .ok_or_else(|| anyhow!("not a channel with a contact"))?;
+                let identity = self
+                    .data
+                    .identities
+                    .get_mut(&uuid)
+                    .ok_or_else(|| anyhow!("unknown safety number: show it with f2 first"))?;
+                if identity.is_verified {
+                    bail!("already verified");
+                }
+                identity.is_verified = true;
+                self.mark_dirty(PendingChange::Identities);
+            }
+            Command::Block => {
+                if !self.data.blocked.insert(channel_id) {
+                    bail!("already blocked");
+                }
+                self.mark_dirty(PendingChange::Blocked);
+            }
+            Command::Unblock => {
+                if !self.data.blocked.remove(&channel_id) {
+                    bail!("not blocked");
+                }
+                self.mark_dirty(PendingChange::Blocked);
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds and selects the channel of a new group with us and the contacts with the names as
+    /// members, and queues the creation of the group on the group server.
+    fn create_group(&mut self, name: String, member_names: &[String]) -> anyhow::Result<()> {
+        let mut members = vec![self.user_id];
+        for member_name in member_names {
+            let uuid = self
+                .find_contact(member_name)
+                .ok_or_else(|| anyhow!("unknown contact: {}", member_name))?;
+            if !members.contains(&uuid) {
+                members.push(uuid);
+            }
+        }
+        let master_key_bytes: GroupMasterKeyBytes = rand::random();
+        let channel_id = ChannelId::from_master_key_bytes(master_key_bytes)?;
+        self.data.channels.items.push(Channel {
+            id: channel_id,
+            name,
+            group_data: Some(GroupData {
+                master_key_bytes,
+                members,
+                revision: 0,
+            }),
+            messages: StatefulList::with_items(Vec::new()),
+            unread_messages: 0,
+            expire_timer: None,
+            is_message_request: false,
+            typing: Default::default(),
+        });
+        let channel_idx = self.data.channels.items.len() - 1;
+        self.mark_channel_dirty(channel_idx);
+        self.data.search_box.take();
+        self.data.channels.filter_channels("", &self.data.names);
+        self.data.channels.state.select(Some(channel_idx));
+        self.enqueue(OutboxItem::GroupChange {
+            channel_id,
+            change: GroupChange::Create,
+        });
+        Ok(())
+    }
+
+    fn add_sent_message(&mut self, channel_idx: usize, message: Message) {
+        let channel_id = self.data.channels.items[channel_idx].id;
+        let arrived_at = message.arrived_at;
+        self.index_message(channel_id, &message);
+        self.data.channels.items[channel_idx]
+            .messages
+            .items
+            .push(message);
+        self.mark_dirty(PendingChange::Message {
+            channel_id,
+            arrived_at,
+        });
+
+        self.reset_unread_messages();
         self.bubble_up_channel(channel_idx);
-        self.save()
+        self.mark_channel_dirty(0);
+    }
+
+    /// Queues the item in the outbox, and sends it right away if nothing else is queued.
+    fn enqueue(&mut self, item: OutboxItem) {
+        self.data.outbox.push(item);
+        self.mark_dirty(PendingChange::Outbox);
+        self.process_outbox();
+    }
+
+    /// Starts sending the next item of the outbox, if the app is online and no other item is
+    /// being sent.
+    ///
+    /// The sending future is taken with `take_outgoing` and is expected to be reported back with
+    /// `handle_send_result`.
+    pub fn process_outbox(&mut self) {
+        if !self.is_online {
+            return;
+        }
+        while let Some(queued) = self.data.outbox.start_next(Instant::now()) {
+            match self.send_item(&queued.item) {
+                Some(send) => {
+                    self.outgoing = Some((queued.id, send));
+                    return;
+                }
+                None => {
+                    // the target does not exist anymore, or the item can't be sent at all
+                    log::warn!(
+                        "dropping outbox item which can't be sent: {:?}",
+                        queued.item
+                    );
+                    self.data.outbox.cancel(queued.id);
+                    self.mark_dirty(PendingChange::Outbox);
+                }
+            }
+        }
+    }
+
+    fn send_item(&mut self, item: &OutboxItem) -> Option<SendFuture> {
+        match *item {
+            OutboxItem::Message {
+                channel_id,
+                arrived_at,
+            } => {
+                let channel = self.find_channel(channel_id)?;
+                let message = self.find_message(channel_id, arrived_at)?;
+                let send = self.signal_manager.send_text(channel, message);
+                self.set_send_receipt(channel_id, arrived_at, Receipt::Pending);
+                Some(send)
+            }
+            OutboxItem::Reaction {
+                channel_id,
+                target_author,
+                target_arrived_at,
+                ref emoji,
+                remove,
+            } => {
+                let channel = self.find_channel(channel_id)?;
+                Some(self.signal_manager.send_reaction(
+                    channel,
+                    target_author,
+                    target_arrived_at,
+                    emoji.clone(),
+                    remove,
+                ))
+            }
+            OutboxItem::Delete {
+                channel_id,
+                target_arrived_at,
+            } => {
+                let channel = self.find_channel(channel_id)?;
+                Some(self.signal_manager.send_delete(channel, target_arrived_at))
+            }
+            OutboxItem::ExpireTimer { channel_id, timer } => {
+                let channel = self.find_channel(channel_id)?;
+                Some(self.signal_manager.send_expire_timer(channel, timer))
+            }
+            OutboxItem::GroupChange {
+                channel_id,
+                ref change,
+            } => {
+                let channel = self.find_channel(channel_id)?;
+                Some(
+                    self.signal_manager
+                        .send_group_change(channel, change.clone()),
+                )
+            }
+            OutboxItem::ProfileKey { channel_id } => {
+                let channel = self.find_channel(channel_id)?;
+                Some(self.signal_manager.send_profile_key(channel))
+            }
+            OutboxItem::ContactsSyncRequest => Some(self.signal_manager.request_contacts_sync()),
+            OutboxItem::Receipt {
+                sender_id,
+                ref timestamps,
+                receipt,
+            } => Some(
+                self.signal_manager
+                    .send_receipt(sender_id, timestamps.clone(), receipt),
+            ),
+        }
+    }
+
+    /// Sends our typing started or stopped to the selected channel, depending on the changes of
+    /// the input box since the last call.
+    ///
+    /// Typing stops when the input is cleared (e.g. when it was sent), is idle for
+    /// `TYPING_IDLE_TIMEOUT`, or when another channel is selected. Commands, message requests
+    /// and blocked channels are not typing.
+    pub fn update_typing(&mut self, now: Instant) {
+        let input_changed = self.own_typing.input != self.data.input.data;
+        if input_changed {
+            self.own_typing.input.clone_from(&self.data.input.data);
+        }
+        let input = &self.own_typing.input;
+        let is_command = matches!(command::parse(input), Ok(Input::Command(_)) | Err(_));
+        let channel_id = self
+            .data
+            .channels
+            .state
+            .selected()
+            .and_then(|idx| self.data.channels.filtered_items.get(idx))
+            .and_then(|&idx| self.data.channels.items.get(idx))
+            .filter(|channel| {
+                !channel.is_message_request && !self.data.blocked.contains(&channel.id)
+            })
+            .map(|channel| channel.id)
+            .filter(|_| !input.is_empty() && !is_command && !self.is_searching);
+
+        if let Some((typing_channel_id, _, changed_at)) = self.own_typing.typing {
+            let is_idle = !input_changed && now.duration_since(changed_at) >= TYPING_IDLE_TIMEOUT;
+            if channel_id != Some(typing_channel_id) || is_idle {
+                self.own_typing.typing = None;
+                self.send_typing(typing_channel_id, TypingAction::Stopped);
+            }
+        }
+
+        let channel_id = match channel_id {
+            Some(channel_id) if input_changed => channel_id,
+            _ => return,
+        };
+        let is_sent = match &mut self.own_typing.typing {
+            Some((_, sent_at, changed_at)) => {
+                *changed_at = now;
+                let is_sent = now.duration_since(*sent_at) >= TYPING_REFRESH_INTERVAL;
+                if is_sent {
+                    *sent_at = now;
+                }
+                is_sent
+            }
+            None => {
+                self.own_typing.typing = Some((channel_id, now, now));
+                true
+            }
+        };
+        if is_sent {
+            self.send_typing(channel_id, TypingAction::Started);
+        }
+    }
+
+    fn send_typing(&mut self, channel_id: ChannelId, action: TypingAction) {
+        if !self.is_online {
+            return;
+        }
+        if let Some(channel) = self.find_channel(channel_id) {
+            let timestamp = util::utc_now_timestamp_msec();
+            let send = self.signal_manager.send_typing(channel, action, timestamp);
+            self.outgoing_typing.push(send);
+        }
+    }
+
+    /// Takes the sendings of our typing indicators, which must be sent in order; they are not
+    /// retried.
+    pub fn take_outgoing_typing(&mut self) -> Vec<SendFuture> {
+        std::mem::take(&mut self.outgoing_typing)
+    }
+
+    /// Takes the sending of the in-flight outbox item together with its id.
+    pub fn take_outgoing(&mut self) -> Option<(u64, SendFuture)> {
+        self.outgoing.take()
+    }
+
+    /// Handles the result of sending an outbox item, and continues with the next one.
+    ///
+    /// Failures while we are offline are not counted as attempts; the item is sent again after
+    /// reconnecting.
+    pub fn handle_send_result(&mut self, id: u64, result: anyhow::Result<()>) {
+        if let Err(e) = &result {
+            log::error!("failed to send: {:#}", e);
+        }
+        let outcome = if result.is_err() && !self.is_online {
+            self.data.outbox.interrupt(id).map(SendOutcome::Retry)
+        } else {
+            self.data.outbox.finish(id, result.is_ok(), Instant::now())
+        };
+        let (item, receipt) = match outcome {
+            Some(SendOutcome::Sent(item)) => (item, Receipt::Sent),
+            Some(SendOutcome::Retry(item)) => (item, Receipt::Queued),
+            Some(SendOutcome::Failed(item)) => (item, Receipt::Failed),
+            None => {
+                // removed from the outbox in the meantime
+                self.process_outbox();
+                return;
+            }
+        };
+        self.mark_dirty(PendingChange::Outbox);
+        match item {
+            OutboxItem::Message {
+                channel_id,
+                arrived_at,
+            } => self.set_send_receipt(channel_id, arrived_at, receipt),
+            OutboxItem::GroupChange { channel_id, change } if receipt == Receipt::Sent => {
+                let arrived_at = util::utc_now_timestamp_msec();
+                self.apply_group_change(channel_id, self.user_id, change, arrived_at);
+            }
+            _ => (),
+        }
+        self.process_outbox();
+    }
+
+    /// Sets whether the Signal servers are reachable.
+    ///
+    /// After reconnecting, the outbox is sent right away.
+    pub fn set_online(&mut self, is_online: bool) {
+        self.is_online = is_online;
+        if is_online {
+            self.data.outbox.resume();
+            self.process_outbox();
+        }
+    }
+
+    /// Updates the receipt of an own message, unless it was confirmed already.
+    fn set_send_receipt(&mut self, channel_id: ChannelId, arrived_at: u64, receipt: Receipt) {
+        let is_changed = match self.find_message_mut(channel_id, arrived_at) {
+            Some(message) if message.receipt < Receipt::Sent && message.receipt != receipt => {
+                message.receipt = receipt;
+                true
+            }
+            _ => false,
+        };
+        if is_changed {
+            self.mark_dirty(PendingChange::Receipt {
+                channel_id,
+                arrived_at,
+            });
+        }
+    }
+
+    /// Resets the state of own messages which were not sent on the last quit.
+    ///
+    /// Messages which are still in the outbox are queued again, the other ones are marked as
+    /// failed.
+    fn reset_unsent_messages(&mut self) {
+        let mut changes = Vec::new();
+        for channel in &mut self.data.channels.items {
+            for message in &mut channel.messages.items {
+                let receipt = match message.receipt {
+                    Receipt::Queued | Receipt::Pending => {
+                        if self
+                            .data
+                            .outbox
+                            .contains_message(channel.id, message.arrived_at)
+                        {
+                            Receipt::Queued
+                        } else {
+                            Receipt::Failed
+                        }
+                    }
+                    receipt => receipt,
+                };
+                if receipt != message.receipt {
+                    message.receipt = receipt;
+                    changes.push(PendingChange::Receipt {
+                        channel_id: channel.id,
+                        arrived_at: message.arrived_at,
+                    });
+                }
+            }
+        }
+        for change in changes {
+            self.mark_dirty(change);
+        }
+    }
+
+    /// Returns the channel and message index of the selected message.
+    fn selected_message_idx(&self) -> Option<(usize, usize)> {
+        let channel_idx = self.data.channels.state.selected()?;
+        let messages = &self.data.channels.items[channel_idx].messages;
+        // messages are shown in reversed order
+        let message_idx = messages
+            .items
+            .len()
+            .checked_sub(messages.state.selected()? + 1)?;
+        Some((channel_idx, message_idx))
+    }
+
+    /// Queues the selected message again, if it failed to send.
+    fn retry_selected_message(&mut self) -> Option<()> {
+        let (channel_idx, message_idx) = self.selected_message_idx()?;
+        let channel = &self.data.channels.items[channel_idx];
+        let message = &channel.messages.items[message_idx];
+        if message.receipt != Receipt::Failed {
+            return None;
+        }
+        let channel_id = channel.id;
+        let arrived_at = message.arrived_at;
+        self.set_send_receipt(channel_id, arrived_at, Receipt::Queued);
+        self.reset_message_selection();
+        self.enqueue(OutboxItem::Message {
+            channel_id,
+            arrived_at,
+        });
+        Some(())
+    }
+
+    /// Removes the selected message, if it failed to send or is still queued.
+    fn discard_selected_message(&mut self) -> Option<()> {
+        let (channel_idx, message_idx) = self.selected_message_idx()?;
+        let channel = &mut self.data.channels.items[channel_idx];
+        let receipt = channel.messages.items[message_idx].receipt;
+        if !matches!(receipt, Receipt::Failed | Receipt::Queued) {
+            return None;
+        }
+        let channel_id = channel.id;
+        let message = channel.messages.items.remove(message_idx);
+        self.mark_dirty(PendingChange::DeletedMessage {
+            channel_id,
+            arrived_at: message.arrived_at,
+        });
+        if self
+            .data
+            .outbox
+            .remove_message(channel_id, message.arrived_at)
+        {
+            self.mark_dirty(PendingChange::Outbox);
+        }
+        self.reset_message_selection();
+        Some(())
+    }
+
+    /// Deletes the selected own message for everyone, if it was sent.
+    ///
+    /// Messages which were not sent can only be discarded.
+    fn delete_selected_message(&mut self) -> Option<()> {
+        let (channel_idx, message_idx) = self.selected_message_idx()?;
+        let channel = &self.data.channels.items[channel_idx];
+        let message = &channel.messages.items[message_idx];
+        if message.from_id != self.user_id
+            || message.is_system
+            || message.is_deleted
+            || message.receipt < Receipt::Sent
+        {
+            return None;
+        }
+        let channel_id = channel.id;
+        let arrived_at = message.arrived_at;
+        self.reset_message_selection();
+        self.handle_delete(channel_id, self.user_id, arrived_at);
+        self.enqueue(OutboxItem::Delete {
+            channel_id,
+            target_arrived_at: arrived_at,
+        });
+        Some(())
     }
 
     pub fn select_previous_channel(&mut self) {
-        if self.reset_unread_messages() {
-            self.save().unwrap();
+        if self.is_message_search() {
+            self.search_results.previous();
+            return;
         }
+        self.reset_unread_messages();
         self.data.channels.previous();
     }
 
     pub fn select_next_channel(&mut self) {
-        if self.reset_unread_messages() {
-            self.save().unwrap();
+        if self.is_message_search() {
+            self.search_results.next();
+            return;
         }
+        self.reset_unread_messages();
         self.data.channels.next();
     }
 
     pub fn on_pgup(&mut self) {
         let select = self.data.channels.state.selected().unwrap_or_default();
+        let messages = &self.data.channels.items[select].messages;
+        // messages are shown in reversed order => the last index is the oldest loaded message
+        let is_oldest_selected = messages
+            .state
+            .selected()
+            .map_or(false, |idx| idx + 1 >= messages.items.len());
+        if is_oldest_selected {
+            if let Err(e) = self.load_older_messages(select) {
+                log::error!("failed to load older messages: {}", e);
+            }
+        }
         self.data.channels.items[select].messages.next();
     }
 
+    /// Loads the next page of messages older than the loaded ones from the storage.
+    ///
+    /// Returns the number of loaded messages.
+    fn load_older_messages(&mut self, channel_idx: usize) -> anyhow::Result<usize> {
+        let channel = &self.data.channels.items[channel_idx];
+        let channel_id = channel.id;
+        let before = match channel.messages.items.first() {
+            Some(message) => message.arrived_at,
+            None => return Ok(0),
+        };
+        let mut messages = self
+            .storage
+            .load_messages(channel_id, before, MESSAGES_PAGE_SIZE)?;
+        for message in &mut messages {
+            // changed messages which are not persisted yet are persisted as loaded messages
+            let arrived_at = message.arrived_at;
+            if let Some(changed) = self.unloaded_messages.remove(&(channel_id, arrived_at)) {
+                *message = changed;
+                self.mark_dirty(PendingChange::Message {
+                    channel_id,
+                    arrived_at,
+                });
+            }
+            self.index_message(channel_id, message);
+        }
+        let num_messages = messages.len();
+        self.data.channels.items[channel_idx]
+            .messages
+            .items
+            .splice(0..0, messages);
+        Ok(num_messages)
+    }
+
     pub fn on_pgdn(&mut self) {
         let select = self.data.channels.state.selected().unwrap_or_default();
         self.data.channels.items[select].messages.previous();
     }
 
+    /// Marks the messages of the selected channel as read, which starts the expiration timers of
+    /// its disappearing messages.
+    ///
+    /// Returns whether there were unread messages.
     pub fn reset_unread_messages(&mut self) -> bool {
         if let Some(selected_idx) = self.data.channels.state.selected() {
-            if self.data.channels.items[selected_idx].unread_messages > 0 {
-                self.data.channels.items[selected_idx].unread_messages = 0;
-                return true;
+            let channel = &mut self.data.channels.items[selected_idx];
+            let had_unread_messages = channel.unread_messages > 0;
+            if had_unread_messages {
+                channel.unread_messages = 0;
+                let channel_id = channel.id;
+                self.mark_dirty(PendingChange::Channel(channel_id));
             }
+            self.start_expire_timers(selected_idx, util::utc_now_timestamp_msec());
+            return had_unread_messages;
         }
         false
     }
@@ -829,33 +2071,53 @@ impl App {
     pub async fn on_message(&mut self, content: Content) -> anyhow::Result<()> {
         // log::debug!("incoming: {:#?}", content);
         let user_id = self.user_id;
+        if self.is_blocked(&content) {
+            return Ok(());
+        }
+        match content.metadata.sender.uuid {
+            Some(sender_uuid) if sender_uuid != user_id => self.check_identity(sender_uuid).await,
+            _ => (),
+        }
 
         let (channel_idx, message) = match (content.metadata, content.body) {
-            // Private note message
+            // Expiration timer changed by us from a different device
             (
                 _,
                 ContentBody::SynchronizeMessage(SyncMessage {
                     sent:
                         Some(Sent {
-                            destination_uuid: Some(destination_uuid),
+                            destination_uuid,
                             timestamp: Some(timestamp),
                             message:
                                 Some(DataMessage {
-                                    body,
-                                    attachments: attachment_pointers,
+                                    flags: Some(flags),
+                                    expire_timer,
+                                    group_v2,
                                     ..
                                 }),
                             ..
                         }),
                     ..
                 }),
-            ) if destination_uuid.parse() == Ok(user_id) => {
-                let channel_idx = self.ensure_own_channel_exists();
-                let attachments = self.save_attachments(attachment_pointers).await;
-                let message = Message::new(user_id, body, timestamp, attachments);
-                (channel_idx, message)
+            ) if is_expiration_timer_update(flags) => {
+                let channel_id = if let Some(GroupContextV2 {
+                    master_key: Some(master_key),
+                    ..
+                }) = group_v2
+                {
+                    ChannelId::from_master_key_bytes(master_key)?
+                } else if let Some(uuid) = destination_uuid {
+                    ChannelId::User(uuid.parse()?)
+                } else {
+                    return Ok(());
+                };
+                if let Some(channel_idx) = self.channel_idx(channel_id) {
+                    let timer = expire_timer.filter(|&timer| timer > 0);
+                    self.handle_expire_timer(channel_idx, user_id, timer, timestamp);
+                }
+                return Ok(());
             }
-            // Direct/group message by us from a different device
+            // Incoming expiration timer change
             (
                 Metadata {
                     sender:
@@ -865,56 +2127,206 @@ impl App {
                         },
                     ..
                 },
+                ContentBody::DataMessage(DataMessage {
+                    flags: Some(flags),
+                    expire_timer,
+                    group_v2,
+                    timestamp: Some(timestamp),
+                    ..
+                }),
+            ) if is_expiration_timer_update(flags) => {
+                let channel_id = if let Some(GroupContextV2 {
+                    master_key: Some(master_key),
+                    ..
+                }) = group_v2
+                {
+                    ChannelId::from_master_key_bytes(master_key)?
+                } else {
+                    ChannelId::User(sender_uuid)
+                };
+                if let Some(channel_idx) = self.channel_idx(channel_id) {
+                    let timer = expire_timer.filter(|&timer| timer > 0);
+                    self.handle_expire_timer(channel_idx, sender_uuid, timer, timestamp);
+                }
+                return Ok(());
+            }
+            // Message deleted for everyone by us from a different device
+            (
+                _,
                 ContentBody::SynchronizeMessage(SyncMessage {
                     sent:
                         Some(Sent {
-                            destination_e164,
                             destination_uuid,
-                            timestamp: Some(timestamp),
                             message:
                                 Some(DataMessage {
-                                    body,
+                                    delete:
+                                        Some(Delete {
+                                            target_sent_timestamp: Some(target_sent_timestamp),
+                                        }),
                                     group_v2,
-                                    quote,
-                                    attachments: attachment_pointers,
                                     ..
                                 }),
                             ..
                         }),
                     ..
                 }),
-            ) if sender_uuid == user_id => {
-                let channel_idx = if let Some(GroupContextV2 {
+            ) => {
+                let channel_id = if let Some(GroupContextV2 {
                     master_key: Some(master_key),
-                    revision: Some(revision),
                     ..
                 }) = group_v2
                 {
-                    // message to a group
-                    let master_key = master_key
-                        .try_into()
-                        .map_err(|_| anyhow!("invalid master key"))?;
-                    self.ensure_group_channel_exists(master_key, revision)
-                        .await
-                        .context("failed to create group channel")?
+                    ChannelId::from_master_key_bytes(master_key)?
+                } else if let Some(uuid) = destination_uuid {
+                    ChannelId::User(uuid.parse()?)
+                } else {
+                    return Ok(());
+                };
+                self.handle_delete(channel_id, user_id, target_sent_timestamp);
+                return Ok(());
+            }
+            // Incoming message deleted for everyone
+            (
+                Metadata {
+                    sender:
+                        ServiceAddress {
+                            uuid: Some(sender_uuid),
+                            ..
+                        },
+                    ..
+                },
+                ContentBody::DataMessage(DataMessage {
+                    delete:
+                        Some(Delete {
+                            target_sent_timestamp: Some(target_sent_timestamp),
+                        }),
+                    group_v2,
+                    ..
+                }),
+            ) => {
+                let channel_id = if let Some(GroupContextV2 {
+                    master_key: Some(master_key),
+                    ..
+                }) = group_v2
+                {
+                    ChannelId::from_master_key_bytes(master_key)?
+                } else {
+                    ChannelId::User(sender_uuid)
+                };
+                self.handle_delete(channel_id, sender_uuid, target_sent_timestamp);
+                return Ok(());
+            }
+            // Private note message
+            (
+                _,
+                ContentBody::SynchronizeMessage(SyncMessage {
+                    sent:
+                        Some(Sent {
+                            destination_uuid: Some(destination_uuid),
+                            timestamp: Some(timestamp),
+                            message:
+                                Some(DataMessage {
+                                    body,
+                                    body_ranges,
+                                    attachments: attachment_pointers,
+                                    expire_timer,
+                                    sticker,
+                                    ..
+                                }),
+                            ..
+                        }),
+                    ..
+                }),
+            ) if destination_uuid.parse() == Ok(user_id) => {
+                let channel_idx = self.ensure_own_channel_exists();
+                let mut attachments = self.save_attachments(attachment_pointers).await;
+                let sticker = self.save_sticker(sticker, &mut attachments).await;
+                let mut message = Message {
+                    expires_in: expire_timer.filter(|&timer| timer > 0),
+                    mentions: Mention::from_body_ranges(body_ranges),
+                    sticker,
+                    ..Message::new(user_id, body, timestamp, attachments)
+                };
+                // own messages are read when they are sent
+                message.start_expire_timer(timestamp);
+                self.sync_expire_timer(channel_idx, message.expires_in);
+                (channel_idx, message)
+            }
+            // Direct/group message by us from a different device
+            (
+                Metadata {
+                    sender:
+                        ServiceAddress {
+                            uuid: Some(sender_uuid),
+                            ..
+                        },
+                    ..
+                },
+                ContentBody::SynchronizeMessage(SyncMessage {
+                    sent:
+                        Some(Sent {
+                            destination_e164,
+                            destination_uuid,
+                            timestamp: Some(timestamp),
+                            message:
+                                Some(DataMessage {
+                                    body,
+                                    body_ranges,
+                                    group_v2,
+                                    quote,
+                                    attachments: attachment_pointers,
+                                    expire_timer,
+                                    sticker,
+                                    ..
+                                }),
+                            ..
+                        }),
+                    ..
+                }),
+            ) if sender_uuid == user_id => {
+                let channel_idx = if let Some(GroupContextV2 {
+                    master_key: Some(master_key),
+                    revision: Some(revision),
+                    group_change,
+                    ..
+                }) = group_v2
+                {
+                    // message to a group
+                    let master_key = master_key
+                        .try_into()
+                        .map_err(|_| anyhow!("invalid master key"))?;
+                    let changed_by = group_change.map(|_| user_id);
+                    self.ensure_group_channel_exists(
+                        master_key, revision, changed_by, None, timestamp,
+                    )
+                    .await
+                    .context("failed to create group channel")?
                 } else if let (Some(destination_uuid), Some(destination_e164)) = (
                     destination_uuid.and_then(|s| s.parse().ok()),
                     destination_e164,
                 ) {
-                    // message to a contact
-                    self.ensure_contact_channel_exists(destination_uuid, &destination_e164)
-                        .await
+                    // message to a contact; replying accepts a message request
+                    let channel_idx =
+                        self.ensure_contact_channel_exists(destination_uuid, &destination_e164);
+                    self.data.channels.items[channel_idx].is_message_request = false;
+                    channel_idx
                 } else {
                     log::warn!("unhandled message from us");
                     return Ok(());
                 };
 
                 let quote = quote.and_then(Message::from_quote).map(Box::new);
-                let attachments = self.save_attachments(attachment_pointers).await;
-                let message = Message {
+                let mut attachments = self.save_attachments(attachment_pointers).await;
+                let sticker = self.save_sticker(sticker, &mut attachments).await;
+                let mut message = Message {
                     quote,
+                    expires_in: expire_timer.filter(|&timer| timer > 0),
+                    mentions: Mention::from_body_ranges(body_ranges),
+                    sticker,
                     ..Message::new(user_id, body, timestamp, attachments)
                 };
+                message.start_expire_timer(timestamp);
+                self.sync_expire_timer(channel_idx, message.expires_in);
 
                 (channel_idx, message)
             }
@@ -931,17 +2343,21 @@ impl App {
                 },
                 ContentBody::DataMessage(DataMessage {
                     body,
+                    body_ranges,
                     group_v2,
                     timestamp: Some(timestamp),
                     profile_key: Some(profile_key),
                     quote,
                     attachments: attachment_pointers,
+                    expire_timer,
+                    sticker,
                     ..
                 }),
             ) => {
                 let (channel_idx, from) = if let Some(GroupContextV2 {
                     master_key: Some(master_key),
                     revision: Some(revision),
+                    group_change,
                     ..
                 }) = group_v2
                 {
@@ -949,8 +2365,16 @@ impl App {
                     let master_key = master_key
                         .try_into()
                         .map_err(|_| anyhow!("invalid group master key"))?;
+                    // the sender of a group update is the one who changed the group
+                    let changed_by = group_change.map(|_| uuid);
                     let channel_idx = self
-                        .ensure_group_channel_exists(master_key, revision)
+                        .ensure_group_channel_exists(
+                            master_key,
+                            revision,
+                            changed_by,
+                            Some(uuid),
+                            timestamp,
+                        )
                         .await
                         .context("failed to create group channel")?;
                     let from = self
@@ -961,11 +2385,15 @@ impl App {
                     (channel_idx, from)
                 } else {
                     // incoming direct message
+                    let is_message_request = !self.is_known_sender(uuid);
                     let name = self
                         .ensure_user_is_known(uuid, profile_key, phone_number)
                         .await
                         .to_string();
-                    let channel_idx = self.ensure_contact_channel_exists(uuid, &name).await;
+                    let channel_idx = self.ensure_contact_channel_exists(uuid, &name);
+                    if is_message_request {
+                        self.data.channels.items[channel_idx].is_message_request = true;
+                    }
                     let from = self.data.channels.items[channel_idx].name.clone();
                     // Reset typing notification as the Tipyng::Stop are not always sent by the server when a message is sent.
                     self.data.channels.items[channel_idx].reset_writing(uuid);
@@ -973,17 +2401,28 @@ impl App {
                     (channel_idx, from)
                 };
 
-                let attachments = self.save_attachments(attachment_pointers).await;
-                self.notify_about_message(&from, body.as_deref(), &attachments);
-
-                // Send "Delivered" receipt
-                self.add_receipt_event(ReceiptEvent::new(uuid, timestamp, Receipt::Received));
-
+                let mut attachments = self.save_attachments(attachment_pointers).await;
+                let sticker = self.save_sticker(sticker, &mut attachments).await;
                 let quote = quote.and_then(Message::from_quote).map(Box::new);
                 let message = Message {
                     quote,
+                    expires_in: expire_timer.filter(|&timer| timer > 0),
+                    mentions: Mention::from_body_ranges(body_ranges),
+                    sticker,
                     ..Message::new(uuid, body, timestamp, attachments)
                 };
+                self.sync_expire_timer(channel_idx, message.expires_in);
+
+                // the sender of a message request doesn't learn that we received it
+                if !self.data.channels.items[channel_idx].is_message_request {
+                    let body = message
+                        .body_with_mentions(|id| self.name_by_id(id))
+                        .or_else(|| message.sticker.as_ref().map(notification_text_for_sticker));
+                    self.notify_about_message(&from, body.as_deref(), &message.attachments);
+
+                    // Send "Delivered" receipt
+                    self.add_receipt_event(ReceiptEvent::new(uuid, timestamp, Receipt::Received));
+                }
 
                 if message.is_empty() {
                     return Ok(());
@@ -1021,7 +2460,6 @@ impl App {
                                 }),
                             ..
                         }),
-                    read,
                     ..
                 }),
             ) => {
@@ -1045,13 +2483,6 @@ impl App {
                     remove.unwrap_or(false),
                     true,
                 );
-                read.into_iter().for_each(|r| {
-                    self.handle_receipt(
-                        Uuid::from_str(r.sender_uuid.unwrap().as_str()).unwrap(),
-                        1,
-                        vec![r.timestamp.unwrap()],
-                    )
-                });
                 return Ok(());
             }
             (
@@ -1129,13 +2560,57 @@ impl App {
                     ..
                 },
                 ContentBody::TypingMessage(TypingMessage {
-                    timestamp: Some(timest),
                     group_id,
                     action: Some(act),
+                    ..
+                }),
+            ) => {
+                let action = TypingAction::from_i32(act);
+                let _ = self.handle_typing(sender_uuid, group_id, action, Instant::now());
+                return Ok(());
+            }
+
+            // Messages read on our other devices
+            (_, ContentBody::SynchronizeMessage(SyncMessage { read, .. })) if !read.is_empty() => {
+                self.handle_read_sync(read);
+                return Ok(());
+            }
+
+            // Contacts synced from our primary device, which are stored by the signal manager
+            (
+                _,
+                ContentBody::SynchronizeMessage(SyncMessage {
+                    contacts: Some(_), ..
+                }),
+            ) => {
+                self.import_contacts();
+                return Ok(());
+            }
+
+            // Groups synced from our primary device
+            (
+                _,
+                ContentBody::SynchronizeMessage(SyncMessage {
+                    groups: Some(Groups { blob: Some(blob) }),
+                    ..
+                }),
+            ) => {
+                self.import_groups(blob).await;
+                return Ok(());
+            }
+
+            // Blocklist synced from our primary device
+            (
+                _,
+                ContentBody::SynchronizeMessage(SyncMessage {
+                    blocked:
+                        Some(Blocked {
+                            uuids, group_ids, ..
+                        }),
+                    ..
                 }),
             ) => {
-                let _ =
-                    self.handle_typing(sender_uuid, group_id, TypingAction::from_i32(act), timest);
+                self.sync_blocked(uuids, group_ids);
                 return Ok(());
             }
 
@@ -1147,6 +2622,314 @@ impl App {
         Ok(())
     }
 
+    /// Marks the messages up to the read ones as read, s.t. the unread counter and the new
+    /// messages marker of their channels are cleared.
+    ///
+    /// Read messages which are not loaded are older than the unread ones, and are ignored.
+    fn handle_read_sync(&mut self, reads: Vec<Read>) {
+        let user_id = self.user_id;
+        let mut changes = Vec::new();
+        let mut read_channels = Vec::new();
+        for read in reads {
+            let (sender_uuid, timestamp) = match (read.sender_uuid, read.timestamp) {
+                (Some(sender_uuid), Some(timestamp)) => (sender_uuid, timestamp),
+                _ => continue,
+            };
+            let sender_uuid = match sender_uuid.parse::<Uuid>() {
+                Ok(uuid) => uuid,
+                Err(_) => continue,
+            };
+            for (channel_idx, channel) in self.data.channels.items.iter_mut().enumerate() {
+                let messages = &channel.messages.items;
+                let idx = messages
+                    .iter()
+                    .rposition(|m| m.arrived_at == timestamp && m.from_id == sender_uuid);
+                if let Some(idx) = idx {
+                    // unread messages are the latest incoming ones
+                    let unread_messages = messages[idx + 1..]
+                        .iter()
+                        .filter(|m| m.from_id != user_id && !m.is_system)
+                        .count();
+                    if unread_messages < channel.unread_messages {
+                        channel.unread_messages = unread_messages;
+                        changes.push(PendingChange::Channel(channel.id));
+                    }
+                    read_channels.push(channel_idx);
+                    break;
+                }
+            }
+        }
+        for change in changes {
+            self.mark_dirty(change);
+        }
+        let now = util::utc_now_timestamp_msec();
+        for channel_idx in read_channels {
+            self.start_expire_timers(channel_idx, now);
+        }
+    }
+
+    /// Remembers the identity key of the contact.
+    ///
+    /// If it changed, the safety number with the contact changed as well: a warning is added to
+    /// the channel with them, and they are not verified anymore.
+    async fn check_identity(&mut self, uuid: Uuid) {
+        let key = match self.signal_manager.identity_key(uuid).await {
+            Some(key) => key,
+            None => return,
+        };
+        let is_changed = match self.data.identities.get(&uuid) {
+            Some(identity) if identity.key == key => return,
+            Some(_) => true,
+            None => false,
+        };
+        self.data.identities.insert(
+            uuid,
+            Identity {
+                key,
+                is_verified: false,
+            },
+        );
+        self.mark_dirty(PendingChange::Identities);
+        if !is_changed {
+            return;
+        }
+
+        if let Some(channel_idx) = self
+            .data
+            .channels
+            .items
+            .iter()
+            .position(|channel| channel.user_id() == Some(uuid))
+        {
+            let text = format!(
+                "Your safety number with {} changed: verify it with f2",
+                self.name_by_id(uuid)
+            );
+            let arrived_at = util::utc_now_timestamp_msec();
+            self.add_message_to_channel(channel_idx, Message::warning(uuid, text, arrived_at));
+        }
+    }
+
+    /// Shows the safety number with the contact of the selected channel, or hides it.
+    ///
+    /// Errors are shown like the ones of commands.
+    pub async fn toggle_safety_number(&mut self) {
+        if self.safety_number.take().is_some() {
+            return;
+        }
+        self.command_error = None;
+        if let Err(e) = self.load_safety_number().await {
+            self.command_error = Some(e.to_string());
+        }
+    }
+
+    async fn load_safety_number(&mut self) -> anyhow::Result<()> {
+        let idx = self
+            .data
+            .channels
+            .state
+            .selected()
+            .ok_or_else(|| anyhow!("no channel selected"))?;
+        let channel = &self.data.channels.items[self.data.channels.filtered_items[idx]];
+        let uuid = channel
+            .user_id()
+            .filter(|&uuid| uuid != self.user_id)
+            .ok_or_else(|| anyhow!("safety numbers exist only with contacts"))?;
+        let phone_number = self
+            .phone_number(uuid)
+            .ok_or_else(|| anyhow!("unknown phone number of {}", channel.name))?;
+        self.check_identity(uuid).await;
+        let safety_number = self
+            .signal_manager
+            .safety_number(uuid, &phone_number)
+            .await?;
+        self.safety_number = Some((uuid, safety_number));
+        Ok(())
+    }
+
+    /// Safety number, which is shown, with the contact, and whether it is verified
+    pub fn safety_number(&self) -> Option<(Uuid, &SafetyNumber, bool)> {
+        let (uuid, safety_number) = self.safety_number.as_ref()?;
+        let is_verified = self
+            .data
+            .identities
+            .get(uuid)
+            .map_or(false, |identity| identity.is_verified);
+        Some((*uuid, safety_number, is_verified))
+    }
+
+    /// Phone number of the contact in E164 format, if it is synced or used as their name
+    fn phone_number(&self, uuid: Uuid) -> Option<String> {
+        self.contacts
+            .get(&uuid)
+            .and_then(|contact| contact.phone_number.clone())
+            .or_else(|| {
+                self.data
+                    .names
+                    .get(&uuid)
+                    .filter(|name| util::is_phone_number(name))
+                    .map(|name| name.replace(&[' ', '-'][..], ""))
+            })
+    }
+
+    /// Whether the user is a contact, we have a channel with them, or share a group with them
+    fn is_known_sender(&self, uuid: Uuid) -> bool {
+        self.data.channels.items.iter().any(|channel| {
+            channel.user_id() == Some(uuid)
+                || channel
+                    .group_data
+                    .as_ref()
+                    .map_or(false, |group_data| group_data.members.contains(&uuid))
+        }) || self.contacts.contains_key(&uuid)
+    }
+
+    /// Takes over the names of the synced contacts, also for their channels.
+    ///
+    /// The name in the address book takes precedence over the profile name; contacts without a
+    /// name are named by their phone number, unless their name is known already.
+    fn import_contacts(&mut self) {
+        let contacts = self.signal_manager.contacts();
+        self.contacts = contacts
+            .iter()
+            .map(|contact| (contact.uuid, contact.clone()))
+            .collect();
+        for contact in contacts {
+            let name = if !contact.name.is_empty() {
+                contact.name
+            } else if self.data.names.contains_key(&contact.uuid) {
+                continue;
+            } else if let Some(phone_number) = contact.phone_number {
+                phone_number
+            } else {
+                continue;
+            };
+            if self.data.names.get(&contact.uuid) == Some(&name) {
+                continue;
+            }
+            if let Some(channel_idx) = self
+                .data
+                .channels
+                .items
+                .iter()
+                .position(|channel| channel.user_id() == Some(contact.uuid))
+            {
+                self.data.channels.items[channel_idx].name = name.clone();
+                self.mark_channel_dirty(channel_idx);
+            }
+            self.data.names.insert(contact.uuid, name);
+            self.mark_dirty(PendingChange::Name(contact.uuid));
+        }
+    }
+
+    /// Finds the contact by its name or phone number.
+    fn find_contact(&self, name_or_phone_number: &str) -> Option<Uuid> {
+        self.data
+            .names
+            .iter()
+            .find(|(_, name)| *name == name_or_phone_number)
+            .map(|(&uuid, _)| uuid)
+            .or_else(|| {
+                self.contacts
+                    .values()
+                    .find(|contact| contact.phone_number.as_deref() == Some(name_or_phone_number))
+                    .map(|contact| contact.uuid)
+            })
+    }
+
+    /// Adds the channels of the groups synced from our primary device, which we don't have a
+    /// channel with yet.
+    ///
+    /// Groups which can't be resolved, e.g. groups v1 which were never migrated, are skipped.
+    async fn import_groups(&mut self, blob: AttachmentPointer) {
+        let master_keys = match self.signal_manager.synced_groups(blob).await {
+            Ok(master_keys) => master_keys,
+            Err(e) => {
+                log::error!("failed to read synced groups: {}", e);
+                return;
+            }
+        };
+        let now = util::utc_now_timestamp_msec();
+        for master_key in master_keys {
+            let is_known = ChannelId::from_master_key_bytes(master_key)
+                .map_or(true, |id| self.channel_idx(id).is_some());
+            if is_known {
+                continue;
+            }
+            if let Err(e) = self
+                .ensure_group_channel_exists(master_key, 0, None, None, now)
+                .await
+            {
+                log::warn!("failed to import synced group: {}", e);
+            }
+        }
+    }
+
+    /// Removes the channel with its whole history, also from the storage.
+    fn delete_channel(&mut self, channel_idx: usize) {
+        let channel = self.data.channels.items.remove(channel_idx);
+        self.data
+            .channels
+            .filter_channels(&self.data.search_box.data, &self.data.names);
+        self.invalidate_search_results();
+        self.mark_dirty(PendingChange::DeletedChannel(channel.id));
+        self.mark_dirty(PendingChange::ChannelOrder);
+    }
+
+    /// Whether the content was sent by a blocked contact or in a blocked group
+    fn is_blocked(&self, content: &Content) -> bool {
+        if self.data.blocked.is_empty() {
+            return false;
+        }
+        let group_id = match &content.body {
+            ContentBody::DataMessage(DataMessage {
+                group_v2:
+                    Some(GroupContextV2 {
+                        master_key: Some(master_key),
+                        ..
+                    }),
+                ..
+            }) => ChannelId::from_master_key_bytes(master_key).ok(),
+            ContentBody::TypingMessage(TypingMessage {
+                group_id: Some(group_id),
+                ..
+            }) => group_id[..].try_into().ok().map(ChannelId::Group),
+            _ => None,
+        };
+        let sender_id = content.metadata.sender.uuid.map(ChannelId::User);
+        [sender_id, group_id]
+            .into_iter()
+            .flatten()
+            .any(|id| self.data.blocked.contains(&id))
+    }
+
+    /// Merges the blocklist of our primary device into the blocked contacts and groups.
+    ///
+    /// Only the changes since the last sync are applied, s.t. our own `/block` and `/unblock`
+    /// are kept, unless they are changed on the primary device. Blocked phone numbers without uuid
+    /// are ignored.
+    fn sync_blocked(&mut self, uuids: Vec<String>, group_ids: Vec<Vec<u8>>) {
+        let synced_blocked: BTreeSet<ChannelId> = uuids
+            .iter()
+            .filter_map(|uuid| uuid.parse().ok().map(ChannelId::User))
+            .chain(
+                group_ids
+                    .iter()
+                    .filter_map(|group_id| group_id[..].try_into().ok().map(ChannelId::Group)),
+            )
+            .collect();
+        if synced_blocked == self.data.synced_blocked {
+            return;
+        }
+        for channel_id in self.data.synced_blocked.difference(&synced_blocked) {
+            self.data.blocked.remove(channel_id);
+        }
+        for channel_id in synced_blocked.difference(&self.data.synced_blocked) {
+            self.data.blocked.insert(*channel_id);
+        }
+        self.data.synced_blocked = synced_blocked;
+        self.mark_dirty(PendingChange::Blocked);
+    }
+
     fn notify_about_message(&mut self, from: &str, body: Option<&str>, attachments: &[Attachment]) {
         let attachments_text = notification_text_for_attachments(attachments);
         let notification = [body, attachments_text.as_deref()]
@@ -1158,12 +2941,14 @@ impl App {
         }
     }
 
-    pub fn step_receipts(&mut self) -> anyhow::Result<()> {
-        if self.receipt_handler.step(self.signal_manager.as_ref()) {
-            // No need to save if no receipt was sent
-            self.save()
-        } else {
-            Ok(())
+    pub fn step_receipts(&mut self) {
+        // Note: the receipts of the messages are marked as changed when they are queued.
+        if let Some((sender_id, timestamps, receipt)) = self.receipt_handler.step() {
+            self.enqueue(OutboxItem::Receipt {
+                sender_id,
+                timestamps,
+                receipt,
+            });
         }
     }
 
@@ -1172,107 +2957,92 @@ impl App {
         sender_uuid: Uuid,
         group_id: Option<Vec<u8>>,
         action: TypingAction,
-        _timestamp: u64,
+        received_at: Instant,
     ) -> Result<(), ()> {
-        if let Some(gid) = group_id {
-            // It's in a group
-            let group = self
-                .data
-                .channels
-                .items
-                .iter_mut()
-                .find(|c| {
-                    if let ChannelId::Group(gid_other) = c.id {
-                        gid_other[..] == gid[..]
-                    } else {
-                        false
-                    }
-                })
-                .ok_or(())?;
-            if let TypingSet::GroupTyping(ref mut hash_set) = group.typing {
-                match action {
-                    TypingAction::Started => {
-                        hash_set.insert(sender_uuid);
-                    }
-                    TypingAction::Stopped => {
-                        hash_set.remove(&sender_uuid);
-                    }
-                }
-            } else {
-                log::error!("Got a single typing hash set on a group.");
-            }
-        } else {
-            let chan = self
-                .data
-                .channels
-                .items
-                .iter_mut()
-                .find(|c| {
-                    if let ChannelId::User(other_uuid) = c.id {
-                        if other_uuid == sender_uuid {
-                            return true;
-                        }
-                    }
-                    false
-                })
-                .unwrap();
-
-            if let TypingSet::SingleTyping(_) = chan.typing {
-                match action {
-                    TypingAction::Started => {
-                        chan.typing = TypingSet::SingleTyping(true);
-                    }
-                    TypingAction::Stopped => {
-                        chan.typing = TypingSet::SingleTyping(false);
-                    }
-                }
-            } else {
-                log::error!("Got a single typing hash set on a group.");
-            }
+        let channel = self
+            .data
+            .channels
+            .items
+            .iter_mut()
+            .find(|c| match (&c.id, &group_id) {
+                (ChannelId::Group(gid_other), Some(gid)) => gid_other[..] == gid[..],
+                (ChannelId::User(other_uuid), None) => *other_uuid == sender_uuid,
+                _ => false,
+            })
+            .ok_or(())?;
+        match action {
+            // the timestamp of the sender is not compared, since its clock may be off
+            TypingAction::Started => channel.typing.start(sender_uuid, received_at),
+            TypingAction::Stopped => channel.typing.stop(sender_uuid),
         }
         Ok(())
     }
 
+    /// Removes the typing of others which was received `TYPING_TIMEOUT` or longer ago
+    pub fn expire_typing(&mut self, now: Instant) {
+        for channel in self.data.channels.items.iter_mut() {
+            channel.typing.expire(now);
+        }
+    }
+
     pub fn add_receipt_event(&mut self, event: ReceiptEvent) {
         self.receipt_handler.add_receipt_event(event);
     }
 
     fn handle_receipt(&mut self, sender_uuid: Uuid, typ: i32, timestamps: Vec<u64>) {
         let earliest = timestamps.iter().min().unwrap();
-        for c in self.data.channels.items.iter_mut() {
-            match c.id {
-                ChannelId::User(other_uuid) if other_uuid == sender_uuid => {
-                    c.messages.items.iter_mut().rev().fold_while(0, |_, b| {
-                        match b.arrived_at.cmp(earliest) {
-                            std::cmp::Ordering::Less => Done(0),
-                            _ => {
-                                if timestamps.contains(&b.arrived_at) {
-                                    b.receipt = b.receipt.update(Receipt::from_i32(typ));
-                                }
-                                Continue(0)
+        let mut changes = Vec::new();
+        let mut unloaded = Vec::new();
+        for (channel_idx, c) in self.data.channels.items.iter_mut().enumerate() {
+            let is_affected = match c.id {
+                ChannelId::User(other_uuid) => other_uuid == sender_uuid,
+                ChannelId::Group(_) => c
+                    .group_data
+                    .as_ref()
+                    .map(|g_data| g_data.members.contains(&sender_uuid))
+                    .unwrap_or(false),
+            };
+            if !is_affected {
+                continue;
+            }
+            let channel_id = c.id;
+            c.messages.items.iter_mut().rev().fold_while(0, |_, b| {
+                match b.arrived_at.cmp(earliest) {
+                    std::cmp::Ordering::Less => Done(0),
+                    _ => {
+                        if timestamps.contains(&b.arrived_at) {
+                            let receipt = b.receipt.update(Receipt::from_i32(typ));
+                            if receipt != b.receipt {
+                                b.receipt = receipt;
+                                changes.push(PendingChange::Receipt {
+                                    channel_id,
+                                    arrived_at: b.arrived_at,
+                                });
                             }
                         }
-                    });
-                }
-                ChannelId::Group(_) => {
-                    if let Some(ref g_data) = c.group_data {
-                        if g_data.members.contains(&sender_uuid) {
-                            c.messages.items.iter_mut().rev().fold_while(0, |_, b| {
-                                match b.arrived_at.cmp(earliest) {
-                                    std::cmp::Ordering::Less => Done(0),
-                                    _ => {
-                                        if timestamps.contains(&b.arrived_at) {
-                                            b.receipt = b.receipt.update(Receipt::from_i32(typ));
-                                        }
-                                        Continue(0)
-                                    }
-                                }
-                            });
-                        }
+                        Continue(0)
                     }
                 }
-                _ => (),
-            }
+            });
+            // messages older than the loaded ones are updated in the storage
+            let oldest_loaded = c.messages.items.first().map(|m| m.arrived_at);
+            unloaded.extend(
+                timestamps
+                    .iter()
+                    .filter(|&&arrived_at| oldest_loaded.map_or(true, |oldest| arrived_at < oldest))
+                    .map(|&arrived_at| (channel_idx, arrived_at)),
+            );
+        }
+        for change in changes {
+            self.mark_dirty(change);
+        }
+        for (channel_idx, arrived_at) in unloaded {
+            self.update_stored_message(channel_idx, arrived_at, |message| {
+                let receipt = message.receipt.update(Receipt::from_i32(typ));
+                let is_changed = receipt != message.receipt;
+                message.receipt = receipt;
+                is_changed
+            });
         }
     }
 
@@ -1291,58 +3061,398 @@ impl App {
             .items
             .iter()
             .position(|channel| channel.id == channel_id)?;
+        let loaded_message = self.data.channels.items[channel_idx]
+            .messages
+            .items
+            .iter_mut()
+            .find(|m| m.arrived_at == target_sent_timestamp);
+        let (is_added, text) = match loaded_message {
+            Some(message) => {
+                let is_added = apply_reaction(message, sender_uuid, &emoji, remove);
+                let text = message.message.clone();
+                self.mark_dirty(PendingChange::Reaction {
+                    channel_id,
+                    arrived_at: target_sent_timestamp,
+                    from_id: sender_uuid,
+                });
+                (is_added, text)
+            }
+            None => {
+                let mut is_added = false;
+                let message =
+                    self.update_stored_message(channel_idx, target_sent_timestamp, |message| {
+                        is_added = apply_reaction(message, sender_uuid, &emoji, remove);
+                        true
+                    })?;
+                (is_added, message.message)
+            }
+        };
+
+        let is_notified = is_added && channel_id != ChannelId::User(self.user_id);
+        if is_notified {
+            // Notification
+            let channel = &self.data.channels.items[channel_idx];
+            let sender_name = name_by_id(&self.data.names, sender_uuid);
+            let summary = if let ChannelId::Group(_) = channel.id {
+                Cow::from(format!("{} in {}", sender_name, channel.name))
+            } else {
+                Cow::from(sender_name)
+            };
+            let mut notification = format!("{} reacted {}", summary, emoji);
+            if let Some(text) = text.as_ref() {
+                notification.push_str(" to: ");
+                notification.push_str(text);
+            }
+            if notify {
+                self.notify(&summary, &notification);
+            }
+            self.touch_channel(channel_idx);
+        }
+
+        Some(())
+    }
+
+    /// Updates a message of the channel which is not loaded, but stored.
+    ///
+    /// The message is persisted with the next flush if `f` returns `true`. Returns the updated
+    /// message, or `None` if the message is not stored or can't be loaded.
+    fn update_stored_message(
+        &mut self,
+        channel_idx: usize,
+        arrived_at: u64,
+        f: impl FnOnce(&mut Message) -> bool,
+    ) -> Option<Message> {
+        let channel_id = self.data.channels.items[channel_idx].id;
+        let key = (channel_id, arrived_at);
+        let mut message = match self.unloaded_messages.get(&key) {
+            Some(message) => message.clone(),
+            None => match self.storage.load_message(channel_id, arrived_at) {
+                Ok(message) => message?,
+                Err(e) => {
+                    log::error!("failed to load message: {}", e);
+                    return None;
+                }
+            },
+        };
+        if f(&mut message) {
+            self.unloaded_messages.insert(key, message.clone());
+            self.mark_dirty(PendingChange::UnloadedMessage {
+                channel_id,
+                arrived_at,
+            });
+        }
+        Some(message)
+    }
+
+    /// Replaces the message with a tombstone, if it was deleted for everyone by its author.
+    fn handle_delete(
+        &mut self,
+        channel_id: ChannelId,
+        from_id: Uuid,
+        target_arrived_at: u64,
+    ) -> Option<()> {
+        match self.find_message_mut(channel_id, target_arrived_at) {
+            Some(message) => {
+                if message.from_id != from_id {
+                    return None;
+                }
+                message.delete();
+                self.mark_dirty(PendingChange::Message {
+                    channel_id,
+                    arrived_at: target_arrived_at,
+                });
+            }
+            None => {
+                let channel_idx = self.channel_idx(channel_id)?;
+                let mut is_deleted = false;
+                self.update_stored_message(channel_idx, target_arrived_at, |message| {
+                    is_deleted = message.from_id == from_id;
+                    if is_deleted {
+                        message.delete();
+                    }
+                    is_deleted
+                })?;
+                if !is_deleted {
+                    return None;
+                }
+            }
+        }
+        // the search results might contain the deleted message
+        self.invalidate_search_results();
+        Some(())
+    }
+
+    /// Sets the expiration timer of the channel, and adds a system line about the change.
+    ///
+    /// Returns whether the timer changed.
+    fn handle_expire_timer(
+        &mut self,
+        channel_idx: usize,
+        from_id: Uuid,
+        timer: Option<u32>,
+        arrived_at: u64,
+    ) -> bool {
         let channel = &mut self.data.channels.items[channel_idx];
-        let message = channel
+        if channel.expire_timer == timer {
+            return false;
+        }
+        channel.expire_timer = timer;
+
+        let name = self.subject_name(from_id);
+        let text = match timer {
+            Some(timer) => format!(
+                "{} set the disappearing message time to {}",
+                name,
+                command::format_timer(timer)
+            ),
+            None => format!("{} turned off disappearing messages", name),
+        };
+        let message = Message::system(from_id, text, arrived_at);
+        // also marks the channel with the new timer as changed
+        self.add_message_to_channel(channel_idx, message);
+        true
+    }
+
+    /// Applies the change to the group of the channel, and adds a system line about it.
+    ///
+    /// The revision of the group is increased by one, unless the group was created.
+    fn apply_group_change(
+        &mut self,
+        channel_id: ChannelId,
+        from_id: Uuid,
+        change: GroupChange,
+        arrived_at: u64,
+    ) -> Option<()> {
+        let channel_idx = self.channel_idx(channel_id)?;
+        let text = self.group_change_text(Some(from_id), &change);
+
+        let channel = &mut self.data.channels.items[channel_idx];
+        let group_data = channel.group_data.as_mut()?;
+        if change != GroupChange::Create {
+            group_data.revision += 1;
+        }
+        match change {
+            GroupChange::Create | GroupChange::Describe(_) => (),
+            GroupChange::Rename(title) => channel.name = title,
+            GroupChange::AddMember(uuid) => {
+                if !group_data.members.contains(&uuid) {
+                    group_data.members.push(uuid);
+                }
+            }
+            GroupChange::RemoveMember(uuid) => group_data.members.retain(|&member| member != uuid),
+            GroupChange::Leave => group_data.members.retain(|&member| member != from_id),
+        }
+        // also marks the changed channel
+        self.add_message_to_channel(channel_idx, Message::system(from_id, text, arrived_at));
+        Some(())
+    }
+
+    /// Adds system lines about the changes between the old and the re-resolved group of the
+    /// channel.
+    ///
+    /// The changes are attributed to `changed_by`, if known. Returns the new index of the channel.
+    fn add_group_change_messages(
+        &mut self,
+        channel_idx: usize,
+        old_name: &str,
+        old_members: &[Uuid],
+        changed_by: Option<Uuid>,
+        arrived_at: u64,
+    ) -> usize {
+        let channel = &self.data.channels.items[channel_idx];
+        let channel_id = channel.id;
+        let members = match channel.group_data.as_ref() {
+            Some(group_data) => &group_data.members,
+            None => return channel_idx,
+        };
+
+        let mut changes = Vec::new();
+        if channel.name != old_name {
+            changes.push(GroupChange::Rename(channel.name.clone()));
+        }
+        changes.extend(
+            members
+                .iter()
+                .filter(|member| !old_members.contains(member))
+                .map(|&member| GroupChange::AddMember(member)),
+        );
+        changes.extend(
+            old_members
+                .iter()
+                .filter(|member| !members.contains(member))
+                .map(|&member| GroupChange::RemoveMember(member)),
+        );
+
+        // Messages are identified by their arrival time, so the lines are placed right before the
+        // message which revealed the changes, at arrival times which no other message of the
+        // channel has.
+        let taken: HashSet<u64> = channel
             .messages
             .items
-            .iter_mut()
-            .find(|m| m.arrived_at == target_sent_timestamp)?;
-        let reaction_idx = message
-            .reactions
             .iter()
-            .position(|(from_id, _)| from_id == &sender_uuid);
-        let is_added = if let Some(idx) = reaction_idx {
-            if remove {
-                message.reactions.swap_remove(idx);
-                false
+            .map(|message| message.arrived_at)
+            .collect();
+        let mut arrived_ats: Vec<u64> = (0..arrived_at)
+            .rev()
+            .filter(|at| !taken.contains(at))
+            .take(changes.len())
+            .collect();
+        arrived_ats.reverse();
+
+        for (change, arrived_at) in changes.into_iter().zip(arrived_ats) {
+            let text = self.group_change_text(changed_by, &change);
+            let from_id = match change {
+                GroupChange::AddMember(uuid) | GroupChange::RemoveMember(uuid)
+                    if changed_by.is_none() =>
+                {
+                    uuid
+                }
+                _ => changed_by.unwrap_or_else(Uuid::nil),
+            };
+            let message = Message::system(from_id, text, arrived_at);
+            // the channel is bubbled up after adding a message
+            let channel_idx = self.channel_idx(channel_id).unwrap_or(channel_idx);
+            self.add_message_to_channel(channel_idx, message);
+        }
+        self.channel_idx(channel_id).unwrap_or(channel_idx)
+    }
+
+    /// Text of the system line about the change of a group by `from_id`, if known
+    fn group_change_text(&self, from_id: Option<Uuid>, change: &GroupChange) -> String {
+        let subject = from_id.map(|id| self.subject_name(id));
+        let object_name = |uuid| {
+            if uuid == self.user_id {
+                "you"
             } else {
-                message.reactions[idx].1 = emoji.clone();
-                true
+                self.name_by_id(uuid)
             }
-        } else {
-            message.reactions.push((sender_uuid, emoji.clone()));
-            true
         };
-
-        if is_added && channel_id != ChannelId::User(self.user_id) {
-            // Notification
-            let sender_name = name_by_id(&self.data.names, sender_uuid);
-            let summary = if let ChannelId::Group(_) = channel.id {
-                Cow::from(format!("{} in {}", sender_name, channel.name))
-            } else {
-                Cow::from(sender_name)
-            };
-            let mut notification = format!("{} reacted {}", summary, emoji);
-            if let Some(text) = message.message.as_ref() {
-                notification.push_str(" to: ");
-                notification.push_str(text);
+        match (subject, change) {
+            (Some(name), GroupChange::Create) => format!("{} created the group", name),
+            (None, GroupChange::Create) => "The group was created".to_string(),
+            (Some(name), GroupChange::Rename(title)) => {
+                format!("{} changed the group name to \"{}\"", name, title)
             }
-            if notify {
-                self.notify(&summary, &notification);
+            (None, GroupChange::Rename(title)) => {
+                format!("The group name changed to \"{}\"", title)
+            }
+            (Some(name), GroupChange::Describe(_)) => {
+                format!("{} changed the group description", name)
+            }
+            (None, GroupChange::Describe(_)) => "The group description changed".to_string(),
+            (Some(name), GroupChange::AddMember(uuid)) if from_id != Some(*uuid) => {
+                format!("{} added {}", name, object_name(*uuid))
+            }
+            (_, GroupChange::AddMember(uuid)) => {
+                format!("{} joined the group", self.subject_name(*uuid))
+            }
+            (Some(name), GroupChange::RemoveMember(uuid)) if from_id != Some(*uuid) => {
+                format!("{} removed {}", name, object_name(*uuid))
+            }
+            (_, GroupChange::RemoveMember(uuid)) => {
+                format!("{} left the group", self.subject_name(*uuid))
             }
+            (name, GroupChange::Leave) => {
+                format!("{} left the group", name.unwrap_or("A member"))
+            }
+        }
+    }
 
-            self.touch_channel(channel_idx);
+    /// Name of the user as subject of a system line
+    fn subject_name(&self, id: Uuid) -> &str {
+        if id == self.user_id {
+            "You"
         } else {
-            self.save().unwrap();
+            self.name_by_id(id)
         }
+    }
 
-        Some(())
+    /// Removes the disappearing messages which expired at `now` (in milliseconds), and the saved
+    /// files of their attachments.
+    ///
+    /// The expired messages are deleted from the storage with the next flush, also the ones
+    /// which are not loaded.
+    pub fn expire_messages(&mut self, now: u64) {
+        let mut expired = Vec::new();
+        for channel in &mut self.data.channels.items {
+            let messages = std::mem::take(&mut channel.messages.items);
+            let (expired_messages, messages): (Vec<_>, Vec<_>) = messages
+                .into_iter()
+                .partition(|message| message.expires_at().map_or(false, |at| at <= now));
+            channel.messages.items = messages;
+            if !expired_messages.is_empty() {
+                channel.messages.state.select(None);
+                channel.messages.rendered = Default::default();
+                expired.extend(expired_messages);
+            }
+        }
+        if !expired.is_empty() {
+            remove_attachment_files(self.attachments_dir.as_deref(), &expired);
+            self.invalidate_search_results();
+            self.mark_dirty(PendingChange::ExpiredMessages);
+        }
+    }
+
+    /// Deletes the expired disappearing messages from the storage, and the saved files of their
+    /// attachments.
+    fn delete_expired_messages(&self) -> anyhow::Result<()> {
+        let expired = self
+            .storage
+            .delete_expired_messages(util::utc_now_timestamp_msec())?;
+        remove_attachment_files(
+            self.attachments_dir.as_deref(),
+            expired.iter().map(|(_, message)| message),
+        );
+        Ok(())
+    }
+
+    /// Starts the expiration timers of the read disappearing messages of the channel at `now`.
+    ///
+    /// The latest `unread_messages` incoming messages are unread; own messages are read.
+    fn start_expire_timers(&mut self, channel_idx: usize, now: u64) {
+        let user_id = self.user_id;
+        let channel = &mut self.data.channels.items[channel_idx];
+        let channel_id = channel.id;
+        let mut unread_messages = channel.unread_messages;
+        let mut changes = Vec::new();
+        for message in channel.messages.items.iter_mut().rev() {
+            if unread_messages > 0 && message.from_id != user_id && !message.is_system {
+                unread_messages -= 1;
+            } else if message.start_expire_timer(now) {
+                changes.push(PendingChange::Message {
+                    channel_id,
+                    arrived_at: message.arrived_at,
+                });
+            }
+        }
+        for change in changes {
+            self.mark_dirty(change);
+        }
+    }
+
+    /// Adopts the expiration timer of a message in the channel, which is the current timer of
+    /// its sender.
+    fn sync_expire_timer(&mut self, channel_idx: usize, timer: Option<u32>) {
+        let channel = &mut self.data.channels.items[channel_idx];
+        if channel.expire_timer != timer {
+            channel.expire_timer = timer;
+            self.mark_channel_dirty(channel_idx);
+        }
     }
 
+    /// Returns the index of the channel of the group, which is (re-)resolved if it is unknown or
+    /// its revision changed.
+    ///
+    /// Changes of a known group are added as system lines to the channel, attributed to
+    /// `changed_by` if known, right before `arrived_at`. The channel of an unknown group is a
+    /// message request, if it was revealed by a message from the unknown user `sender`.
     async fn ensure_group_channel_exists(
         &mut self,
         master_key: GroupMasterKeyBytes,
         revision: u32,
+        changed_by: Option<Uuid>,
+        sender: Option<Uuid>,
+        arrived_at: u64,
     ) -> anyhow::Result<usize> {
         let id = ChannelId::from_master_key_bytes(master_key)?;
         if let Some(channel_idx) = self
@@ -1373,11 +3483,24 @@ impl App {
                 .await;
 
                 let channel = &mut self.data.channels.items[channel_idx];
-                channel.name = name;
-                channel.group_data = Some(group_data);
+                let old_name = std::mem::replace(&mut channel.name, name);
+                let old_group_data = channel.group_data.replace(group_data);
+                // the revision changed, even if no change is visible
+                self.mark_channel_dirty(channel_idx);
+                if let Some(old_group_data) = old_group_data {
+                    return Ok(self.add_group_change_messages(
+                        channel_idx,
+                        &old_name,
+                        &old_group_data.members,
+                        changed_by,
+                        arrived_at,
+                    ));
+                }
             }
             Ok(channel_idx)
         } else {
+            // before the group is added, its members are known senders
+            let is_message_request = sender.map_or(false, |uuid| !self.is_known_sender(uuid));
             let ResolvedGroup {
                 name,
                 group_data,
@@ -1399,9 +3522,13 @@ impl App {
                 group_data: Some(group_data),
                 messages: StatefulList::with_items(Vec::new()),
                 unread_messages: 0,
-                typing: TypingSet::GroupTyping(HashSet::new()),
+                expire_timer: None,
+                is_message_request,
+                typing: Default::default(),
             });
-            Ok(self.data.channels.items.len() - 1)
+            let channel_idx = self.data.channels.items.len() - 1;
+            self.mark_channel_dirty(channel_idx);
+            Ok(channel_idx)
         }
     }
 
@@ -1418,6 +3545,7 @@ impl App {
         {
             let phone_number_name = phone_number.format().mode(Mode::E164).to_string();
             self.data.names.insert(uuid, phone_number_name);
+            self.mark_dirty(PendingChange::Name(uuid));
         }
         self.data.names.get(&uuid).unwrap()
     }
@@ -1435,6 +3563,7 @@ impl App {
                 Err(_) => None,
             };
             self.data.names.insert(uuid, name?);
+            self.mark_dirty(PendingChange::Name(uuid));
         }
         self.data.names.get(&uuid).map(|s| s.as_str())
     }
@@ -1466,13 +3595,17 @@ impl App {
                 group_data: None,
                 messages: StatefulList::with_items(Vec::new()),
                 unread_messages: 0,
-                typing: TypingSet::SingleTyping(false),
+                expire_timer: None,
+                is_message_request: false,
+                typing: Default::default(),
             });
-            self.data.channels.items.len() - 1
+            let channel_idx = self.data.channels.items.len() - 1;
+            self.mark_channel_dirty(channel_idx);
+            channel_idx
         }
     }
 
-    async fn ensure_contact_channel_exists(&mut self, uuid: Uuid, name: &str) -> usize {
+    fn ensure_contact_channel_exists(&mut self, uuid: Uuid, name: &str) -> usize {
         if let Some(channel_idx) = self
             .data
             .channels
@@ -1484,6 +3617,7 @@ impl App {
                 let channel = &mut self.data.channels.items[channel_idx];
                 if &channel.name != name {
                     channel.name = name.clone();
+                    self.mark_channel_dirty(channel_idx);
                 }
             }
             channel_idx
@@ -1494,20 +3628,30 @@ impl App {
                 group_data: None,
                 messages: StatefulList::with_items(Vec::new()),
                 unread_messages: 0,
-                typing: TypingSet::SingleTyping(false),
+                expire_timer: None,
+                is_message_request: false,
+                typing: Default::default(),
             });
-            self.data.channels.items.len() - 1
+            let channel_idx = self.data.channels.items.len() - 1;
+            self.mark_channel_dirty(channel_idx);
+            channel_idx
         }
     }
 
     fn add_message_to_channel(&mut self, channel_idx: usize, message: Message) {
+        let channel_id = self.data.channels.items[channel_idx].id;
+        let arrived_at = message.arrived_at;
+        self.index_message(channel_id, &message);
         let channel = &mut self.data.channels.items[channel_idx];
-
         channel.messages.items.push(message);
         if let Some(idx) = channel.messages.state.selected() {
             // keep selection on the old message
             channel.messages.state.select(Some(idx + 1));
         }
+        self.mark_dirty(PendingChange::Message {
+            channel_id,
+            arrived_at,
+        });
 
         self.touch_channel(channel_idx);
     }
@@ -1520,7 +3664,8 @@ impl App {
         }
 
         self.bubble_up_channel(channel_idx);
-        self.save().unwrap();
+        // after bubbling up, the touched channel is the first one
+        self.mark_channel_dirty(0);
     }
 
     fn bubble_up_channel(&mut self, channel_idx: usize) {
@@ -1544,7 +3689,10 @@ impl App {
         }
     }
 
-    fn extract_attachments(&mut self, input: &str) -> (String, Vec<(AttachmentSpec, Vec<u8>)>) {
+    /// Extracts the `file://` paths of existing files from the input as attachments.
+    ///
+    /// The files are read when the message is sent.
+    fn extract_attachments(&mut self, input: &str) -> (String, Vec<Attachment>) {
         let mut offset = 0;
         let mut clean_input = String::new();
 
@@ -1553,7 +3701,8 @@ impl App {
             let path_str = &input[start..end].strip_prefix("file://")?;
 
             let path = Path::new(path_str);
-            let contents = std::fs::read(path).ok()?;
+            let metadata = std::fs::metadata(path).ok().filter(|m| m.is_file())?;
+            let size = metadata.len().try_into().ok()?;
 
             clean_input.push_str(input[offset..start].trim_end_matches(""));
             offset = end;
@@ -1562,21 +3711,12 @@ impl App {
                 .first()
                 .map(|mime| mime.essence_str().to_string())
                 .unwrap_or_default();
-            let spec = AttachmentSpec {
+            Some(Attachment {
+                id: path_str.to_string(),
                 content_type,
-                length: contents.len(),
-                file_name: Path::new(path)
-                    .file_name()
-                    .map(|f| f.to_string_lossy().into()),
-                preview: None,
-                voice_note: None,
-                borderless: None,
-                width: None,
-                height: None,
-                caption: None,
-                blur_hash: None,
-            };
-            Some((spec, contents))
+                filename: path.to_path_buf(),
+                size,
+            })
         });
 
         let attachments = attachments.collect();
@@ -1604,12 +3744,146 @@ impl App {
         attachments
     }
 
+    /// Parses the sticker, and adds its saved image to the attachments.
+    async fn save_sticker(
+        &mut self,
+        sticker: Option<data_message::Sticker>,
+        attachments: &mut Vec<Attachment>,
+    ) -> Option<Sticker> {
+        let sticker_proto = sticker?;
+        let sticker = Sticker::from_proto(&sticker_proto);
+        if sticker.is_none() {
+            log::warn!("dropping sticker without pack or sticker id");
+        }
+        if let Some(data) = sticker_proto.data {
+            attachments.extend(self.save_attachments(vec![data]).await);
+        }
+        sticker
+    }
+
     pub fn toggle_help(&mut self) {
         self.display_help = !self.display_help;
     }
 
     pub fn toggle_search(&mut self) {
         self.is_searching = !self.is_searching;
+        if !self.is_searching {
+            self.stored_search_results.clear();
+        }
+    }
+
+    /// Whether the search box contains a message search query, i.e. it starts with `/`.
+    pub fn is_message_search(&self) -> bool {
+        self.is_searching && self.data.search_box.data.starts_with(MESSAGE_SEARCH_PREFIX)
+    }
+
+    /// Adds the message to the search index, and marks the search results as outdated.
+    fn index_message(&mut self, channel_id: ChannelId, message: &Message) {
+        self.search_index.add(channel_id, message);
+        self.search_query = None;
+    }
+
+    /// Marks the search results as outdated after messages were removed, incl. the results of the
+    /// storage search.
+    fn invalidate_search_results(&mut self) {
+        self.search_query = None;
+        self.stored_search_results.clear();
+    }
+
+    /// Updates the search results, if the message search query or the indexed messages changed.
+    ///
+    /// The loaded messages are searched in the search index; the messages which are not loaded
+    /// yet are searched in the storage. The results of the storage are cached per query.
+    pub fn update_search_results(&mut self) {
+        let query = self
+            .data
+            .search_box
+            .data
+            .strip_prefix(MESSAGE_SEARCH_PREFIX)
+            .unwrap_or_default();
+        if self.search_query.as_deref() == Some(query) {
+            return;
+        }
+
+        let mut matches: Vec<_> = self.search_index.search(query).into_iter().collect();
+        matches.sort_unstable_by_key(|&(_, arrived_at)| Reverse(arrived_at));
+        matches.truncate(SEARCH_RESULTS_LIMIT);
+        let mut results: Vec<SearchResult> = matches
+            .iter()
+            .filter_map(|&(channel_id, arrived_at)| {
+                SearchResult::new(channel_id, self.find_message(channel_id, arrived_at)?)
+            })
+            .collect();
+        let stored_results = match self.stored_search_results.entry(query.to_string()) {
+            Entry::Occupied(entry) => entry.get().clone(),
+            Entry::Vacant(entry) => match self.storage.search_messages(query, SEARCH_RESULTS_LIMIT)
+            {
+                Ok(stored_results) => entry.insert(stored_results.unwrap_or_default()).clone(),
+                Err(e) => {
+                    log::error!("failed to search messages: {}", e);
+                    Vec::new()
+                }
+            },
+        };
+        if !stored_results.is_empty() {
+            // loaded messages are found in memory, which has their current bodies
+            let stored_results = stored_results.into_iter().filter(|result| {
+                self.find_message(result.channel_id, result.arrived_at)
+                    .is_none()
+            });
+            results.extend(stored_results);
+            results.sort_unstable_by_key(|result| Reverse(result.arrived_at));
+            results.truncate(SEARCH_RESULTS_LIMIT);
+        }
+
+        self.search_query = Some(query.to_string());
+        self.search_results = StatefulList::with_items(results);
+        if !self.search_results.items.is_empty() {
+            self.search_results.state.select(Some(0));
+        }
+    }
+
+    /// Leaves the search, and selects the channel and the message of the selected search result.
+    ///
+    /// Older messages are loaded from the storage until the message is found.
+    fn jump_to_search_result(&mut self) -> Option<()> {
+        let selected = self.search_results.state.selected()?;
+        let result = self.search_results.items.get(selected)?;
+        let (channel_id, arrived_at) = (result.channel_id, result.arrived_at);
+        let channel_idx = self
+            .data
+            .channels
+            .items
+            .iter()
+            .position(|channel| channel.id == channel_id)?;
+
+        let message_idx = loop {
+            let messages = &self.data.channels.items[channel_idx].messages.items;
+            if let Some(idx) = messages.iter().rposition(|m| m.arrived_at == arrived_at) {
+                break idx;
+            }
+            match self.load_older_messages(channel_idx) {
+                Ok(0) => return None,
+                Ok(_) => (),
+                Err(e) => {
+                    log::error!("failed to load older messages: {}", e);
+                    return None;
+                }
+            }
+        };
+
+        self.data.search_box.take();
+        self.is_searching = false;
+        self.data.channels.filter_channels("", &self.data.names);
+        self.data.channels.state.select(Some(channel_idx));
+        self.reset_unread_messages();
+
+        // messages are shown in reversed order
+        let messages = &mut self.data.channels.items[channel_idx].messages;
+        messages
+            .state
+            .select(Some(messages.items.len() - 1 - message_idx));
+        Some(())
     }
 
     pub fn is_help(&self) -> bool {
@@ -1617,6 +3891,58 @@ impl App {
     }
 }
 
+/// Removes the saved files of the attachments of the messages, e.g. of expired disappearing
+/// messages.
+///
+/// Only the files in the directory of saved attachments are removed, but not the files which
+/// were attached to own messages.
+fn remove_attachment_files<'a>(
+    attachments_dir: Option<&Path>,
+    messages: impl IntoIterator<Item = &'a Message>,
+) {
+    let attachments_dir = match attachments_dir {
+        Some(dir) => dir,
+        None => return,
+    };
+    let attachments = messages
+        .into_iter()
+        .flat_map(|message| &message.attachments)
+        .filter(|attachment| attachment.filename.starts_with(attachments_dir));
+    for attachment in attachments {
+        match std::fs::remove_file(&attachment.filename) {
+            Ok(()) => (),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+            Err(e) => log::warn!(
+                "failed to remove attachment '{}': {}",
+                attachment.filename.display(),
+                e
+            ),
+        }
+    }
+}
+
+/// Adds, replaces or removes the reaction of `from_id` on the message.
+///
+/// Returns whether a reaction was added or replaced.
+fn apply_reaction(message: &mut Message, from_id: Uuid, emoji: &str, remove: bool) -> bool {
+    let reaction_idx = message
+        .reactions
+        .iter()
+        .position(|(reaction_from_id, _)| *reaction_from_id == from_id);
+    if let Some(idx) = reaction_idx {
+        if remove {
+            message.reactions.remove(idx);
+            false
+        } else {
+            message.reactions[idx].1 = emoji.to_string();
+            true
+        }
+    } else {
+        message.reactions.push((from_id, emoji.to_string()));
+        true
+    }
+}
+
 pub fn name_by_id(names: &HashMap<Uuid, String>, id: Uuid) -> &str {
     names.get(&id).map(|s| s.as_ref()).unwrap_or("Unknown Name")
 }
@@ -1633,6 +3959,41 @@ fn to_emoji(s: &str) -> Option<&str> {
     }
 }
 
+/// Replaces `@` followed by the name of a member with a mention placeholder.
+///
+/// Longer names are matched first, s.t. a name is not mistaken for a prefix of it.
+fn extract_mentions(text: &str, members: &[(Uuid, &str)]) -> (String, Vec<Mention>) {
+    let mut members = members.to_vec();
+    members.sort_unstable_by_key(|(_, name)| Reverse(name.len()));
+
+    let mut body = String::with_capacity(text.len());
+    let mut mentions = Vec::new();
+    let mut offset = 0;
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let mentioned = rest.strip_prefix('@').and_then(|after_at| {
+            members
+                .iter()
+                .find_map(|&(uuid, name)| Some((uuid, after_at.strip_prefix(name)?)))
+        });
+        if let Some((uuid, after_mention)) = mentioned {
+            mentions.push(Mention {
+                start: offset,
+                length: 1,
+                uuid,
+            });
+            body.push(MENTION_PLACEHOLDER);
+            offset += MENTION_PLACEHOLDER.len_utf16() as u32;
+            rest = after_mention;
+        } else {
+            body.push(c);
+            offset += c.len_utf16() as u32;
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    (body, mentions)
+}
+
 fn open_url(message: &Message, url_regex: &Regex) -> Option<()> {
     let text = message.message.as_ref()?;
     let (start, end) = url_regex.find(text.as_bytes())?;
@@ -1643,6 +4004,17 @@ fn open_url(message: &Message, url_regex: &Regex) -> Option<()> {
     Some(())
 }
 
+fn is_expiration_timer_update(flags: u32) -> bool {
+    flags & Flags::ExpirationTimerUpdate as u32 != 0
+}
+
+fn notification_text_for_sticker(sticker: &Sticker) -> String {
+    match sticker.emoji.as_deref() {
+        Some(emoji) => format!("<sticker {}>", emoji),
+        None => "<sticker>".into(),
+    }
+}
+
 fn notification_text_for_attachments(attachments: &[Attachment]) -> Option<String> {
     match attachments.len() {
         0 => None,
@@ -1656,16 +4028,21 @@ mod tests {
     use super::*;
 
     use crate::config::User;
+    use crate::outbox::MAX_SEND_ATTEMPTS;
     use crate::signal::test::SignalManagerMock;
     use crate::storage::test::InMemoryStorage;
+    use crate::storage::SqliteStorage;
 
-    use std::cell::RefCell;
+    use std::cell::{Cell, RefCell};
     use std::rc::Rc;
 
     fn test_app() -> (App, Rc<RefCell<Vec<Message>>>) {
         let signal_manager = SignalManagerMock::new();
         let sent_messages = signal_manager.sent_messages.clone();
+        (test_app_with(signal_manager), sent_messages)
+    }
 
+    fn test_app_with(signal_manager: SignalManagerMock) -> App {
         let mut app = App::try_new(
             Config::with_user(User {
                 name: "Tyler Durden".to_string(),
@@ -1675,6 +4052,8 @@ mod tests {
             Box::new(InMemoryStorage::new()),
         )
         .unwrap();
+        // never remove the saved attachments of the user
+        app.attachments_dir = None;
 
         app.data.channels.items.push(Channel {
             id: ChannelId::User(Uuid::new_v4()),
@@ -1685,38 +4064,320 @@ mod tests {
                 revision: 1,
             }),
             messages: StatefulList::with_items(vec![Message {
-                from_id: app.user_id,
-                message: Some("First message".to_string()),
-                arrived_at: 0,
-                quote: Default::default(),
-                attachments: Default::default(),
-                reactions: Default::default(),
                 receipt: Default::default(),
+                ..Message::text(app.user_id, "First message", 0)
             }]),
             unread_messages: 1,
-            typing: TypingSet::GroupTyping(HashSet::new()),
+            expire_timer: None,
+            is_message_request: false,
+            typing: Default::default(),
         });
         app.data.channels.state.select(Some(0));
+        app.set_online(true);
+
+        app
+    }
+
+    /// Sends the outbox until it is empty or sending an item failed.
+    async fn send_outbox(app: &mut App) {
+        while let Some((id, send)) = app.take_outgoing() {
+            let result = send.await;
+            app.handle_send_result(id, result);
+        }
+    }
+
+    /// Storage failing to save, e.g. because the disk is full.
+    struct FailingStorage;
+
+    impl Storage for FailingStorage {
+        fn save_app_data(&self, _data: &AppData) -> anyhow::Result<()> {
+            Err(anyhow!("no space left on device"))
+        }
+
+        fn load_app_data(&self, _user_id: Uuid, _user_name: String) -> anyhow::Result<AppData> {
+            Ok(Default::default())
+        }
+    }
+
+    /// Incremental storage which fails to persist the order of channels.
+    struct FailingChannelOrderStorage;
+
+    impl Storage for FailingChannelOrderStorage {
+        fn save_app_data(&self, _data: &AppData) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn is_incremental(&self) -> bool {
+            true
+        }
+
+        fn update_channel_order(&self, _channel_ids: &[ChannelId]) -> anyhow::Result<()> {
+            Err(anyhow!("no space left on device"))
+        }
+
+        fn load_app_data(&self, _user_id: Uuid, _user_name: String) -> anyhow::Result<AppData> {
+            Ok(Default::default())
+        }
+    }
+
+    #[test]
+    fn test_flush_drops_failing_change() {
+        let (mut app, _) = test_app();
+        app.storage = Box::new(FailingChannelOrderStorage);
+        let user_id = app.user_id;
+
+        app.mark_dirty(PendingChange::ChannelOrder);
+        app.mark_dirty(PendingChange::Name(user_id));
+        assert!(app.flush().is_err());
+        // the other changes are persisted
+        assert_eq!(
+            app.pending_changes.iter().collect::<Vec<_>>(),
+            [&PendingChange::ChannelOrder]
+        );
+
+        for _ in 1..MAX_PERSIST_ATTEMPTS {
+            assert!(app.flush().is_err());
+        }
+        assert!(app.pending_changes.is_empty());
+        assert!(app.failed_changes.is_empty());
+
+        // the dropped change is reported also after successful flushes
+        app.mark_dirty(PendingChange::Name(user_id));
+        app.flush().unwrap();
+        let error = app.storage_error.as_ref().unwrap();
+        assert!(error.contains("1 changes"));
+    }
+
+    #[test]
+    fn test_unloaded_message_is_persisted_on_flush() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut app, _) = test_app();
+        app.storage = Box::new(SqliteStorage::open(dir.path().join("gurk.sqlite")).unwrap());
+        let channel = &mut app.data.channels.items[0];
+        let channel_id = channel.id;
+        let message = channel.messages.items.remove(0);
+        app.storage.append_message(channel, &message).unwrap();
+
+        let marla = Uuid::new_v4();
+        let robert = Uuid::new_v4();
+        app.handle_reaction(channel_id, 0, marla, "👍".to_string(), false, false);
+        app.handle_reaction(channel_id, 0, robert, "👊".to_string(), false, false);
+        assert!(app
+            .pending_changes
+            .contains(&PendingChange::UnloadedMessage {
+                channel_id,
+                arrived_at: 0
+            }));
+        let stored = app.storage.load_message(channel_id, 0).unwrap().unwrap();
+        assert!(stored.reactions.is_empty());
+
+        // both reactions are persisted
+        app.flush().unwrap();
+        let stored = app.storage.load_message(channel_id, 0).unwrap().unwrap();
+        assert!(stored.reactions.contains(&(marla, "👍".to_string())));
+        assert!(stored.reactions.contains(&(robert, "👊".to_string())));
+        assert!(app.unloaded_messages.is_empty());
+    }
+
+    #[test]
+    fn test_send_input_marks_changes() {
+        let (mut app, _) = test_app();
+        let channel_id = app.data.channels.items[0].id;
+
+        app.get_input().put_char('a');
+        app.send_input(0).unwrap();
+        // already pending changes are not duplicated
+        app.mark_dirty(PendingChange::ChannelOrder);
+
+        let arrived_at = app.data.channels.items[0].messages.items[1].arrived_at;
+        assert_eq!(
+            app.pending_changes.iter().copied().collect::<Vec<_>>(),
+            vec![
+                PendingChange::UsedWords,
+                PendingChange::Message {
+                    channel_id,
+                    arrived_at
+                },
+                PendingChange::Channel(channel_id),
+                PendingChange::ChannelOrder,
+                PendingChange::Outbox,
+                PendingChange::Receipt {
+                    channel_id,
+                    arrived_at
+                },
+            ]
+        );
+
+        app.flush().unwrap();
+        assert!(app.pending_changes.is_empty());
+    }
+
+    #[test]
+    fn test_new_channel_is_marked_dirty() {
+        let (mut app, _) = test_app();
+        let uuid = Uuid::new_v4();
+
+        let channel_idx = app.ensure_contact_channel_exists(uuid, "Marla Singer");
+        assert!(app
+            .pending_changes
+            .contains(&PendingChange::Channel(uuid.into())));
+        app.flush().unwrap();
+
+        // renamed channel
+        app.data.names.insert(uuid, "Marla".to_string());
+        assert_eq!(
+            app.ensure_contact_channel_exists(uuid, "Marla Singer"),
+            channel_idx
+        );
+        assert_eq!(app.data.channels.items[channel_idx].name, "Marla");
+        assert!(app
+            .pending_changes
+            .contains(&PendingChange::Channel(uuid.into())));
+    }
+
+    #[test]
+    fn test_flush_failure_keeps_changes() {
+        let (mut app, _) = test_app();
+        app.storage = Box::new(FailingStorage);
+        app.mark_dirty(PendingChange::ChannelOrder);
+
+        assert!(app.flush().is_err());
+        assert_eq!(
+            app.pending_changes.iter().collect::<Vec<_>>(),
+            [&PendingChange::ChannelOrder]
+        );
+        let error = app.storage_error.as_ref().unwrap();
+        assert!(error.contains("no space left"));
+
+        app.storage = Box::new(InMemoryStorage::new());
+        app.flush().unwrap();
+        assert!(app.pending_changes.is_empty());
+        assert_eq!(app.storage_error, None);
+    }
+
+    #[test]
+    fn test_send_input() {
+        let (mut app, sent_messages) = test_app();
+        let input = "Hello, World!";
+        for c in input.chars() {
+            app.get_input().put_char(c);
+        }
+        app.send_input(0).unwrap();
+
+        let sent = sent_messages.borrow();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].message.as_ref().unwrap(), input);
+
+        assert_eq!(app.data.channels.items[0].unread_messages, 0);
+
+        assert_eq!(app.get_input().data, "");
+    }
+
+    #[test]
+    fn test_send_input_with_mention() {
+        let (mut app, sent_messages) = test_app();
+        let marla = Uuid::new_v4();
+        app.data.names.insert(marla, "Marla Singer".to_string());
+        app.data
+            .names
+            .insert(Uuid::new_v4(), "Marlon Brando".to_string());
+        let group_data = app.data.channels.items[0].group_data.as_mut().unwrap();
+        group_data.members.push(marla);
+
+        for c in "Hi @ma".chars() {
+            app.get_input().put_char(c);
+        }
+        app.complete_mention(0).unwrap();
+        assert_eq!(app.data.input.data, "Hi @Marla Singer ");
+        // nothing left to complete
+        assert!(app.complete_mention(0).is_none());
+
+        for c in "and @Tyler Durden".chars() {
+            app.get_input().put_char(c);
+        }
+        app.send_input(0).unwrap();
+
+        let sent = sent_messages.borrow();
+        assert_eq!(sent[0].message.as_deref(), Some("Hi \u{fffc} and \u{fffc}"));
+        assert_eq!(
+            sent[0].mentions,
+            [
+                Mention {
+                    start: 3,
+                    length: 1,
+                    uuid: marla
+                },
+                Mention {
+                    start: 9,
+                    length: 1,
+                    uuid: app.user_id
+                },
+            ]
+        );
+        assert_eq!(
+            sent[0]
+                .body_with_mentions(|id| app.name_by_id(id))
+                .as_deref(),
+            Some("Hi @Marla Singer and @Tyler Durden")
+        );
+    }
+
+    #[test]
+    fn test_complete_mention_common_prefix() {
+        let (mut app, _) = test_app();
+        let group_data = app.data.channels.items[0].group_data.as_mut().unwrap();
+        for name in ["Marla Singer", "Marla Smith"] {
+            let uuid = Uuid::new_v4();
+            group_data.members.push(uuid);
+            app.data.names.insert(uuid, name.to_string());
+        }
+
+        for c in "@m".chars() {
+            app.get_input().put_char(c);
+        }
+        app.complete_mention(0).unwrap();
+        assert_eq!(app.data.input.data, "@Marla S");
 
-        (app, sent_messages)
+        // not a mention
+        app.data.input.take();
+        for c in "me@m".chars() {
+            app.get_input().put_char(c);
+        }
+        assert!(app.complete_mention(0).is_none());
     }
 
     #[test]
-    fn test_send_input() {
-        let (mut app, sent_messages) = test_app();
-        let input = "Hello, World!";
-        for c in input.chars() {
+    fn test_complete_used_word() {
+        let (mut app, _) = test_app();
+        app.data.used_words = ["project", "progress", "mayhem"]
+            .iter()
+            .map(|word| word.to_string())
+            .collect();
+
+        for c in "the pro".chars() {
             app.get_input().put_char(c);
         }
-        app.send_input(0).unwrap();
-
-        let sent = sent_messages.borrow();
-        assert_eq!(sent.len(), 1);
-        assert_eq!(sent[0].message.as_ref().unwrap(), input);
+        app.complete().unwrap();
+        assert_eq!(app.data.input.data, "the project");
 
-        assert_eq!(app.data.channels.items[0].unread_messages, 0);
+        // nothing to complete
+        app.get_input().put_char(' ');
+        assert!(app.complete().is_none());
+    }
 
-        assert_eq!(app.get_input().data, "");
+    #[test]
+    fn test_sticker_message_is_not_empty() {
+        let message = Message::new(Uuid::nil(), None, 1, Vec::new());
+        assert!(message.is_empty());
+        let message = Message {
+            sticker: Some(Sticker {
+                pack_id: "0a1b".to_string(),
+                sticker_id: 7,
+                emoji: None,
+            }),
+            ..message
+        };
+        assert!(!message.is_empty());
     }
 
     #[test]
@@ -1809,4 +4470,1014 @@ mod tests {
         let reactions = &app.data.channels.items[0].messages.items[0].reactions;
         assert!(reactions.is_empty());
     }
+
+    /// Storage which counts the searches of stored messages.
+    struct SearchCountingStorage(Rc<Cell<usize>>);
+
+    impl Storage for SearchCountingStorage {
+        fn save_app_data(&self, _data: &AppData) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn search_messages(
+            &self,
+            _query: &str,
+            _limit: usize,
+        ) -> anyhow::Result<Option<Vec<SearchResult>>> {
+            self.0.set(self.0.get() + 1);
+            Ok(Some(Vec::new()))
+        }
+
+        fn load_app_data(&self, _user_id: Uuid, _user_name: String) -> anyhow::Result<AppData> {
+            Ok(Default::default())
+        }
+    }
+
+    #[test]
+    fn test_stored_search_results_are_cached() {
+        let (mut app, _) = test_app();
+        let searches = Rc::new(Cell::new(0));
+        app.storage = Box::new(SearchCountingStorage(searches.clone()));
+
+        app.toggle_search();
+        for c in "/hel".chars() {
+            app.get_input().put_char(c);
+        }
+        app.update_search_results();
+        assert_eq!(searches.get(), 1);
+
+        // new messages are searched in the search index only
+        let message = Message::new(app.user_id, Some("Hello".to_string()), 1, vec![]);
+        app.add_message_to_channel(0, message);
+        app.update_search_results();
+        assert_eq!(searches.get(), 1);
+        assert!(app
+            .search_results
+            .items
+            .iter()
+            .any(|result| result.arrived_at == 1));
+
+        // removed messages invalidate the stored results
+        app.invalidate_search_results();
+        app.update_search_results();
+        assert_eq!(searches.get(), 2);
+    }
+
+    #[test]
+    fn test_search_and_jump_to_message() {
+        let (mut app, _) = test_app();
+        for (arrived_at, text) in [(1, "Hello, World!"), (2, "Bye")] {
+            let message = Message::new(app.user_id, Some(text.to_string()), arrived_at, vec![]);
+            app.add_message_to_channel(0, message);
+        }
+
+        app.toggle_search();
+        for c in "/hel".chars() {
+            app.get_input().put_char(c);
+        }
+        assert!(app.is_message_search());
+        app.update_search_results();
+        assert_eq!(app.search_results.items.len(), 1);
+        assert_eq!(app.search_results.items[0].text, "Hello, World!");
+        assert_eq!(app.search_results.state.selected(), Some(0));
+
+        app.on_key(KeyEvent::new(KeyCode::Enter, KeyModifiers::NONE))
+            .unwrap();
+        assert!(!app.is_searching);
+        assert!(app.data.search_box.data.is_empty());
+        assert_eq!(app.data.channels.state.selected(), Some(0));
+        // messages are selected in reversed order
+        let messages = &app.data.channels.items[0].messages;
+        assert_eq!(messages.state.selected(), Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_outbox_is_sent_on_reconnect() {
+        let (mut app, sent_messages) = test_app();
+        app.set_online(false);
+
+        app.get_input().put_char('a');
+        app.send_input(0).unwrap();
+        // react to the first message
+        app.data.channels.items[0].messages.state.select(Some(1));
+        app.get_input().put_char('👍');
+        app.add_reaction(0);
+        app.add_receipt_event(ReceiptEvent::new(Uuid::new_v4(), 0, Receipt::Delivered));
+        app.step_receipts();
+
+        assert_eq!(app.data.outbox.len(), 3);
+        assert!(app.take_outgoing().is_none());
+        assert!(sent_messages.borrow().is_empty());
+        let message = &app.data.channels.items[0].messages.items[1];
+        assert_eq!(message.receipt, Receipt::Queued);
+
+        app.set_online(true);
+        let message = &app.data.channels.items[0].messages.items[1];
+        assert_eq!(message.receipt, Receipt::Pending);
+        send_outbox(&mut app).await;
+
+        assert!(app.data.outbox.is_empty());
+        assert!(app.pending_changes.contains(&PendingChange::Outbox));
+        let sent = sent_messages.borrow();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].message.as_deref(), Some("a"));
+        let message = &app.data.channels.items[0].messages.items[1];
+        assert_eq!(message.receipt, Receipt::Sent);
+    }
+
+    #[tokio::test]
+    async fn test_outbox_retries_and_fails_message() {
+        let signal_manager = SignalManagerMock::new();
+        let is_failing = signal_manager.is_failing.clone();
+        let sent_messages = signal_manager.sent_messages.clone();
+        let mut app = test_app_with(signal_manager);
+        is_failing.set(true);
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let input = format!("a file://{}", file.path().display());
+        for c in input.chars() {
+            app.get_input().put_char(c);
+        }
+        app.send_input(0).unwrap();
+        send_outbox(&mut app).await;
+        let message = &app.data.channels.items[0].messages.items[1];
+        assert_eq!(message.receipt, Receipt::Queued);
+        assert_eq!(message.attachments.len(), 1);
+        assert_eq!(app.data.outbox.len(), 1);
+
+        for _ in 1..MAX_SEND_ATTEMPTS {
+            // reconnecting retries right away
+            app.set_online(true);
+            send_outbox(&mut app).await;
+        }
+        let message = &app.data.channels.items[0].messages.items[1];
+        let arrived_at = message.arrived_at;
+        assert_eq!(message.receipt, Receipt::Failed);
+        assert!(app.data.outbox.is_empty());
+
+        // retry the selected failed message
+        is_failing.set(false);
+        app.data.channels.items[0].messages.state.select(Some(0));
+        app.on_key(KeyEvent::new(KeyCode::Char('r'), KeyModifiers::ALT))
+            .unwrap();
+        assert_eq!(app.data.channels.items[0].messages.state.selected(), None);
+        send_outbox(&mut app).await;
+
+        let sent = sent_messages.borrow();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].message.as_deref(), Some("a"));
+        assert_eq!(sent[0].attachments[0].filename, file.path());
+        let messages = &app.data.channels.items[0].messages;
+        assert_eq!(messages.items.len(), 2);
+        assert_eq!(messages.items[1].arrived_at, arrived_at);
+        assert_eq!(messages.items[1].attachments.len(), 1);
+        assert_eq!(messages.items[1].receipt, Receipt::Sent);
+    }
+
+    #[tokio::test]
+    async fn test_send_failure_while_offline_is_not_counted() {
+        let signal_manager = SignalManagerMock::new();
+        let is_failing = signal_manager.is_failing.clone();
+        let mut app = test_app_with(signal_manager);
+        is_failing.set(true);
+
+        app.get_input().put_char('a');
+        app.send_input(0).unwrap();
+        let (id, send) = app.take_outgoing().unwrap();
+        // the connection is lost while sending
+        app.set_online(false);
+        app.handle_send_result(id, send.await);
+
+        let message = &app.data.channels.items[0].messages.items[1];
+        assert_eq!(message.receipt, Receipt::Queued);
+        assert_eq!(app.data.outbox.items().next().unwrap().attempts, 0);
+
+        is_failing.set(false);
+        app.set_online(true);
+        send_outbox(&mut app).await;
+        assert!(app.data.outbox.is_empty());
+        let message = &app.data.channels.items[0].messages.items[1];
+        assert_eq!(message.receipt, Receipt::Sent);
+    }
+
+    #[test]
+    fn test_discard_queued_message() {
+        let (mut app, _) = test_app();
+        let channel_id = app.data.channels.items[0].id;
+
+        // messages being sent are not discarded
+        app.get_input().put_char('a');
+        app.send_input(0).unwrap();
+        app.data.channels.items[0].messages.state.select(Some(0));
+        app.on_key(KeyEvent::new(KeyCode::Char('d'), KeyModifiers::ALT))
+            .unwrap();
+        assert_eq!(app.data.channels.items[0].messages.items.len(), 2);
+
+        app.set_online(false);
+        app.get_input().put_char('b');
+        app.send_input(0).unwrap();
+        let arrived_at = app.data.channels.items[0].messages.items[2].arrived_at;
+        assert_eq!(app.data.outbox.len(), 2);
+
+        app.data.channels.items[0].messages.state.select(Some(0));
+        app.on_key(KeyEvent::new(KeyCode::Char('d'), KeyModifiers::ALT))
+            .unwrap();
+        assert_eq!(app.data.channels.items[0].messages.items.len(), 2);
+        assert_eq!(app.data.outbox.len(), 1);
+        let deleted = PendingChange::DeletedMessage {
+            channel_id,
+            arrived_at,
+        };
+        assert!(app.pending_changes.contains(&deleted));
+    }
+
+    #[tokio::test]
+    async fn test_delete_own_message_for_everyone() {
+        let signal_manager = SignalManagerMock::new();
+        let sent_deletes = signal_manager.sent_deletes.clone();
+        let mut app = test_app_with(signal_manager);
+        let channel_id = app.data.channels.items[0].id;
+        app.data.channels.items[0].messages.items[0].receipt = Receipt::Delivered;
+
+        app.data.channels.items[0].messages.state.select(Some(0));
+        app.on_key(KeyEvent::new(KeyCode::Char('x'), KeyModifiers::ALT))
+            .unwrap();
+        send_outbox(&mut app).await;
+
+        let message = &app.data.channels.items[0].messages.items[0];
+        assert!(message.is_deleted);
+        assert_eq!(message.message, None);
+        assert_eq!(*sent_deletes.borrow(), [0]);
+        assert!(app.pending_changes.contains(&PendingChange::Message {
+            channel_id,
+            arrived_at: 0
+        }));
+
+        // deleted messages are not deleted again
+        app.data.channels.items[0].messages.state.select(Some(0));
+        app.on_key(KeyEvent::new(KeyCode::Char('x'), KeyModifiers::ALT))
+            .unwrap();
+        assert!(app.data.outbox.is_empty());
+    }
+
+    #[test]
+    fn test_expire_timer_command() {
+        let (mut app, _) = test_app();
+        let channel_id = app.data.channels.items[0].id;
+
+        // invalid commands are kept in the input
+        app.data.input.set("/timer soon");
+        app.send_input(0).unwrap();
+        assert_eq!(app.data.input.data, "/timer soon");
+        assert_eq!(app.command_error.as_deref(), Some("invalid timer: soon"));
+
+        app.data.input.set("/timer 1h");
+        app.send_input(0).unwrap();
+        assert_eq!(app.command_error, None);
+        let channel = &app.data.channels.items[0];
+        assert_eq!(channel.expire_timer, Some(3600));
+        let system_message = &channel.messages.items[1];
+        assert!(system_message.is_system);
+        assert_eq!(
+            system_message.message.as_deref(),
+            Some("You set the disappearing message time to 1 hour")
+        );
+        assert!(app.data.outbox.items().any(|queued| queued.item
+            == OutboxItem::ExpireTimer {
+                channel_id,
+                timer: Some(3600)
+            }));
+
+        // sent messages disappear
+        app.data.input.set("//secret");
+        app.send_input(0).unwrap();
+        let message = &app.data.channels.items[0].messages.items[2];
+        assert_eq!(message.message.as_deref(), Some("/secret"));
+        assert_eq!(message.expires_in, Some(3600));
+
+        // setting the same timer again does nothing
+        app.data.input.set("/timer 60m");
+        app.send_input(0).unwrap();
+        assert_eq!(app.data.channels.items[0].messages.items.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_group_commands() {
+        let (mut app, _) = test_app();
+        let marla = Uuid::new_v4();
+        app.data.names.insert(marla, "Marla Singer".to_string());
+
+        app.data.input.set("/group rename Paper Street");
+        app.send_input(0).unwrap();
+        // applied only after it was sent
+        assert_eq!(app.data.channels.items[0].name, "test");
+        send_outbox(&mut app).await;
+        let channel = &app.data.channels.items[0];
+        assert_eq!(channel.name, "Paper Street");
+        assert_eq!(channel.group_data.as_ref().unwrap().revision, 2);
+        let system_message = channel.messages.items.last().unwrap();
+        assert!(system_message.is_system);
+        assert_eq!(
+            system_message.message.as_deref(),
+            Some("You changed the group name to \"Paper Street\"")
+        );
+
+        app.data.input.set("/group add @Marla Singer");
+        app.send_input(0).unwrap();
+        send_outbox(&mut app).await;
+        let channel = &app.data.channels.items[0];
+        assert_eq!(
+            channel.group_data.as_ref().unwrap().members,
+            [app.user_id, marla]
+        );
+        assert_eq!(
+            channel.messages.items.last().unwrap().message.as_deref(),
+            Some("You added Marla Singer")
+        );
+
+        // failing commands are kept in the input
+        app.data.input.set("/group add @Marla Singer");
+        app.send_input(0).unwrap();
+        assert_eq!(app.data.input.data, "/group add @Marla Singer");
+        assert_eq!(
+            app.command_error.as_deref(),
+            Some("already a member: Marla Singer")
+        );
+        app.data.input.set("/group remove @Robert Paulson");
+        app.send_input(0).unwrap();
+        assert_eq!(
+            app.command_error.as_deref(),
+            Some("not a member: Robert Paulson")
+        );
+        assert!(app.data.outbox.is_empty());
+
+        app.data.input.set("/group leave");
+        app.send_input(0).unwrap();
+        send_outbox(&mut app).await;
+        let channel = &app.data.channels.items[0];
+        assert_eq!(channel.group_data.as_ref().unwrap().members, [marla]);
+        assert_eq!(channel.group_data.as_ref().unwrap().revision, 4);
+    }
+
+    #[tokio::test]
+    async fn test_create_group() {
+        let (mut app, _) = test_app();
+        let marla = Uuid::new_v4();
+        app.data.names.insert(marla, "Marla Singer".to_string());
+
+        app.data
+            .input
+            .set("/group create Project Mayhem @Robert Paulson");
+        app.send_input(0).unwrap();
+        assert_eq!(
+            app.command_error.as_deref(),
+            Some("unknown contact: Robert Paulson")
+        );
+        assert_eq!(app.data.channels.items.len(), 1);
+
+        app.data
+            .input
+            .set("/group create Project Mayhem @Marla Singer");
+        app.send_input(0).unwrap();
+        assert_eq!(app.data.channels.items.len(), 2);
+        let channel_idx = app.data.channels.state.selected().unwrap();
+        let channel = &app.data.channels.items[channel_idx];
+        assert_eq!(channel.name, "Project Mayhem");
+        let group_data = channel.group_data.as_ref().unwrap();
+        assert_eq!(group_data.members, [app.user_id, marla]);
+        assert_eq!(group_data.revision, 0);
+        assert_eq!(
+            channel.id,
+            ChannelId::from_master_key_bytes(group_data.master_key_bytes).unwrap()
+        );
+
+        let channel_id = channel.id;
+        send_outbox(&mut app).await;
+        let channel = app.find_channel(channel_id).unwrap();
+        assert_eq!(channel.group_data.as_ref().unwrap().revision, 0);
+        assert_eq!(
+            channel.messages.items.last().unwrap().message.as_deref(),
+            Some("You created the group")
+        );
+    }
+
+    #[test]
+    fn test_group_change_messages() {
+        let (mut app, _) = test_app();
+        let [marla, bob, robert] = [Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4()];
+        app.data.names.insert(marla, "Marla Singer".to_string());
+        app.data.names.insert(bob, "Bob".to_string());
+        app.data.names.insert(robert, "Robert Paulson".to_string());
+        let old_members = vec![app.user_id, marla, robert];
+
+        // group re-resolved after an update by Marla
+        let channel = &mut app.data.channels.items[0];
+        channel.name = "Project Mayhem".to_string();
+        channel.group_data.as_mut().unwrap().members = vec![app.user_id, marla, bob];
+        let channel_idx = app.add_group_change_messages(0, "test", &old_members, Some(marla), 10);
+        assert_eq!(channel_idx, 0);
+        let messages = &app.data.channels.items[0].messages.items[1..];
+        assert!(messages.iter().all(|message| message.is_system));
+        assert_eq!(
+            messages
+                .iter()
+                .map(|message| (message.arrived_at, message.message.as_deref().unwrap()))
+                .collect::<Vec<_>>(),
+            [
+                (
+                    7,
+                    "Marla Singer changed the group name to \"Project Mayhem\""
+                ),
+                (8, "Marla Singer added Bob"),
+                (9, "Marla Singer removed Robert Paulson"),
+            ]
+        );
+
+        // group re-resolved after a missed update
+        app.data.channels.items[0]
+            .group_data
+            .as_mut()
+            .unwrap()
+            .members
+            .retain(|&member| member != bob);
+        let old_members = vec![app.user_id, marla, bob];
+        // the line does not take the arrival time of an existing message
+        app.data.channels.items[0].messages.items.push(Message::new(
+            marla,
+            Some("Hi".to_string()),
+            19,
+            vec![],
+        ));
+        app.add_group_change_messages(0, "Project Mayhem", &old_members, None, 20);
+        let message = app.data.channels.items[0].messages.items.last().unwrap();
+        assert_eq!(message.from_id, bob);
+        assert_eq!(message.arrived_at, 18);
+        assert_eq!(message.message.as_deref(), Some("Bob left the group"));
+    }
+
+    #[tokio::test]
+    async fn test_group_revision_change_is_persisted() {
+        let signal_manager = SignalManagerMock::new();
+        let resolved_group = signal_manager.resolved_group.clone();
+        let mut app = test_app_with(signal_manager);
+        let master_key = [7; 32];
+        let group_data = GroupData {
+            master_key_bytes: master_key,
+            members: vec![app.user_id],
+            revision: 1,
+        };
+        app.data.channels.items[0].id = ChannelId::from_master_key_bytes(master_key).unwrap();
+        app.data.channels.items[0].group_data = Some(group_data.clone());
+        *resolved_group.borrow_mut() = Some((
+            "test".to_string(),
+            GroupData {
+                revision: 2,
+                ..group_data
+            },
+        ));
+        app.pending_changes.clear();
+
+        let channel_idx = app
+            .ensure_group_channel_exists(master_key, 2, None, None, 10)
+            .await
+            .unwrap();
+        let channel = &app.data.channels.items[channel_idx];
+        assert_eq!(channel.group_data.as_ref().unwrap().revision, 2);
+        // no visible change
+        assert_eq!(channel.messages.items.len(), 1);
+        assert!(app
+            .pending_changes
+            .contains(&PendingChange::Channel(channel.id)));
+    }
+
+    #[test]
+    fn test_send_typing() {
+        let signal_manager = SignalManagerMock::new();
+        let sent_typing = signal_manager.sent_typing.clone();
+        let mut app = test_app_with(signal_manager);
+        app.data.channels.filter_channels("", &app.data.names);
+        let channel_id = app.data.channels.items[0].id;
+        let now = Instant::now();
+        let seconds = |secs| now + Duration::from_secs(secs);
+
+        app.data.input.set("Hi");
+        app.update_typing(now);
+        app.data.input.set("Hi Marla");
+        app.update_typing(seconds(1));
+        assert_eq!(*sent_typing.borrow(), [(channel_id, TypingAction::Started)]);
+
+        // ongoing typing is sent again after the refresh interval only
+        app.data.input.set("Hi Marla, how");
+        app.update_typing(now + TYPING_REFRESH_INTERVAL);
+        assert_eq!(sent_typing.borrow().len(), 2);
+
+        // idle typing stops
+        app.update_typing(now + TYPING_REFRESH_INTERVAL + TYPING_IDLE_TIMEOUT);
+        assert_eq!(
+            sent_typing.borrow().last(),
+            Some(&(channel_id, TypingAction::Stopped))
+        );
+
+        // commands are not typing
+        app.data.input.set("/timer 1h");
+        app.update_typing(seconds(20));
+        assert_eq!(sent_typing.borrow().len(), 3);
+
+        // sending the input stops typing
+        app.data.input.set("Bye");
+        app.update_typing(seconds(21));
+        app.send_input(0).unwrap();
+        app.update_typing(seconds(22));
+        assert_eq!(
+            sent_typing.borrow()[3..],
+            [
+                (channel_id, TypingAction::Started),
+                (channel_id, TypingAction::Stopped)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expire_typing() {
+        let (mut app, _) = test_app();
+        let sender = match app.data.channels.items[0].id {
+            ChannelId::User(uuid) => uuid,
+            ChannelId::Group(_) => unreachable!(),
+        };
+
+        // typing expires after it was received, regardless of the clock of the sender
+        let now = Instant::now();
+        app.handle_typing(sender, None, TypingAction::Started, now)
+            .unwrap();
+        assert!(app.data.channels.items[0].is_writing());
+
+        app.expire_typing(now + Duration::from_secs(1));
+        assert!(app.data.channels.items[0].is_writing());
+        app.expire_typing(now + TYPING_TIMEOUT + Duration::from_secs(1));
+        assert!(!app.data.channels.items[0].is_writing());
+    }
+
+    #[test]
+    fn test_handle_read_sync() {
+        let (mut app, _) = test_app();
+        let sender = Uuid::new_v4();
+        let channel = &mut app.data.channels.items[0];
+        for arrived_at in 1..=3 {
+            channel.messages.items.push(Message::new(
+                sender,
+                Some("Hi".to_string()),
+                arrived_at,
+                vec![],
+            ));
+        }
+        // own messages and system lines are never unread
+        let user_id = app.user_id;
+        channel
+            .messages
+            .items
+            .push(Message::new(user_id, Some("Hey".to_string()), 4, vec![]));
+        channel.messages.items.push(Message::system(
+            sender,
+            "Sender left the group".to_string(),
+            5,
+        ));
+        channel.unread_messages = 3;
+        let channel_id = channel.id;
+        let read = |timestamp| Read {
+            sender_uuid: Some(sender.to_string()),
+            timestamp: Some(timestamp),
+            ..Default::default()
+        };
+
+        app.handle_read_sync(vec![read(2)]);
+        assert_eq!(app.data.channels.items[0].unread_messages, 1);
+        assert!(app
+            .pending_changes
+            .contains(&PendingChange::Channel(channel_id)));
+
+        // reading an older message doesn't make newer ones unread
+        app.handle_read_sync(vec![read(1)]);
+        assert_eq!(app.data.channels.items[0].unread_messages, 1);
+
+        app.handle_read_sync(vec![read(3)]);
+        assert_eq!(app.data.channels.items[0].unread_messages, 0);
+    }
+
+    #[test]
+    fn test_import_contacts() {
+        let mut signal_manager = SignalManagerMock::new();
+        let (marla, bob, robert) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        signal_manager.contacts = vec![
+            Contact {
+                uuid: marla,
+                name: "Marla Singer".to_string(),
+                phone_number: Some("+11111111111".to_string()),
+            },
+            Contact {
+                uuid: bob,
+                name: String::new(),
+                phone_number: Some("+12222222222".to_string()),
+            },
+            Contact {
+                uuid: robert,
+                name: String::new(),
+                phone_number: Some("+13333333333".to_string()),
+            },
+        ];
+        let mut app = test_app_with(signal_manager);
+        app.data.names.insert(marla, "Marla".to_string());
+        app.data.names.insert(robert, "Robert Paulson".to_string());
+        let channel_idx = app.ensure_contact_channel_exists(marla, "Marla");
+
+        app.import_contacts();
+        assert_eq!(app.data.names[&marla], "Marla Singer");
+        assert_eq!(app.data.channels.items[channel_idx].name, "Marla Singer");
+        assert_eq!(app.data.names[&bob], "+12222222222");
+        assert_eq!(app.data.names[&robert], "Robert Paulson");
+        assert!(app.pending_changes.contains(&PendingChange::Name(marla)));
+        assert!(!app.pending_changes.contains(&PendingChange::Name(robert)));
+        assert!(app.is_known_sender(bob));
+    }
+
+    #[tokio::test]
+    async fn test_import_groups() {
+        let mut signal_manager = SignalManagerMock::new();
+        signal_manager.synced_groups = vec![[1; 32], [2; 32]];
+        let resolved_group = signal_manager.resolved_group.clone();
+        let mut app = test_app_with(signal_manager);
+        let num_channels = app.data.channels.items.len();
+        *resolved_group.borrow_mut() = Some((
+            "Project Mayhem".to_string(),
+            GroupData {
+                master_key_bytes: [1; 32],
+                members: vec![app.user_id],
+                revision: 3,
+            },
+        ));
+
+        app.import_groups(Default::default()).await;
+        assert_eq!(app.data.channels.items.len(), num_channels + 2);
+        let channel_id = ChannelId::from_master_key_bytes([2; 32]).unwrap();
+        let channel = app.find_channel(channel_id).unwrap();
+        assert_eq!(channel.name, "Project Mayhem");
+        assert!(!channel.is_message_request);
+        assert!(app
+            .pending_changes
+            .contains(&PendingChange::Channel(channel_id)));
+
+        // known groups are not imported again
+        app.import_groups(Default::default()).await;
+        assert_eq!(app.data.channels.items.len(), num_channels + 2);
+    }
+
+    #[test]
+    fn test_contact_commands() {
+        let mut signal_manager = SignalManagerMock::new();
+        let marla = Uuid::new_v4();
+        signal_manager.contacts = vec![Contact {
+            uuid: marla,
+            name: "Marla Singer".to_string(),
+            phone_number: Some("+11111111111".to_string()),
+        }];
+        let mut app = test_app_with(signal_manager);
+        app.import_contacts();
+
+        app.run_command(0, Command::Chat("+11111111111".to_string()))
+            .unwrap();
+        let channel_idx = app.data.channels.state.selected().unwrap();
+        let channel = &app.data.channels.items[channel_idx];
+        assert_eq!(channel.id, ChannelId::User(marla));
+        assert_eq!(channel.name, "Marla Singer");
+
+        // the existing channel is opened
+        app.run_command(0, Command::Chat("Marla Singer".to_string()))
+            .unwrap();
+        assert_eq!(app.data.channels.items.len(), 2);
+        assert_eq!(app.data.channels.state.selected(), Some(channel_idx));
+        assert!(app
+            .run_command(0, Command::Chat("Tyler".to_string()))
+            .is_err());
+
+        app.run_command(0, Command::SyncContacts).unwrap();
+        assert_eq!(
+            app.data.outbox.items().last().map(|queued| &queued.item),
+            Some(&OutboxItem::ContactsSyncRequest)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_identity_change() {
+        let signal_manager = SignalManagerMock::new();
+        let identity_keys = signal_manager.identity_keys.clone();
+        let mut app = test_app_with(signal_manager);
+        let marla = match app.data.channels.items[0].id {
+            ChannelId::User(uuid) => uuid,
+            ChannelId::Group(_) => unreachable!(),
+        };
+        app.data.names.insert(marla, "Marla".to_string());
+
+        // the first identity key is remembered silently
+        identity_keys.borrow_mut().insert(marla, vec![1; 33]);
+        app.check_identity(marla).await;
+        app.run_command(0, Command::Verify).unwrap();
+        assert!(app.data.identities[&marla].is_verified);
+        assert!(app.run_command(0, Command::Verify).is_err());
+        assert_eq!(app.data.channels.items[0].messages.items.len(), 1);
+
+        identity_keys.borrow_mut().insert(marla, vec![2; 33]);
+        app.check_identity(marla).await;
+        let identity = &app.data.identities[&marla];
+        assert_eq!(identity.key, vec![2; 33]);
+        assert!(!identity.is_verified);
+        assert!(app.pending_changes.contains(&PendingChange::Identities));
+        let warning = app.data.channels.items[0].messages.items.last().unwrap();
+        assert!(warning.is_system && warning.is_warning);
+        assert_eq!(
+            warning.message.as_deref(),
+            Some("⚠ Your safety number with Marla changed: verify it with f2")
+        );
+
+        // unchanged keys don't add warnings
+        app.check_identity(marla).await;
+        assert_eq!(app.data.channels.items[0].messages.items.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_toggle_safety_number() {
+        let signal_manager = SignalManagerMock::new();
+        let identity_keys = signal_manager.identity_keys.clone();
+        let mut app = test_app_with(signal_manager);
+        app.data.channels.filter_channels("", &app.data.names);
+        let marla = match app.data.channels.items[0].id {
+            ChannelId::User(uuid) => uuid,
+            ChannelId::Group(_) => unreachable!(),
+        };
+        identity_keys.borrow_mut().insert(marla, vec![1; 33]);
+
+        // the phone number is needed to compute the safety number
+        app.toggle_safety_number().await;
+        assert!(app.safety_number().is_none());
+        assert!(app.command_error.is_some());
+
+        app.data.names.insert(marla, "+11111111111".to_string());
+        app.toggle_safety_number().await;
+        let (uuid, safety_number, is_verified) = app.safety_number().unwrap();
+        assert_eq!(uuid, marla);
+        assert_eq!(safety_number.digits.len(), 60);
+        assert!(!is_verified);
+        assert_eq!(app.command_error, None);
+        assert!(app.data.identities.contains_key(&marla));
+
+        app.toggle_safety_number().await;
+        assert!(app.safety_number().is_none());
+    }
+
+    #[test]
+    fn test_block_commands() {
+        let (mut app, _) = test_app();
+        let channel_id = app.data.channels.items[0].id;
+
+        app.data.input.set("/block");
+        app.send_input(0).unwrap();
+        assert!(app.data.blocked.contains(&channel_id));
+        assert!(app.pending_changes.contains(&PendingChange::Blocked));
+
+        app.data.input.set("/block");
+        app.send_input(0).unwrap();
+        assert_eq!(app.command_error.as_deref(), Some("already blocked"));
+
+        app.data.input.set("/unblock");
+        app.send_input(0).unwrap();
+        assert_eq!(app.command_error, None);
+        assert!(app.data.blocked.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_group_from_unknown_sender_is_message_request() {
+        let signal_manager = SignalManagerMock::new();
+        let resolved_group = signal_manager.resolved_group.clone();
+        let mut app = test_app_with(signal_manager);
+        let known = app.data.channels.items[0].user_id().unwrap();
+        let stranger = Uuid::new_v4();
+
+        for (master_key, sender, is_message_request) in [
+            ([1; 32], Some(stranger), true),
+            ([2; 32], Some(known), false),
+            ([3; 32], None, false),
+        ] {
+            *resolved_group.borrow_mut() = Some((
+                "Paper Street".to_string(),
+                GroupData {
+                    master_key_bytes: master_key,
+                    members: vec![app.user_id, stranger, known],
+                    revision: 1,
+                },
+            ));
+            let channel_idx = app
+                .ensure_group_channel_exists(master_key, 1, None, sender, 10)
+                .await
+                .unwrap();
+            assert_eq!(
+                app.data.channels.items[channel_idx].is_message_request,
+                is_message_request
+            );
+        }
+    }
+
+    #[test]
+    fn test_message_request_commands() {
+        let (mut app, sent_messages) = test_app();
+        let channel_id = app.data.channels.items[0].id;
+        app.data.channels.items[0].is_message_request = true;
+
+        // no replies to message requests
+        app.data.input.set("Who are you?");
+        app.send_input(0).unwrap();
+        assert_eq!(app.data.input.data, "Who are you?");
+        assert_eq!(
+            app.command_error.as_deref(),
+            Some("accept the message request first: /accept")
+        );
+        assert!(sent_messages.borrow().is_empty());
+
+        app.data.input.set("/accept");
+        app.send_input(0).unwrap();
+        assert!(!app.data.channels.items[0].is_message_request);
+        assert!(app
+            .data
+            .outbox
+            .items()
+            .any(|queued| queued.item == OutboxItem::ProfileKey { channel_id }));
+        app.data.input.set("/accept");
+        app.send_input(0).unwrap();
+        assert_eq!(app.command_error.as_deref(), Some("not a message request"));
+
+        app.data.input.set("/delete");
+        app.send_input(0).unwrap();
+        assert!(app.data.channels.items.is_empty());
+        assert_eq!(app.data.channels.state.selected(), None);
+        assert!(app
+            .pending_changes
+            .contains(&PendingChange::DeletedChannel(channel_id)));
+    }
+
+    #[test]
+    fn test_is_known_sender() {
+        let (mut app, _) = test_app();
+        let marla = Uuid::new_v4();
+        assert!(!app.is_known_sender(marla));
+        app.data.channels.items[0]
+            .group_data
+            .as_mut()
+            .unwrap()
+            .members
+            .push(marla);
+        assert!(app.is_known_sender(marla));
+    }
+
+    #[test]
+    fn test_sync_blocked() {
+        let (mut app, _) = test_app();
+        let contact_id = Uuid::new_v4();
+        let local_id = ChannelId::User(Uuid::new_v4());
+        app.data.blocked.insert(local_id);
+
+        app.sync_blocked(
+            vec![contact_id.to_string(), "+0000000001".to_string()],
+            vec![vec![42; 32], vec![1, 2, 3]],
+        );
+        let mut expected = BTreeSet::new();
+        expected.insert(local_id);
+        expected.insert(ChannelId::User(contact_id));
+        expected.insert(ChannelId::Group([42; 32]));
+        assert_eq!(app.data.blocked, expected);
+        assert!(app.pending_changes.contains(&PendingChange::Blocked));
+
+        // a contact unblocked by us stays unblocked, until it changes on the primary device
+        app.data.blocked.remove(&ChannelId::User(contact_id));
+        app.sync_blocked(vec![contact_id.to_string()], vec![vec![42; 32]]);
+        assert!(!app.data.blocked.contains(&ChannelId::User(contact_id)));
+
+        // unblocked on the primary device
+        app.sync_blocked(vec![contact_id.to_string()], vec![]);
+        assert!(!app.data.blocked.contains(&ChannelId::Group([42; 32])));
+        assert!(app.data.blocked.contains(&local_id));
+    }
+
+    #[test]
+    fn test_expire_messages() {
+        let (mut app, _) = test_app();
+        let attachments_dir = tempfile::tempdir().unwrap();
+        app.attachments_dir = Some(attachments_dir.path().to_path_buf());
+        let filename = attachments_dir.path().join("signal-burn.jpg");
+        std::fs::write(&filename, b"image").unwrap();
+
+        let channel = &mut app.data.channels.items[0];
+        channel.messages.items.push(Message {
+            expires_in: Some(60),
+            ..Message::new(
+                Uuid::new_v4(),
+                Some("Burn after reading".to_string()),
+                1000,
+                vec![Attachment {
+                    id: "burn".to_string(),
+                    content_type: "image/jpeg".to_string(),
+                    filename: filename.clone(),
+                    size: 5,
+                }],
+            )
+        });
+        channel.messages.state.select(Some(0));
+
+        // the timer of an unread message has not started yet
+        app.expire_messages(u64::MAX);
+        assert_eq!(app.data.channels.items[0].messages.items.len(), 2);
+
+        assert!(app.reset_unread_messages());
+        let started_at = app.data.channels.items[0].messages.items[1]
+            .expire_started_at
+            .unwrap();
+
+        app.expire_messages(started_at + 59999);
+        assert_eq!(app.data.channels.items[0].messages.items.len(), 2);
+        assert!(!app
+            .pending_changes
+            .contains(&PendingChange::ExpiredMessages));
+
+        app.expire_messages(started_at + 60000);
+        let channel = &app.data.channels.items[0];
+        assert_eq!(channel.messages.items.len(), 1);
+        assert_eq!(channel.messages.state.selected(), None);
+        assert!(app
+            .pending_changes
+            .contains(&PendingChange::ExpiredMessages));
+        assert!(!filename.exists());
+    }
+
+    #[test]
+    fn test_incoming_expire_timer_updates_channel() {
+        let (mut app, _) = test_app();
+
+        app.sync_expire_timer(0, Some(3600));
+        assert_eq!(app.data.channels.items[0].expire_timer, Some(3600));
+        let channel_id = app.data.channels.items[0].id;
+        assert!(app
+            .pending_changes
+            .contains(&PendingChange::Channel(channel_id)));
+
+        app.sync_expire_timer(0, None);
+        assert_eq!(app.data.channels.items[0].expire_timer, None);
+    }
+
+    #[test]
+    fn test_handle_delete_by_author_only() {
+        let (mut app, _) = test_app();
+        let channel_id = app.data.channels.items[0].id;
+
+        assert_eq!(app.handle_delete(channel_id, Uuid::new_v4(), 0), None);
+        assert!(!app.data.channels.items[0].messages.items[0].is_deleted);
+
+        assert_eq!(app.handle_delete(channel_id, app.user_id, 0), Some(()));
+        let message = &app.data.channels.items[0].messages.items[0];
+        assert!(message.is_deleted);
+        assert_eq!(message.message, None);
+    }
+
+    #[test]
+    fn test_handle_delete_of_unloaded_message() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut app, _) = test_app();
+        app.storage = Box::new(SqliteStorage::open(dir.path().join("gurk.sqlite")).unwrap());
+        let channel = &mut app.data.channels.items[0];
+        let channel_id = channel.id;
+        let message = channel.messages.items.remove(0);
+        app.storage.append_message(channel, &message).unwrap();
+
+        assert_eq!(app.handle_delete(channel_id, Uuid::new_v4(), 0), None);
+        assert_eq!(app.handle_delete(channel_id, app.user_id, 0), Some(()));
+        app.flush().unwrap();
+        let stored = app.storage.load_message(channel_id, 0).unwrap().unwrap();
+        assert!(stored.is_deleted);
+        assert_eq!(stored.message, None);
+    }
+
+    #[test]
+    fn test_reset_unsent_messages() {
+        let (mut app, _) = test_app();
+        app.set_online(false);
+        app.get_input().put_char('a');
+        app.send_input(0).unwrap();
+        let channel = &mut app.data.channels.items[0];
+        // in flight on quit
+        channel.messages.items[1].receipt = Receipt::Pending;
+        // lost from the outbox
+        let mut message = Message::new(app.user_id, Some("b".to_string()), 1, vec![]);
+        message.receipt = Receipt::Queued;
+        channel.messages.items.push(message);
+
+        app.reset_unsent_messages();
+        let messages = &app.data.channels.items[0].messages.items;
+        assert_eq!(messages[1].receipt, Receipt::Queued);
+        assert_eq!(messages[2].receipt, Receipt::Failed);
+    }
 }
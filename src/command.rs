@@ -0,0 +1,281 @@
+//! Commands entered in the input box
+//!
+//! An input starting with `/` is a command instead of a message. A message starting with `/` is
+//! written with a leading `//`.
+
+use anyhow::{anyhow, bail};
+
+/// Prefix of the input box content, which runs a command instead of sending a message
+pub const COMMAND_PREFIX: char = '/';
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Sets the expiration timer of disappearing messages in seconds; `None` turns it off
+    ExpireTimer(Option<u32>),
+    /// Creates a group or changes the group of the channel
+    Group(GroupCommand),
+    /// Blocks the contact or group of the channel
+    Block,
+    /// Unblocks the contact or group of the channel
+    Unblock,
+    /// Accepts the message request of the channel
+    Accept,
+    /// Deletes the channel with its history
+    DeleteChannel,
+    /// Opens the channel with the contact with the name or phone number, creating it if needed
+    Chat(String),
+    /// Requests the contacts from our primary device
+    SyncContacts,
+    /// Marks the safety number with the contact of the channel as verified
+    Verify,
+}
+
+/// Creation or change of a group; members are referred to by their names
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GroupCommand {
+    /// Creates a group with the name and the members
+    Create(String, Vec<String>),
+    Rename(String),
+    Describe(String),
+    Add(String),
+    Remove(String),
+    Leave,
+}
+
+/// Parsed content of the input box
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input<'a> {
+    Message(&'a str),
+    Command(Command),
+}
+
+/// Parses the input box content as message or command.
+///
+/// Fails if the input is an unknown or invalid command.
+pub fn parse(input: &str) -> anyhow::Result<Input<'_>> {
+    let command = match input.strip_prefix(COMMAND_PREFIX) {
+        Some(command) if !command.starts_with(COMMAND_PREFIX) => command,
+        Some(escaped) => return Ok(Input::Message(escaped)),
+        None => return Ok(Input::Message(input)),
+    };
+    let (name, rest) = command
+        .trim_start()
+        .split_once(char::is_whitespace)
+        .unwrap_or((command.trim_start(), ""));
+    let mut args = rest.split_whitespace();
+    let command = match name {
+        "timer" => {
+            let timer = args
+                .next()
+                .ok_or_else(|| anyhow!("usage: /timer <off|30s|5m|1h|1d|1w>"))?;
+            Command::ExpireTimer(parse_timer(timer)?)
+        }
+        // the rest of the input is a single argument, e.g. a name with spaces
+        "group" => return Ok(Input::Command(Command::Group(parse_group(rest)?))),
+        "block" => Command::Block,
+        "unblock" => Command::Unblock,
+        "accept" => Command::Accept,
+        "delete" => Command::DeleteChannel,
+        // the rest of the input is a single argument, e.g. a name with spaces
+        "chat" => match rest.trim() {
+            "" => bail!("usage: /chat <NAME|PHONE NUMBER>"),
+            contact => return Ok(Input::Command(Command::Chat(contact.to_string()))),
+        },
+        "contacts" => Command::SyncContacts,
+        "verify" => Command::Verify,
+        "" => bail!("missing command"),
+        name => bail!("unknown command: /{}", name),
+    };
+    if args.next().is_some() {
+        bail!("too many arguments");
+    }
+    Ok(Input::Command(command))
+}
+
+const GROUP_USAGE: &str = "usage: /group <create NAME @MEMBER...|rename NAME|description TEXT|\
+    add @NAME|remove @NAME|leave>";
+
+fn parse_group(args: &str) -> anyhow::Result<GroupCommand> {
+    let (subcommand, arg) = args
+        .trim()
+        .split_once(char::is_whitespace)
+        .unwrap_or((args.trim(), ""));
+    let arg = arg.trim();
+    let command = match subcommand {
+        "create" => {
+            // names may contain spaces, so the members are separated by their `@`
+            let mut parts = arg.split('@').map(str::trim);
+            let name = parts.next().filter(|name| !name.is_empty());
+            let name = name.ok_or_else(|| anyhow!(GROUP_USAGE))?;
+            let members = parts
+                .filter(|member| !member.is_empty())
+                .map(str::to_string)
+                .collect();
+            GroupCommand::Create(name.to_string(), members)
+        }
+        "rename" if !arg.is_empty() => GroupCommand::Rename(arg.to_string()),
+        "description" => GroupCommand::Describe(arg.to_string()),
+        "add" if !arg.is_empty() => GroupCommand::Add(member_name(arg)),
+        "remove" if !arg.is_empty() => GroupCommand::Remove(member_name(arg)),
+        "leave" if arg.is_empty() => GroupCommand::Leave,
+        _ => bail!(GROUP_USAGE),
+    };
+    Ok(command)
+}
+
+/// Name of a member, which may be written as mention.
+fn member_name(arg: &str) -> String {
+    arg.strip_prefix('@').unwrap_or(arg).to_string()
+}
+
+const UNITS: &[(char, u32, &str)] = &[
+    ('w', 7 * 24 * 60 * 60, "week"),
+    ('d', 24 * 60 * 60, "day"),
+    ('h', 60 * 60, "hour"),
+    ('m', 60, "minute"),
+    ('s', 1, "second"),
+];
+
+/// Parses an expiration timer like `30s`, `5m`, `1h`, `1d` or `1w` into seconds; `off` is `None`.
+fn parse_timer(timer: &str) -> anyhow::Result<Option<u32>> {
+    if timer == "off" {
+        return Ok(None);
+    }
+    let invalid = || anyhow!("invalid timer: {}", timer);
+    let unit = timer.chars().last().ok_or_else(invalid)?;
+    let (_, factor, _) = UNITS
+        .iter()
+        .find(|(suffix, _, _)| *suffix == unit)
+        .ok_or_else(invalid)?;
+    let value: u32 = timer[..timer.len() - 1].parse().map_err(|_| invalid())?;
+    match value.checked_mul(*factor) {
+        Some(0) => Ok(None),
+        Some(seconds) => Ok(Some(seconds)),
+        None => Err(invalid()),
+    }
+}
+
+/// Formats the expiration timer in the largest unit which divides it, e.g. `2 hours`.
+pub fn format_timer(seconds: u32) -> String {
+    let (factor, name) = UNITS
+        .iter()
+        .find(|(_, factor, _)| seconds % factor == 0)
+        .map(|(_, factor, name)| (*factor, *name))
+        .unwrap_or((1, "second"));
+    let value = seconds / factor;
+    if value == 1 {
+        format!("1 {}", name)
+    } else {
+        format!("{} {}s", value, name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_message() {
+        assert_eq!(parse("hello").unwrap(), Input::Message("hello"));
+        assert_eq!(parse("//shrug").unwrap(), Input::Message("/shrug"));
+    }
+
+    #[test]
+    fn test_parse_timer_command() {
+        assert_eq!(
+            parse("/timer 5m").unwrap(),
+            Input::Command(Command::ExpireTimer(Some(300)))
+        );
+        assert_eq!(
+            parse("/timer 1w").unwrap(),
+            Input::Command(Command::ExpireTimer(Some(604800)))
+        );
+        assert_eq!(
+            parse("/timer off").unwrap(),
+            Input::Command(Command::ExpireTimer(None))
+        );
+        assert!(parse("/timer").is_err());
+        assert!(parse("/timer 5").is_err());
+        assert!(parse("/timer 5m 1h").is_err());
+        assert!(parse("/unknown").is_err());
+    }
+
+    #[test]
+    fn test_parse_group_command() {
+        assert_eq!(
+            parse("/group create Project Mayhem @Marla Singer @Robert Paulson").unwrap(),
+            Input::Command(Command::Group(GroupCommand::Create(
+                "Project Mayhem".to_string(),
+                vec!["Marla Singer".to_string(), "Robert Paulson".to_string()]
+            )))
+        );
+        assert_eq!(
+            parse("/group rename Paper Street Soap Company").unwrap(),
+            Input::Command(Command::Group(GroupCommand::Rename(
+                "Paper Street Soap Company".to_string()
+            )))
+        );
+        assert_eq!(
+            parse("/group add @Marla Singer").unwrap(),
+            Input::Command(Command::Group(GroupCommand::Add(
+                "Marla Singer".to_string()
+            )))
+        );
+        assert_eq!(
+            parse("/group description").unwrap(),
+            Input::Command(Command::Group(GroupCommand::Describe(String::new())))
+        );
+        assert_eq!(
+            parse("/group leave").unwrap(),
+            Input::Command(Command::Group(GroupCommand::Leave))
+        );
+        assert!(parse("/group").is_err());
+        assert!(parse("/group create @Marla Singer").is_err());
+        assert!(parse("/group rename").is_err());
+        assert!(parse("/group leave now").is_err());
+    }
+
+    #[test]
+    fn test_parse_block_command() {
+        assert_eq!(parse("/block").unwrap(), Input::Command(Command::Block));
+        assert_eq!(parse("/unblock").unwrap(), Input::Command(Command::Unblock));
+        assert!(parse("/block Marla").is_err());
+    }
+
+    #[test]
+    fn test_parse_message_request_commands() {
+        assert_eq!(parse("/accept").unwrap(), Input::Command(Command::Accept));
+        assert_eq!(
+            parse("/delete").unwrap(),
+            Input::Command(Command::DeleteChannel)
+        );
+    }
+
+    #[test]
+    fn test_parse_contact_commands() {
+        assert_eq!(
+            parse("/chat Marla Singer").unwrap(),
+            Input::Command(Command::Chat("Marla Singer".to_string()))
+        );
+        assert_eq!(
+            parse("/contacts").unwrap(),
+            Input::Command(Command::SyncContacts)
+        );
+        assert!(parse("/chat").is_err());
+    }
+
+    #[test]
+    fn test_parse_verify_command() {
+        assert_eq!(parse("/verify").unwrap(), Input::Command(Command::Verify));
+        assert!(parse("/verify Marla").is_err());
+    }
+
+    #[test]
+    fn test_format_timer() {
+        assert_eq!(format_timer(30), "30 seconds");
+        assert_eq!(format_timer(60), "1 minute");
+        assert_eq!(format_timer(2 * 60 * 60), "2 hours");
+        assert_eq!(format_timer(7 * 24 * 60 * 60), "1 week");
+        assert_eq!(format_timer(90), "90 seconds");
+    }
+}
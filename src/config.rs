@@ -9,6 +9,27 @@ pub struct Config {
     /// Path to the JSON file (incl. filename) storing channels and messages.
     #[serde(default = "default_data_path")]
     pub data_path: PathBuf,
+    /// Number of rotated backups of the JSON data file.
+    #[serde(default = "default_data_backups")]
+    pub data_backups: usize,
+    /// Storage backend for channels and messages.
+    #[serde(default)]
+    pub storage: StorageBackend,
+    /// Path to the SQLite database (incl. filename) storing channels and messages.
+    ///
+    /// Only used with the `sqlite` storage backend.
+    #[serde(default = "default_sqlite_path")]
+    pub sqlite_path: PathBuf,
+    /// Whether to encrypt messages, names and phone numbers in the storage.
+    ///
+    /// The encryption key is derived from a passphrase which is asked for at startup.
+    #[serde(default)]
+    pub encrypt_storage: bool,
+    /// Command printing the storage passphrase to stdout, e.g. `pass show gurk`.
+    ///
+    /// If not set, the passphrase is asked for at startup.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub passphrase_command: Option<String>,
     /// Path to the Signal database containing the linked device data.
     #[serde(default = "default_signal_db_path")]
     pub signal_db_path: PathBuf,
@@ -19,6 +40,24 @@ pub struct Config {
     pub user: User,
 }
 
+/// Storage backend for channels and messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// Single JSON file at `data_path`.
+    Json,
+    /// SQLite database at `sqlite_path`.
+    ///
+    /// On the first start, the existing JSON file at `data_path` is migrated into the database.
+    Sqlite,
+}
+
+impl Default for StorageBackend {
+    fn default() -> Self {
+        Self::Json
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct User {
     /// Name to be shown in the application
@@ -33,6 +72,11 @@ impl Config {
         Config {
             user,
             data_path: default_data_path(),
+            data_backups: default_data_backups(),
+            storage: StorageBackend::default(),
+            sqlite_path: default_sqlite_path(),
+            encrypt_storage: false,
+            passphrase_command: None,
             signal_db_path: default_signal_db_path(),
             first_name_only: false,
         }
@@ -149,6 +193,14 @@ fn default_data_path() -> PathBuf {
     default_data_dir().join("gurk.data.json")
 }
 
+fn default_data_backups() -> usize {
+    3
+}
+
+fn default_sqlite_path() -> PathBuf {
+    default_data_dir().join("gurk.sqlite")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,556 @@
+//! Export of the channel history to Markdown, HTML and JSON Lines
+
+use crate::app::{name_by_id, AppData, Channel, Message, Receipt};
+use crate::storage::{Storage, MESSAGES_PAGE_SIZE};
+use crate::util::utc_timestamp_msec_to_local;
+
+use anyhow::{anyhow, bail};
+use chrono::{Local, NaiveDate, TimeZone as _, Utc};
+use serde_json::json;
+use structopt::StructOpt;
+use uuid::Uuid;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[derive(Debug, StructOpt)]
+pub struct ExportArgs {
+    /// Output format: markdown, html or jsonl
+    #[structopt(short, long, default_value = "markdown")]
+    format: ExportFormat,
+    /// Output file; if not given, the export is written to stdout
+    #[structopt(short, long)]
+    output: Option<PathBuf>,
+    /// Exports only channels whose name contains the given text (case-insensitive)
+    #[structopt(short, long)]
+    channel: Option<String>,
+    /// Exports only messages arrived on or after the given date (YYYY-MM-DD)
+    #[structopt(long)]
+    since: Option<NaiveDate>,
+    /// Exports only messages arrived on or before the given date (YYYY-MM-DD)
+    #[structopt(long)]
+    until: Option<NaiveDate>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Markdown,
+    Html,
+    JsonLines,
+}
+
+impl FromStr for ExportFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "markdown" | "md" => Ok(Self::Markdown),
+            "html" => Ok(Self::Html),
+            "jsonl" | "json-lines" => Ok(Self::JsonLines),
+            _ => bail!("unknown export format: {}", s),
+        }
+    }
+}
+
+/// Selects the channels and messages to export.
+#[derive(Debug, Default)]
+pub struct ExportFilter {
+    /// Case-insensitive part of the channel name
+    pub channel: Option<String>,
+    /// Inclusive lower bound of the arrival timestamp in msec
+    pub since: Option<u64>,
+    /// Exclusive upper bound of the arrival timestamp in msec
+    pub until: Option<u64>,
+}
+
+impl ExportFilter {
+    fn matches_channel(&self, channel: &Channel) -> bool {
+        self.channel.as_ref().map_or(true, |name| {
+            channel.name.to_lowercase().contains(&name.to_lowercase())
+        })
+    }
+
+    fn matches_message(&self, message: &Message) -> bool {
+        self.since.map_or(true, |since| since <= message.arrived_at)
+            && self.until.map_or(true, |until| message.arrived_at < until)
+    }
+}
+
+/// Runs the `export` subcommand.
+pub fn run(storage: &dyn Storage, user_name: String, args: ExportArgs) -> anyhow::Result<()> {
+    let filter = ExportFilter {
+        channel: args.channel,
+        since: args.since.map(start_of_day_timestamp).transpose()?,
+        until: args
+            .until
+            .map(|date| start_of_day_timestamp(date.succ()))
+            .transpose()?,
+    };
+
+    // Our own user id is not known without the Signal database, however our name is already
+    // stored in the app data.
+    let mut data = storage.load_app_data(Uuid::nil(), user_name)?;
+    load_full_history(storage, &mut data, &filter)?;
+
+    match args.output {
+        Some(path) => {
+            let mut out = BufWriter::new(File::create(&path)?);
+            export(&data, &filter, args.format, &mut out)?;
+            out.flush()?;
+        }
+        None => {
+            let stdout = std::io::stdout();
+            export(&data, &filter, args.format, &mut stdout.lock())?;
+        }
+    }
+    Ok(())
+}
+
+fn start_of_day_timestamp(date: NaiveDate) -> anyhow::Result<u64> {
+    let dt = Local
+        .from_local_datetime(&date.and_hms(0, 0, 0))
+        .earliest()
+        .ok_or_else(|| anyhow!("invalid local date: {}", date))?;
+    Ok(dt.timestamp_millis() as u64)
+}
+
+/// Loads the messages of the exported channels which were not loaded with the app data.
+fn load_full_history(
+    storage: &dyn Storage,
+    data: &mut AppData,
+    filter: &ExportFilter,
+) -> anyhow::Result<()> {
+    for channel in &mut data.channels.items {
+        if !filter.matches_channel(channel) {
+            continue;
+        }
+        while let Some(oldest) = channel.messages.items.first() {
+            if filter
+                .since
+                .map_or(false, |since| oldest.arrived_at < since)
+            {
+                break;
+            }
+            let messages =
+                storage.load_messages(channel.id, oldest.arrived_at, MESSAGES_PAGE_SIZE)?;
+            if messages.is_empty() {
+                break;
+            }
+            channel.messages.items.splice(0..0, messages);
+        }
+    }
+    Ok(())
+}
+
+/// Writes the channels and messages selected by the filter in the given format.
+pub fn export(
+    data: &AppData,
+    filter: &ExportFilter,
+    format: ExportFormat,
+    out: &mut impl Write,
+) -> anyhow::Result<()> {
+    let channels = data
+        .channels
+        .items
+        .iter()
+        .filter(|channel| filter.matches_channel(channel));
+    match format {
+        ExportFormat::Markdown => {
+            for channel in channels {
+                write_markdown_channel(channel, filter, &data.names, out)?;
+            }
+        }
+        ExportFormat::Html => {
+            writeln!(out, "{}", HTML_HEADER)?;
+            for channel in channels {
+                write_html_channel(channel, filter, &data.names, out)?;
+            }
+            writeln!(out, "</body>\n</html>")?;
+        }
+        ExportFormat::JsonLines => {
+            for channel in channels {
+                write_json_lines_channel(channel, filter, &data.names, out)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn messages<'a>(
+    channel: &'a Channel,
+    filter: &'a ExportFilter,
+) -> impl Iterator<Item = &'a Message> + 'a {
+    channel
+        .messages
+        .items
+        .iter()
+        .filter(move |message| filter.matches_message(message))
+}
+
+/// Message text with the mentions replaced by the names of the mentioned users
+fn body(message: &Message, names: &HashMap<Uuid, String>) -> Option<String> {
+    message.body_with_mentions(|id| name_by_id(names, id))
+}
+
+fn format_time(timestamp: u64) -> String {
+    utc_timestamp_msec_to_local(timestamp)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+fn receipt_name(receipt: Receipt) -> Option<&'static str> {
+    match receipt {
+        Receipt::Nothing => None,
+        Receipt::Queued => Some("queued"),
+        Receipt::Pending => Some("pending"),
+        Receipt::Failed => Some("failed"),
+        Receipt::Sent => Some("sent"),
+        Receipt::Received => Some("received"),
+        Receipt::Delivered => Some("delivered"),
+    }
+}
+
+fn file_link(path: &Path) -> String {
+    format!("file://{}", path.display()).replace(' ', "%20")
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or_else(|| path.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn write_markdown_channel(
+    channel: &Channel,
+    filter: &ExportFilter,
+    names: &HashMap<Uuid, String>,
+    out: &mut impl Write,
+) -> anyhow::Result<()> {
+    writeln!(out, "# {}\n", channel.name)?;
+    for message in messages(channel, filter) {
+        let mut header = format!(
+            "- **{}** · {}",
+            name_by_id(names, message.from_id),
+            format_time(message.arrived_at)
+        );
+        if let Some(receipt) = receipt_name(message.receipt) {
+            header.push_str(" · ");
+            header.push_str(receipt);
+        }
+        writeln!(out, "{}", header)?;
+
+        if let Some(quote) = message.quote.as_ref() {
+            let text = body(quote, names).unwrap_or_default();
+            let name = name_by_id(names, quote.from_id);
+            for (idx, line) in text.lines().enumerate() {
+                if idx == 0 {
+                    writeln!(out, "  > {}: {}", name, line)?;
+                } else {
+                    writeln!(out, "  > {}", line)?;
+                }
+            }
+        }
+        if let Some(text) = body(message, names) {
+            for line in text.lines() {
+                writeln!(out, "  {}", line)?;
+            }
+        }
+        if let Some(sticker) = message.sticker.as_ref() {
+            writeln!(out, "  {}", sticker)?;
+        }
+        for attachment in &message.attachments {
+            writeln!(
+                out,
+                "  [{}]({})",
+                file_name(&attachment.filename),
+                file_link(&attachment.filename)
+            )?;
+        }
+        if !message.reactions.is_empty() {
+            let reactions = message
+                .reactions
+                .iter()
+                .map(|(from_id, emoji)| format!("{} {}", emoji, name_by_id(names, *from_id)))
+                .collect::<Vec<_>>()
+                .join(", ");
+            writeln!(out, "  Reactions: {}", reactions)?;
+        }
+    }
+    writeln!(out)?;
+    Ok(())
+}
+
+const HTML_HEADER: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>gurk export</title>
+<style>
+body { font-family: sans-serif; max-width: 50em; margin: auto; }
+.message { margin: 0.5em 0; }
+.meta { color: #666; font-size: 0.9em; }
+.from { font-weight: bold; color: #000; }
+blockquote { margin: 0.2em 0 0.2em 1em; padding-left: 0.5em; border-left: 2px solid #ccc; }
+.text { white-space: pre-wrap; }
+</style>
+</head>
+<body>"#;
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn write_html_channel(
+    channel: &Channel,
+    filter: &ExportFilter,
+    names: &HashMap<Uuid, String>,
+    out: &mut impl Write,
+) -> anyhow::Result<()> {
+    writeln!(out, "<section>\n<h1>{}</h1>", escape_html(&channel.name))?;
+    for message in messages(channel, filter) {
+        writeln!(out, r#"<div class="message">"#)?;
+        write!(
+            out,
+            r#"<div class="meta"><span class="from">{}</span> <time datetime="{}">{}</time>"#,
+            escape_html(name_by_id(names, message.from_id)),
+            Utc.timestamp_millis(message.arrived_at as i64).to_rfc3339(),
+            format_time(message.arrived_at)
+        )?;
+        if let Some(receipt) = receipt_name(message.receipt) {
+            write!(out, r#" <span class="receipt">{}</span>"#, receipt)?;
+        }
+        writeln!(out, "</div>")?;
+
+        if let Some(quote) = message.quote.as_ref() {
+            writeln!(
+                out,
+                r#"<blockquote><span class="from">{}</span>: <span class="text">{}</span></blockquote>"#,
+                escape_html(name_by_id(names, quote.from_id)),
+                escape_html(&body(quote, names).unwrap_or_default())
+            )?;
+        }
+        if let Some(text) = body(message, names) {
+            writeln!(out, r#"<div class="text">{}</div>"#, escape_html(&text))?;
+        }
+        if let Some(sticker) = message.sticker.as_ref() {
+            writeln!(
+                out,
+                r#"<div class="sticker">{}</div>"#,
+                escape_html(&sticker.to_string())
+            )?;
+        }
+        for attachment in &message.attachments {
+            writeln!(
+                out,
+                r#"<div class="attachment"><a href="{}">{}</a></div>"#,
+                escape_html(&file_link(&attachment.filename)),
+                escape_html(&file_name(&attachment.filename))
+            )?;
+        }
+        if !message.reactions.is_empty() {
+            let reactions = message
+                .reactions
+                .iter()
+                .map(|(from_id, emoji)| {
+                    format!(
+                        r#"<span title="{}">{}</span>"#,
+                        escape_html(name_by_id(names, *from_id)),
+                        escape_html(emoji)
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join(" ");
+            writeln!(out, r#"<div class="reactions">{}</div>"#, reactions)?;
+        }
+        writeln!(out, "</div>")?;
+    }
+    writeln!(out, "</section>")?;
+    Ok(())
+}
+
+fn write_json_lines_channel(
+    channel: &Channel,
+    filter: &ExportFilter,
+    names: &HashMap<Uuid, String>,
+    out: &mut impl Write,
+) -> anyhow::Result<()> {
+    for message in messages(channel, filter) {
+        let quote = message.quote.as_ref().map(|quote| {
+            json!({
+                "timestamp": quote.arrived_at,
+                "from_id": quote.from_id,
+                "from": name_by_id(names, quote.from_id),
+                "text": body(quote, names),
+            })
+        });
+        let attachments: Vec<_> = message
+            .attachments
+            .iter()
+            .map(|attachment| {
+                json!({
+                    "filename": file_name(&attachment.filename),
+                    "content_type": attachment.content_type,
+                    "size": attachment.size,
+                    "link": file_link(&attachment.filename),
+                })
+            })
+            .collect();
+        let reactions: Vec<_> = message
+            .reactions
+            .iter()
+            .map(|(from_id, emoji)| {
+                json!({
+                    "from_id": from_id,
+                    "from": name_by_id(names, *from_id),
+                    "emoji": emoji,
+                })
+            })
+            .collect();
+        let line = json!({
+            "channel": channel.name,
+            "timestamp": message.arrived_at,
+            "time": Utc.timestamp_millis(message.arrived_at as i64).to_rfc3339(),
+            "from_id": message.from_id,
+            "from": name_by_id(names, message.from_id),
+            "text": body(message, names),
+            "sticker": message.sticker,
+            "quote": quote,
+            "attachments": attachments,
+            "reactions": reactions,
+            "receipt": receipt_name(message.receipt),
+        });
+        serde_json::to_writer(&mut *out, &line)?;
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Shared test data with the channel with Marla Singer and the group Project Mayhem
+    fn test_app_data() -> (AppData, Uuid, Uuid) {
+        let user_id = Uuid::new_v4();
+        let data = crate::storage::test::test_app_data(user_id, "Tyler Durden");
+        let contact_id = data.channels.items[0].messages.items[0].from_id;
+        (data, user_id, contact_id)
+    }
+
+    fn export_to_string(data: &AppData, filter: &ExportFilter, format: ExportFormat) -> String {
+        let mut out = Vec::new();
+        export(data, filter, format, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_export_markdown() {
+        let (data, _, _) = test_app_data();
+        let out = export_to_string(&data, &Default::default(), ExportFormat::Markdown);
+
+        assert!(out.contains("# Marla Singer\n"));
+        assert!(out.contains("# Project Mayhem\n"));
+        assert!(out.contains("- **Tyler Durden** · "));
+        assert!(out.contains(" · delivered\n  hi\n  there\n"));
+        assert!(out.contains("  [signal-some-id.png](file:///tmp/signal-some-id.png)\n"));
+        assert!(out.contains("  Reactions: 👍 Tyler Durden\n"));
+        assert!(out.contains("  > Marla Singer: hi\n  > there\n  hello <world>\n"));
+        assert!(out.contains("  @Tyler Durden is the space monkey\n"));
+    }
+
+    #[test]
+    fn test_export_html() {
+        let (data, _, _) = test_app_data();
+        let out = export_to_string(&data, &Default::default(), ExportFormat::Html);
+
+        assert!(out.starts_with("<!DOCTYPE html>"));
+        assert!(out.trim_end().ends_with("</html>"));
+        assert!(out.contains("<h1>Marla Singer</h1>"));
+        assert!(out.contains(r#"<div class="text">hello &lt;world&gt;</div>"#));
+        assert!(out.contains(r#"<span class="receipt">delivered</span>"#));
+        assert!(out.contains(r#"<span title="Tyler Durden">👍</span>"#));
+        assert!(out.contains(r#"<a href="file:///tmp/signal-some-id.png">signal-some-id.png</a>"#));
+        assert!(out.contains("<blockquote>"));
+    }
+
+    #[test]
+    fn test_export_json_lines() -> anyhow::Result<()> {
+        let (data, user_id, contact_id) = test_app_data();
+        let out = export_to_string(&data, &Default::default(), ExportFormat::JsonLines);
+
+        let lines: Vec<serde_json::Value> = out
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?;
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0]["channel"], "Marla Singer");
+        assert_eq!(lines[0]["from_id"], contact_id.to_string());
+        assert_eq!(lines[0]["from"], "Marla Singer");
+        assert_eq!(lines[0]["text"], "hi\nthere");
+        assert_eq!(lines[0]["receipt"], "delivered");
+        assert_eq!(lines[0]["reactions"][0]["from_id"], user_id.to_string());
+        assert_eq!(lines[0]["reactions"][0]["emoji"], "👍");
+        assert_eq!(lines[0]["time"], "1970-01-01T00:00:00.001+00:00");
+        assert_eq!(
+            lines[0]["attachments"][0]["link"],
+            "file:///tmp/signal-some-id.png"
+        );
+        assert_eq!(lines[1]["quote"]["from"], "Marla Singer");
+        assert_eq!(lines[1]["receipt"], "sent");
+        assert_eq!(lines[2]["channel"], "Project Mayhem");
+        assert_eq!(lines[2]["text"], "@Tyler Durden is the space monkey");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_export_filter() {
+        let (data, _, _) = test_app_data();
+
+        let filter = ExportFilter {
+            channel: Some("marla".to_string()),
+            since: Some(2),
+            until: None,
+        };
+        let out = export_to_string(&data, &filter, ExportFormat::JsonLines);
+        assert_eq!(out.lines().count(), 1);
+        assert!(out.contains("hello <world>"));
+
+        let filter = ExportFilter {
+            channel: Some("Mayhem".to_string()),
+            ..Default::default()
+        };
+        let out = export_to_string(&data, &filter, ExportFormat::Markdown);
+        assert!(!out.contains("Marla Singer"));
+        assert!(out.contains("# Project Mayhem"));
+
+        let filter = ExportFilter {
+            until: Some(2),
+            ..Default::default()
+        };
+        let out = export_to_string(&data, &filter, ExportFormat::JsonLines);
+        assert_eq!(out.lines().count(), 1);
+        assert!(out.contains("hi\\nthere"));
+    }
+
+    #[test]
+    fn test_export_format_from_str() {
+        assert_eq!(
+            "md".parse::<ExportFormat>().unwrap(),
+            ExportFormat::Markdown
+        );
+        assert_eq!("html".parse::<ExportFormat>().unwrap(), ExportFormat::Html);
+        assert_eq!(
+            "jsonl".parse::<ExportFormat>().unwrap(),
+            ExportFormat::JsonLines
+        );
+        assert!("pdf".parse::<ExportFormat>().is_err());
+    }
+}
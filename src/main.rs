@@ -1,8 +1,12 @@
 //! Signal Messenger client for terminal
 
 mod app;
+mod command;
 mod config;
 mod cursor;
+mod export;
+mod outbox;
+mod search;
 mod shortcuts;
 mod signal;
 mod storage;
@@ -11,6 +15,7 @@ mod util;
 
 use app::{App, Event};
 
+use anyhow::Context as _;
 use crossterm::{
     event::{
         DisableMouseCapture, EnableMouseCapture, Event as CEvent, EventStream, KeyCode,
@@ -19,7 +24,7 @@ use crossterm::{
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
-use log::{error, info};
+use log::{error, info, warn};
 use structopt::StructOpt;
 use tokio_stream::StreamExt;
 use tui::{backend::CrosstermBackend, Terminal};
@@ -28,12 +33,17 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use crate::{signal::PresageManager, storage::JsonStorage};
+use crate::config::{Config, StorageBackend};
+use crate::outbox::SEND_TIMEOUT;
+use crate::signal::{PresageManager, SendFuture};
+use crate::storage::{EncryptedStorage, JsonStorage, SqliteStorage, Storage};
 
 const TARGET_FPS: u64 = 144;
 const RECEIPT_TICK_PERIOD: u64 = 144;
 const FRAME_BUDGET: Duration = Duration::from_millis(1000 / TARGET_FPS);
 const RECEIPT_BUDGET: Duration = Duration::from_millis(RECEIPT_TICK_PERIOD * 1000 / TARGET_FPS);
+/// Delay after the first change of the app data until it is persisted
+const FLUSH_DELAY: Duration = Duration::from_secs(1);
 const MESSAGE_SCROLL_BACK: bool = false;
 
 #[derive(Debug, StructOpt)]
@@ -44,6 +54,14 @@ struct Args {
     /// Relinks the device (helpful when device was unlinked)
     #[structopt(long)]
     relink: bool,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Exports the channel history to Markdown, HTML or JSON Lines
+    Export(export::ExportArgs),
 }
 
 fn init_file_logger(verbosity: u8) -> anyhow::Result<()> {
@@ -76,6 +94,13 @@ async fn main() -> anyhow::Result<()> {
     }
     log_panics::init();
 
+    if let Some(Command::Export(export_args)) = args.command {
+        let config = Config::load_installed()?
+            .context("no config found: please run gurk first to link the device")?;
+        let storage = open_storage_read_only(&config)?;
+        return export::run(storage.as_ref(), config.user.name, export_args);
+    }
+
     tokio::task::LocalSet::new()
         .run_until(run_single_threaded(args.relink))
         .await
@@ -87,13 +112,55 @@ async fn is_online() -> bool {
         .is_ok()
 }
 
+fn open_storage(config: &Config) -> anyhow::Result<Box<dyn Storage>> {
+    let json_storage = JsonStorage::new(config.data_path.clone(), config::fallback_data_path())
+        .with_backups(config.data_backups);
+    let storage: Box<dyn Storage> = match config.storage {
+        StorageBackend::Json => Box::new(json_storage),
+        StorageBackend::Sqlite => {
+            let mut storage = SqliteStorage::open(&config.sqlite_path)?;
+            storage.migrate_from_json(&json_storage)?;
+            Box::new(storage)
+        }
+    };
+    if config.encrypt_storage {
+        let key_path = EncryptedStorage::key_path(config);
+        let passphrase = storage::read_passphrase(config, !key_path.exists())?;
+        let storage = EncryptedStorage::new(storage, key_path, &passphrase)?;
+        Ok(Box::new(storage))
+    } else {
+        Ok(storage)
+    }
+}
+
+/// Opens the storage without modifying it, e.g. for exporting it.
+fn open_storage_read_only(config: &Config) -> anyhow::Result<Box<dyn Storage>> {
+    let storage: Box<dyn Storage> = match config.storage {
+        StorageBackend::Json => Box::new(
+            JsonStorage::new(config.data_path.clone(), config::fallback_data_path())
+                .with_backups(config.data_backups)
+                .read_only(),
+        ),
+        StorageBackend::Sqlite => Box::new(SqliteStorage::open_read_only(&config.sqlite_path)?),
+    };
+    if config.encrypt_storage {
+        let passphrase = storage::read_passphrase(config, false)?;
+        let key_path = EncryptedStorage::key_path(config);
+        let storage = EncryptedStorage::open_read_only(storage, key_path, &passphrase)?;
+        Ok(Box::new(storage))
+    } else {
+        Ok(storage)
+    }
+}
+
 async fn run_single_threaded(relink: bool) -> anyhow::Result<()> {
-    let (signal_manager, config) = signal::ensure_linked_device(relink).await?;
-    let storage = JsonStorage::new(config.data_path.clone(), config::fallback_data_path());
+    let (signal_manager, store, config) = signal::ensure_linked_device(relink).await?;
+    let storage = open_storage(&config)?;
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<Event>(100);
     let mut app = App::try_new(
         config,
-        Box::new(PresageManager::new(signal_manager.clone())),
-        Box::new(storage),
+        Box::new(PresageManager::new(signal_manager.clone(), store)),
+        storage,
     )?;
 
     enable_raw_mode()?;
@@ -104,19 +171,20 @@ async fn run_single_threaded(relink: bool) -> anyhow::Result<()> {
     let mut stdout = std::io::stdout();
     execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
 
-    let (tx, mut rx) = tokio::sync::mpsc::channel::<Event>(100);
     tokio::spawn({
         let tx = tx.clone();
         async move {
             let mut reader = EventStream::new().fuse();
             while let Some(event) = reader.next().await {
-                match event {
-                    Ok(CEvent::Key(key)) => tx.send(Event::Input(key)).await.unwrap(),
-                    Ok(CEvent::Resize(cols, rows)) => {
-                        tx.send(Event::Resize { cols, rows }).await.unwrap()
-                    }
-                    Ok(CEvent::Mouse(button)) => tx.send(Event::Click(button)).await.unwrap(),
-                    _ => (),
+                let event = match event {
+                    Ok(CEvent::Key(key)) => Event::Input(key),
+                    Ok(CEvent::Resize(cols, rows)) => Event::Resize { cols, rows },
+                    Ok(CEvent::Mouse(button)) => Event::Click(button),
+                    _ => continue,
+                };
+                if tx.send(event).await.is_err() {
+                    // the app is shutting down
+                    break;
                 }
             }
         }
@@ -128,14 +196,21 @@ async fn run_single_threaded(relink: bool) -> anyhow::Result<()> {
 
     let inner_tx = tx.clone();
     tokio::task::spawn_local(async move {
+        // the loop ends when the events channel is closed, i.e. the app is shutting down
         loop {
             let messages = if !is_online().await {
+                if inner_tx.send(Event::Online(false)).await.is_err() {
+                    return;
+                }
                 tokio::time::sleep(std::time::Duration::from_secs(10)).await;
                 continue;
             } else {
                 match signal_manager.receive_messages().await {
                     Ok(messages) => {
                         info!("connected and listening for incoming messages");
+                        if inner_tx.send(Event::Online(true)).await.is_err() {
+                            return;
+                        }
                         messages
                     }
                     Err(e) => {
@@ -143,10 +218,7 @@ async fn run_single_threaded(relink: bool) -> anyhow::Result<()> {
                             "failed to initialize the stream of Signal messages.\n\
                             Maybe the device was unlinked? Please try to restart with '--relink` flag.",
                         );
-                        inner_tx
-                            .send(Event::Quit(Some(e)))
-                            .await
-                            .expect("logic error: events channel closed");
+                        let _ = inner_tx.send(Event::Quit(Some(e))).await;
                         return;
                     }
                 }
@@ -154,12 +226,14 @@ async fn run_single_threaded(relink: bool) -> anyhow::Result<()> {
 
             tokio::pin!(messages);
             while let Some(message) = messages.next().await {
-                inner_tx
-                    .send(Event::Message(message))
-                    .await
-                    .expect("logic error: events channel closed")
+                if inner_tx.send(Event::Message(message)).await.is_err() {
+                    return;
+                }
+            }
+            info!("messages channel disconnected. trying to reconnect.");
+            if inner_tx.send(Event::Online(false)).await.is_err() {
+                return;
             }
-            info!("messages channel disconnected. trying to reconnect.")
         }
     });
 
@@ -169,16 +243,41 @@ async fn run_single_threaded(relink: bool) -> anyhow::Result<()> {
     let mut last_render_at = Instant::now();
     let is_render_spawned = Arc::new(AtomicBool::new(false));
 
+    // Persist changes of the app data in bursts
+    let (flush_tx, mut flush_rx) = tokio::sync::mpsc::unbounded_channel();
+    app.set_flush_notifier(flush_tx);
+    let flush_event_tx = tx.clone();
+    tokio::spawn(async move {
+        // the app notifies only on the first change after a flush
+        while flush_rx.recv().await.is_some() {
+            tokio::time::sleep(FLUSH_DELAY).await;
+            if flush_event_tx.send(Event::Flush).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    // Send our typing indicators one after the other, s.t. a stop never overtakes its start
+    let (typing_tx, mut typing_rx) = tokio::sync::mpsc::unbounded_channel::<SendFuture>();
+    tokio::task::spawn_local(async move {
+        while let Some(send) = typing_rx.recv().await {
+            match tokio::time::timeout(SEND_TIMEOUT, send).await {
+                Ok(Ok(())) => (),
+                Ok(Err(e)) => warn!("failed to send typing: {}", e),
+                Err(_) => warn!("failed to send typing: timed out"),
+            }
+        }
+    });
+
     let tick_tx = tx.clone();
-    // Tick to trigger receipt sending
+    // Tick to trigger receipt sending and expiring messages
     tokio::spawn(async move {
         let mut interval = tokio::time::interval(RECEIPT_BUDGET);
         loop {
             interval.tick().await;
-            tick_tx
-                .send(Event::Tick)
-                .await
-                .expect("Cannot tick: events channel closed.");
+            if tick_tx.send(Event::Tick).await.is_err() {
+                break;
+            }
         }
     });
 
@@ -195,9 +294,8 @@ async fn run_single_threaded(relink: bool) -> anyhow::Result<()> {
                     // Redraw message is needed to make sure that we render the skipped frame
                     // if it was the last frame in the rendering budget window.
                     tokio::time::sleep(budget).await;
-                    tx.send(Event::Redraw)
-                        .await
-                        .expect("logic error: events channel closed");
+                    // on shutdown, there is nothing to redraw anymore
+                    let _ = tx.send(Event::Redraw).await;
                     is_render_spawned.store(false, Ordering::Relaxed);
                 });
             }
@@ -208,7 +306,16 @@ async fn run_single_threaded(relink: bool) -> anyhow::Result<()> {
 
         match rx.recv().await {
             Some(Event::Tick) => {
-                let _ = app.step_receipts();
+                app.step_receipts();
+                // retries the outbox after a failed attempt
+                app.process_outbox();
+                app.expire_messages(util::utc_now_timestamp_msec());
+                app.expire_typing(Instant::now());
+            }
+            Some(Event::Flush) => {
+                if let Err(e) = app.flush() {
+                    error!("failed to save app data: {}", e);
+                }
             }
             Some(Event::Click(event)) => match event.kind {
                 MouseEventKind::Down(MouseButton::Left) => {
@@ -220,9 +327,7 @@ async fn run_single_threaded(relink: bool) -> anyhow::Result<()> {
                             .filter(|&idx| idx < app.data.channels.items.len())
                     {
                         app.data.channels.state.select(Some(channel_idx));
-                        if app.reset_unread_messages() {
-                            app.save().unwrap();
-                        }
+                        app.reset_unread_messages();
                     }
                 }
                 MouseEventKind::ScrollUp => {
@@ -250,6 +355,7 @@ async fn run_single_threaded(relink: bool) -> anyhow::Result<()> {
                     // Toggle help panel
                     app.toggle_help();
                 }
+                KeyCode::F(2u8) => app.toggle_safety_number().await,
                 KeyCode::Char('c') if event.modifiers.contains(KeyModifiers::CONTROL) => {
                     break;
                 }
@@ -332,6 +438,8 @@ async fn run_single_threaded(relink: bool) -> anyhow::Result<()> {
                     error!("failed on incoming message: {}", e);
                 }
             }
+            Some(Event::SendResult { id, result }) => app.handle_send_result(id, result),
+            Some(Event::Online(is_online)) => app.set_online(is_online),
             Some(Event::Resize { .. }) | Some(Event::Redraw) => {
                 // will just redraw the app
             }
@@ -345,6 +453,21 @@ async fn run_single_threaded(relink: bool) -> anyhow::Result<()> {
                 break;
             }
         }
+        app.update_typing(Instant::now());
+        for send in app.take_outgoing_typing() {
+            // the sending task ends only with the app
+            let _ = typing_tx.send(send);
+        }
+        if let Some((id, send)) = app.take_outgoing() {
+            let tx = tx.clone();
+            tokio::task::spawn_local(async move {
+                let result = tokio::time::timeout(SEND_TIMEOUT, send)
+                    .await
+                    .unwrap_or_else(|_| Err(anyhow::anyhow!("timed out")));
+                // on shutdown, the item stays in the outbox and is sent on the next start
+                let _ = tx.send(Event::SendResult { id, result }).await;
+            });
+        }
         if app.should_quit {
             break;
         }
@@ -358,5 +481,10 @@ async fn run_single_threaded(relink: bool) -> anyhow::Result<()> {
     .unwrap();
     terminal.show_cursor().unwrap();
 
-    res
+    // final flush of the pending changes
+    let flush_res = app.flush().context("failed to save app data");
+    match (res, flush_res) {
+        (Err(e), Err(flush_e)) => Err(anyhow::anyhow!("{:#}\n{:#}", e, flush_e)),
+        (res, flush_res) => res.and(flush_res),
+    }
 }
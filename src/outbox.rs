@@ -0,0 +1,381 @@
+//! Persistent queue of outgoing messages, receipts and other updates
+//!
+//! Everything sent to Signal is queued in the outbox first, which is stored with the app data.
+//! Items are sent one at a time in order. When sending an item fails, the item is retried after a
+//! delay starting at `RETRY_DELAY` and doubling with each attempt, until it failed
+//! `MAX_SEND_ATTEMPTS` times; in the meantime, the items after it are sent.
+
+use crate::app::{ChannelId, Receipt};
+use crate::signal::GroupChange;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Number of failed attempts after which an item is given up
+pub const MAX_SEND_ATTEMPTS: u32 = 8;
+
+/// Delay after the first failed attempt until the item is retried
+pub const RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Maximum delay between two attempts to send an item
+pub const MAX_RETRY_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Time after which sending an item is aborted and counted as a failed attempt
+pub const SEND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Stored item of the outbox
+///
+/// Changing the stored fields requires a migration step in `storage::migration`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OutboxItem {
+    /// Own message stored in the channel, identified by its arrival timestamp
+    Message {
+        channel_id: ChannelId,
+        arrived_at: u64,
+    },
+    Reaction {
+        channel_id: ChannelId,
+        target_author: Uuid,
+        target_arrived_at: u64,
+        emoji: String,
+        remove: bool,
+    },
+    /// Deletion of the own message stored in the channel for everyone
+    Delete {
+        channel_id: ChannelId,
+        target_arrived_at: u64,
+    },
+    /// Expiration timer of disappearing messages in seconds; `None` turns it off
+    ExpireTimer {
+        channel_id: ChannelId,
+        timer: Option<u32>,
+    },
+    /// Creation or change of the group of the channel, which is applied locally after it was
+    /// sent
+    GroupChange {
+        channel_id: ChannelId,
+        change: GroupChange,
+    },
+    /// Our profile key shared with the contact or group of the channel, e.g. after accepting its
+    /// message request
+    ProfileKey { channel_id: ChannelId },
+    /// Request of the contacts of our primary device
+    ContactsSyncRequest,
+    Receipt {
+        sender_id: Uuid,
+        timestamps: Vec<u64>,
+        receipt: Receipt,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueuedItem {
+    /// Id which identifies the item in the outbox; ids are increasing in the queue order
+    pub id: u64,
+    pub item: OutboxItem,
+    /// Number of failed attempts to send the item
+    pub attempts: u32,
+}
+
+/// Result of an attempt to send an item
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SendOutcome {
+    /// The item was sent and removed from the outbox
+    Sent(OutboxItem),
+    /// The item failed to send, and stays queued to be retried
+    Retry(OutboxItem),
+    /// The item failed to send `MAX_SEND_ATTEMPTS` times, and was removed from the outbox
+    Failed(OutboxItem),
+}
+
+/// Queue of outgoing items
+///
+/// Outboxes are equal if their stored items are equal.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct Outbox {
+    items: VecDeque<QueuedItem>,
+    /// Id of the item which is being sent
+    #[serde(skip)]
+    in_flight: Option<u64>,
+    /// Items which failed to send by their id, with the time from which they are retried
+    #[serde(skip)]
+    retry_at: HashMap<u64, Instant>,
+    #[serde(skip)]
+    next_id: u64,
+}
+
+impl PartialEq for Outbox {
+    fn eq(&self, other: &Self) -> bool {
+        self.items == other.items
+    }
+}
+
+impl Eq for Outbox {}
+
+impl Outbox {
+    pub fn with_items(items: Vec<QueuedItem>) -> Self {
+        Self {
+            items: items.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn items(&self) -> impl Iterator<Item = &QueuedItem> {
+        self.items.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    /// Appends the item to the queue and returns its id.
+    pub fn push(&mut self, item: OutboxItem) -> u64 {
+        // loaded items have no counter, so continue after the last one
+        let id = self
+            .items
+            .back()
+            .map_or(self.next_id, |queued| self.next_id.max(queued.id + 1));
+        self.next_id = id + 1;
+        self.items.push_back(QueuedItem {
+            id,
+            item,
+            attempts: 0,
+        });
+        id
+    }
+
+    /// Returns the first item which is due to be sent and marks it as in flight.
+    ///
+    /// Returns `None`, if no item is due, or an item is in flight already.
+    pub fn start_next(&mut self, now: Instant) -> Option<QueuedItem> {
+        if self.in_flight.is_some() {
+            return None;
+        }
+        let queued = self
+            .items
+            .iter()
+            .find(|queued| self.retry_at.get(&queued.id).map_or(true, |&at| at <= now))?
+            .clone();
+        self.in_flight = Some(queued.id);
+        Some(queued)
+    }
+
+    /// Records the result of sending the in-flight item with the given id.
+    ///
+    /// Returns `None`, if the item was removed from the outbox in the meantime.
+    pub fn finish(&mut self, id: u64, is_sent: bool, now: Instant) -> Option<SendOutcome> {
+        if self.in_flight != Some(id) {
+            return None;
+        }
+        self.in_flight = None;
+        let idx = self.items.iter().position(|queued| queued.id == id)?;
+        if is_sent {
+            self.retry_at.remove(&id);
+            return self
+                .items
+                .remove(idx)
+                .map(|queued| SendOutcome::Sent(queued.item));
+        }
+
+        let queued = &mut self.items[idx];
+        queued.attempts += 1;
+        if queued.attempts >= MAX_SEND_ATTEMPTS {
+            self.retry_at.remove(&id);
+            self.items
+                .remove(idx)
+                .map(|queued| SendOutcome::Failed(queued.item))
+        } else {
+            self.retry_at.insert(id, now + retry_delay(queued.attempts));
+            Some(SendOutcome::Retry(queued.item.clone()))
+        }
+    }
+
+    /// Stops sending the in-flight item with the given id without counting a failed attempt,
+    /// e.g. because we went offline; the item is sent again right away.
+    ///
+    /// Returns the item, if it is still queued.
+    pub fn interrupt(&mut self, id: u64) -> Option<OutboxItem> {
+        if self.in_flight != Some(id) {
+            return None;
+        }
+        self.in_flight = None;
+        self.items
+            .iter()
+            .find(|queued| queued.id == id)
+            .map(|queued| queued.item.clone())
+    }
+
+    /// Removes the in-flight item with the given id without sending it, e.g. because its target
+    /// does not exist anymore.
+    pub fn cancel(&mut self, id: u64) {
+        if self.in_flight == Some(id) {
+            self.in_flight = None;
+        }
+        self.retry_at.remove(&id);
+        self.items.retain(|queued| queued.id != id);
+    }
+
+    /// Removes the queued message, and returns whether it was queued.
+    ///
+    /// If the message is in flight, the outbox stays blocked until the result of sending it is
+    /// known, since it might be sent anyway.
+    pub fn remove_message(&mut self, channel_id: ChannelId, arrived_at: u64) -> bool {
+        let item = OutboxItem::Message {
+            channel_id,
+            arrived_at,
+        };
+        let len = self.items.len();
+        let retry_at = &mut self.retry_at;
+        self.items.retain(|queued| {
+            let is_removed = queued.item == item;
+            if is_removed {
+                retry_at.remove(&queued.id);
+            }
+            !is_removed
+        });
+        self.items.len() != len
+    }
+
+    pub fn contains_message(&self, channel_id: ChannelId, arrived_at: u64) -> bool {
+        let item = OutboxItem::Message {
+            channel_id,
+            arrived_at,
+        };
+        self.items.iter().any(|queued| queued.item == item)
+    }
+
+    /// Sends the failed items again without waiting for their retry delay, e.g. after
+    /// reconnecting.
+    pub fn resume(&mut self) {
+        self.retry_at.clear();
+    }
+}
+
+/// Delay until an item is retried after it failed to send `attempts` times
+fn retry_delay(attempts: u32) -> Duration {
+    let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+    RETRY_DELAY.saturating_mul(factor).min(MAX_RETRY_DELAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(arrived_at: u64) -> OutboxItem {
+        OutboxItem::Message {
+            channel_id: ChannelId::User(Uuid::nil()),
+            arrived_at,
+        }
+    }
+
+    #[test]
+    fn test_outbox_sends_in_order() {
+        let now = Instant::now();
+        let mut outbox = Outbox::default();
+        let first = outbox.push(message(1));
+        let second = outbox.push(message(2));
+        assert!(first < second);
+
+        let queued = outbox.start_next(now).unwrap();
+        assert_eq!(queued.item, message(1));
+        // only a single item is in flight
+        assert_eq!(outbox.start_next(now), None);
+
+        assert_eq!(
+            outbox.finish(first, true, now),
+            Some(SendOutcome::Sent(message(1)))
+        );
+        assert_eq!(outbox.start_next(now).unwrap().item, message(2));
+        assert_eq!(
+            outbox.finish(second, true, now),
+            Some(SendOutcome::Sent(message(2)))
+        );
+        assert!(outbox.is_empty());
+        assert_eq!(outbox.start_next(now), None);
+    }
+
+    #[test]
+    fn test_outbox_retries_failed_item() {
+        let mut now = Instant::now();
+        let mut outbox = Outbox::default();
+        let id = outbox.push(message(1));
+        let next = outbox.push(message(2));
+
+        assert_eq!(outbox.start_next(now).unwrap().id, id);
+        assert_eq!(
+            outbox.finish(id, false, now),
+            Some(SendOutcome::Retry(message(1)))
+        );
+        // the failed item does not block the next one
+        assert_eq!(outbox.start_next(now).unwrap().id, next);
+        assert_eq!(
+            outbox.finish(next, true, now),
+            Some(SendOutcome::Sent(message(2)))
+        );
+
+        for attempts in 1..MAX_SEND_ATTEMPTS {
+            // waits until its retry delay passed
+            let delay = retry_delay(attempts);
+            assert_eq!(outbox.start_next(now + delay / 2), None);
+            now += delay;
+
+            assert_eq!(outbox.start_next(now).unwrap().id, id);
+            let outcome = outbox.finish(id, false, now);
+            if attempts + 1 < MAX_SEND_ATTEMPTS {
+                assert_eq!(outcome, Some(SendOutcome::Retry(message(1))));
+            } else {
+                assert_eq!(outcome, Some(SendOutcome::Failed(message(1))));
+            }
+        }
+        assert!(outbox.is_empty());
+    }
+
+    #[test]
+    fn test_outbox_retry_delay_increases() {
+        assert_eq!(retry_delay(1), RETRY_DELAY);
+        assert_eq!(retry_delay(2), 2 * RETRY_DELAY);
+        assert_eq!(retry_delay(3), 4 * RETRY_DELAY);
+        assert_eq!(retry_delay(MAX_SEND_ATTEMPTS), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(u32::MAX), MAX_RETRY_DELAY);
+    }
+
+    #[test]
+    fn test_outbox_interrupted_item_is_not_counted() {
+        let now = Instant::now();
+        let mut outbox = Outbox::default();
+        let id = outbox.push(message(1));
+
+        outbox.start_next(now).unwrap();
+        assert_eq!(outbox.interrupt(id), Some(message(1)));
+        let queued = outbox.start_next(now).unwrap();
+        assert_eq!(queued.id, id);
+        assert_eq!(queued.attempts, 0);
+    }
+
+    #[test]
+    fn test_outbox_remove_in_flight_message() {
+        let now = Instant::now();
+        let mut outbox = Outbox::with_items(vec![QueuedItem {
+            id: 7,
+            item: message(1),
+            attempts: 2,
+        }]);
+        assert!(outbox.contains_message(ChannelId::User(Uuid::nil()), 1));
+
+        outbox.start_next(now).unwrap();
+        assert!(outbox.remove_message(ChannelId::User(Uuid::nil()), 1));
+        // ids continue after the loaded items
+        assert_eq!(outbox.push(message(2)), 8);
+        // blocked until the result of the removed item is known
+        assert_eq!(outbox.start_next(now), None);
+        assert_eq!(outbox.finish(7, true, now), None);
+        assert_eq!(outbox.start_next(now).unwrap().item, message(2));
+    }
+}
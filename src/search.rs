@@ -0,0 +1,124 @@
+//! Full-text search over the message bodies of all channels
+
+use crate::app::{ChannelId, Message};
+
+use uuid::Uuid;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Prefix of the search box content, which searches messages instead of channels
+pub const MESSAGE_SEARCH_PREFIX: char = '/';
+
+/// Maximum number of search results
+pub const SEARCH_RESULTS_LIMIT: usize = 100;
+
+/// Message found by a search
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchResult {
+    pub channel_id: ChannelId,
+    pub from_id: Uuid,
+    pub arrived_at: u64,
+    pub text: String,
+}
+
+impl SearchResult {
+    pub fn new(channel_id: ChannelId, message: &Message) -> Option<Self> {
+        Some(Self {
+            channel_id,
+            from_id: message.from_id,
+            arrived_at: message.arrived_at,
+            text: message.message.clone()?,
+        })
+    }
+}
+
+/// Inverted index from the words of message bodies to the messages containing them
+///
+/// Messages are identified by their channel and arrival timestamp. A query matches a message if
+/// every word of the query is a prefix of a word in the message body; words are compared case
+/// insensitively.
+#[derive(Debug, Default)]
+pub struct SearchIndex {
+    words: BTreeMap<String, BTreeSet<(ChannelId, u64)>>,
+}
+
+impl SearchIndex {
+    pub fn add(&mut self, channel_id: ChannelId, message: &Message) {
+        let text = match message.message.as_ref() {
+            Some(text) => text,
+            None => return,
+        };
+        for word in tokenize(text) {
+            self.words
+                .entry(word)
+                .or_default()
+                .insert((channel_id, message.arrived_at));
+        }
+    }
+
+    /// Returns the channel and arrival timestamp of all messages matching the query.
+    pub fn search(&self, query: &str) -> BTreeSet<(ChannelId, u64)> {
+        let mut matches: Option<BTreeSet<(ChannelId, u64)>> = None;
+        for word in tokenize(query) {
+            let word_matches: BTreeSet<_> = self
+                .words
+                .range(word.clone()..)
+                .take_while(|(indexed_word, _)| indexed_word.starts_with(&word))
+                .flat_map(|(_, messages)| messages.iter().copied())
+                .collect();
+            matches = Some(match matches {
+                Some(matches) => &matches & &word_matches,
+                None => word_matches,
+            });
+        }
+        matches.unwrap_or_default()
+    }
+}
+
+/// Splits the text into lowercase words of alphanumeric characters.
+pub fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(arrived_at: u64, text: &str) -> Message {
+        Message::text(Uuid::nil(), text, arrived_at)
+    }
+
+    #[test]
+    fn test_tokenize() {
+        let words: Vec<_> = tokenize("Hello, World! it's 42").collect();
+        assert_eq!(words, ["hello", "world", "it", "s", "42"]);
+    }
+
+    #[test]
+    fn test_search_index() {
+        let alice = ChannelId::User(Uuid::new_v4());
+        let bob = ChannelId::User(Uuid::new_v4());
+
+        let mut index = SearchIndex::default();
+        index.add(alice, &message(1, "Meeting tomorrow at noon"));
+        index.add(alice, &message(2, "see you at the meeting"));
+        index.add(bob, &message(3, "Tomorrow is fine"));
+
+        assert_eq!(
+            index.search("meet"),
+            [(alice, 1), (alice, 2)].into_iter().collect()
+        );
+        assert_eq!(
+            index.search("TOMORROW"),
+            [(alice, 1), (bob, 3)].into_iter().collect()
+        );
+        assert_eq!(
+            index.search("meeting tomorrow"),
+            [(alice, 1)].into_iter().collect()
+        );
+        assert!(index.search("lunch").is_empty());
+        assert!(index.search("").is_empty());
+    }
+}
@@ -10,10 +10,18 @@ pub static SHORTCUTS: &[ShortCut] = &[
         event: "f1",
         description: "Toggle help panel.",
     },
+    ShortCut {
+        event: "f2",
+        description: "Toggle safety number of the selected contact.",
+    },
     ShortCut {
         event: "tab",
         description: "Sends emoji from input line as reaction on selected message.",
     },
+    ShortCut {
+        event: "tab",
+        description: "After @ in a group: completes the name of the mentioned member.",
+    },
     ShortCut {
         event: "alt+enter",
         description: "Switch between single-line and multi-line modes.",
@@ -66,6 +74,18 @@ pub static SHORTCUTS: &[ShortCut] = &[
         event: "alt+Down / PgDown",
         description: "Select next message.",
     },
+    ShortCut {
+        event: "alt+r",
+        description: "Retry sending the selected failed message.",
+    },
+    ShortCut {
+        event: "alt+d",
+        description: "Discard the selected failed or queued message.",
+    },
+    ShortCut {
+        event: "alt+x",
+        description: "Delete the selected own message for everyone.",
+    },
     ShortCut {
         event: "ctrl+j / Up, single-line mode",
         description: "Select previous channel.",
@@ -82,4 +102,12 @@ pub static SHORTCUTS: &[ShortCut] = &[
         event: "ctrl+k / Down, multi-line mode",
         description: "Next line",
     },
+    ShortCut {
+        event: "/, at the beginning of the search bar",
+        description: "Search messages in all channels.",
+    },
+    ShortCut {
+        event: "enter, message search",
+        description: "Jump to the selected search result.",
+    },
 ];
@@ -1,4 +1,4 @@
-use crate::app::{Channel, ChannelId, GroupData, Message, Receipt};
+use crate::app::{Channel, ChannelId, GroupData, Message, Receipt, TypingAction};
 use crate::config::{self, Config};
 use crate::util::utc_now_timestamp_msec;
 
@@ -7,17 +7,32 @@ use anyhow::{bail, Context as _};
 use async_trait::async_trait;
 use chrono::Utc;
 use gh_emoji::Replacer;
+use hkdf::Hkdf;
 use log::error;
 use presage::prelude::content::Reaction;
-use presage::prelude::proto::data_message::Quote;
-use presage::prelude::proto::{AttachmentPointer, ReceiptMessage};
+use presage::prelude::proto::body_range::AssociatedValue;
+use presage::prelude::proto::data_message::{self, Delete, Flags, Quote};
+use presage::prelude::proto::{
+    sync_message, AttachmentPointer, BodyRange, GroupDetails, ReceiptMessage, SyncMessage,
+    TypingMessage,
+};
+use presage::prelude::protocol::{Fingerprint, IdentityKeyStore, ProtocolAddress};
 use presage::prelude::{
     AttachmentSpec, ContentBody, DataMessage, GroupContextV2, GroupMasterKey, SignalServers,
 };
+use prost::Message as _;
 use serde::{Deserialize, Serialize};
+use sha2::Sha256;
 use uuid::Uuid;
 
+use std::fmt;
+use std::future::{self, Future};
 use std::path::PathBuf;
+use std::pin::Pin;
+
+mod groups;
+
+use groups::GroupServer;
 
 pub const GROUP_MASTER_KEY_LEN: usize = 32;
 pub const GROUP_IDENTIFIER_LEN: usize = 32;
@@ -25,8 +40,21 @@ pub const GROUP_IDENTIFIER_LEN: usize = 32;
 pub type GroupMasterKeyBytes = [u8; GROUP_MASTER_KEY_LEN];
 pub type GroupIdentifierBytes = [u8; GROUP_IDENTIFIER_LEN];
 
+/// Store of the Signal Manager, which contains the protocol state incl. identity keys
+pub type Store = presage::SledConfigStore;
+
 /// Signal Manager backed by a `sled` store.
-pub type Manager = presage::Manager<presage::SledConfigStore>;
+pub type Manager = presage::Manager<Store>;
+
+/// Version of safety numbers based on phone numbers
+const SAFETY_NUMBER_VERSION: u32 = 1;
+/// Hash iterations of safety numbers
+const SAFETY_NUMBER_ITERATIONS: u32 = 5200;
+/// Device id of the primary device; identity keys are shared by all devices of a user
+const PRIMARY_DEVICE_ID: u32 = 1;
+
+/// Sending of a message, reaction or receipt, which is done when the future is polled
+pub type SendFuture = Pin<Box<dyn Future<Output = anyhow::Result<()>>>>;
 
 #[async_trait(?Send)]
 pub trait SignalManager {
@@ -34,6 +62,29 @@ pub trait SignalManager {
 
     async fn contact_name(&self, id: Uuid, profile_key: [u8; 32]) -> Option<String>;
 
+    /// Contacts synced from our primary device; contacts without uuid are skipped.
+    fn contacts(&self) -> Vec<Contact>;
+
+    /// Serialized identity key of the contact as of its last message, if there was any.
+    async fn identity_key(&self, id: Uuid) -> Option<Vec<u8>>;
+
+    /// Computes the safety number with the contact, who is identified by its phone number in
+    /// E164 format.
+    async fn safety_number(&self, id: Uuid, phone_number: &str) -> anyhow::Result<SafetyNumber>;
+
+    /// Asks our primary device to sync its contacts and groups; the contacts are available in
+    /// `contacts`, when the sync message arrived, and the groups are read with `synced_groups`.
+    fn request_contacts_sync(&self) -> SendFuture;
+
+    /// Master keys of the groups in the groups sync of our primary device.
+    ///
+    /// The primary device syncs only groups v1, which were migrated to groups v2 with a master key
+    /// derived from their v1 group id. Inactive groups are skipped.
+    async fn synced_groups(
+        &mut self,
+        blob: AttachmentPointer,
+    ) -> anyhow::Result<Vec<GroupMasterKeyBytes>>;
+
     async fn resolve_group(
         &mut self,
         master_key_bytes: GroupMasterKeyBytes,
@@ -44,17 +95,77 @@ pub trait SignalManager {
         attachment_pointer: AttachmentPointer,
     ) -> anyhow::Result<Attachment>;
 
-    fn send_receipt(&self, sender_uuid: Uuid, timestamps: Vec<u64>, receipt: Receipt);
+    fn send_receipt(&self, sender_uuid: Uuid, timestamps: Vec<u64>, receipt: Receipt)
+        -> SendFuture;
 
-    fn send_text(
+    /// Sends our typing indicator to the contact or the members of the group of the channel.
+    fn send_typing(&self, channel: &Channel, action: TypingAction, timestamp: u64) -> SendFuture;
+
+    /// Creates a new own message with a `Queued` receipt, which is sent later with `send_text`.
+    fn create_text(
         &self,
-        channel: &Channel,
         text: String,
         quote_message: Option<&Message>,
-        attachments: Vec<(AttachmentSpec, Vec<u8>)>,
+        attachments: Vec<Attachment>,
     ) -> Message;
 
-    fn send_reaction(&self, channel: &Channel, message: &Message, emoji: String, remove: bool);
+    /// Sends the own message incl. its quote and attachments; the attachment files are read when
+    /// sending.
+    fn send_text(&self, channel: &Channel, message: &Message) -> SendFuture;
+
+    fn send_reaction(
+        &self,
+        channel: &Channel,
+        target_author: Uuid,
+        target_arrived_at: u64,
+        emoji: String,
+        remove: bool,
+    ) -> SendFuture;
+
+    /// Deletes the own message which arrived at `target_arrived_at` for everyone.
+    fn send_delete(&self, channel: &Channel, target_arrived_at: u64) -> SendFuture;
+
+    /// Sets the expiration timer of disappearing messages in seconds; `None` turns it off.
+    fn send_expire_timer(&self, channel: &Channel, timer: Option<u32>) -> SendFuture;
+
+    /// Shares our profile key, s.t. the recipients can see our name and avatar.
+    fn send_profile_key(&self, channel: &Channel) -> SendFuture;
+
+    /// Applies the change to the group of the channel on the group server, and notifies the
+    /// members about it.
+    ///
+    /// `GroupChange::Create` creates the group with the name and the members of the channel.
+    fn send_group_change(&self, channel: &Channel, change: GroupChange) -> SendFuture;
+}
+
+/// Creation or change of a group, which is applied by the group server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GroupChange {
+    Create,
+    Rename(String),
+    Describe(String),
+    AddMember(Uuid),
+    RemoveMember(Uuid),
+    Leave,
+}
+
+/// Contact from the address book of our primary device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Contact {
+    pub uuid: Uuid,
+    /// Name in the address book; empty if the contact has no name
+    pub name: String,
+    /// Phone number formatted as E164
+    pub phone_number: Option<String>,
+}
+
+/// Safety number with a contact, which is derived from our and their identity key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SafetyNumber {
+    /// 60 digits, which are compared in groups of 5
+    pub digits: String,
+    /// Encoding which is scanned as QR code by the contact
+    pub scannable: Vec<u8>,
 }
 
 pub struct ResolvedGroup {
@@ -65,16 +176,54 @@ pub struct ResolvedGroup {
 
 pub struct PresageManager {
     manager: Manager,
+    store: Store,
     emoji_replacer: Replacer,
 }
 
 impl PresageManager {
-    pub fn new(manager: Manager) -> Self {
+    pub fn new(manager: Manager, store: Store) -> Self {
         Self {
             manager,
+            store,
             emoji_replacer: Replacer::new(),
         }
     }
+
+    /// Sends the data message without attachments to the contact or the group of the channel.
+    fn send_data_message(
+        &self,
+        channel: &Channel,
+        mut data_message: DataMessage,
+        timestamp: u64,
+    ) -> SendFuture {
+        let manager = self.manager.clone();
+        match (channel.id, channel.group_data.as_ref()) {
+            (ChannelId::User(uuid), _) => {
+                let body = ContentBody::DataMessage(data_message);
+                Box::pin(async move {
+                    manager.send_message(uuid, body, timestamp).await?;
+                    Ok::<_, anyhow::Error>(())
+                })
+            }
+            (ChannelId::Group(_), Some(group_data)) => {
+                let self_uuid = self.user_id();
+                data_message.group_v2 = Some(group_context(group_data));
+                let members = group_data.members.clone();
+
+                Box::pin(async move {
+                    let recipients = members
+                        .into_iter()
+                        .filter(|uuid| *uuid != self_uuid)
+                        .map(Into::into);
+                    manager
+                        .send_message_to_group(recipients, data_message, timestamp)
+                        .await?;
+                    Ok::<_, anyhow::Error>(())
+                })
+            }
+            (ChannelId::Group(_), None) => broken_channel(),
+        }
+    }
 }
 
 #[async_trait(?Send)]
@@ -83,7 +232,12 @@ impl SignalManager for PresageManager {
         self.manager.uuid()
     }
 
-    fn send_receipt(&self, _sender_uuid: Uuid, timestamps: Vec<u64>, receipt: Receipt) {
+    fn send_receipt(
+        &self,
+        sender_uuid: Uuid,
+        timestamps: Vec<u64>,
+        receipt: Receipt,
+    ) -> SendFuture {
         let now_timestamp = utc_now_timestamp_msec();
         let data_message = ReceiptMessage {
             r#type: Some(receipt.to_i32()),
@@ -91,158 +245,223 @@ impl SignalManager for PresageManager {
         };
 
         let manager = self.manager.clone();
-        tokio::task::spawn_local(async move {
+        Box::pin(async move {
             let body = ContentBody::ReceiptMessage(data_message);
-            if let Err(e) = manager
-                .send_message(_sender_uuid, body, now_timestamp)
-                .await
-            {
-                log::error!("Failed to send message to {}: {}", _sender_uuid, e);
+            manager
+                .send_message(sender_uuid, body, now_timestamp)
+                .await?;
+            Ok::<_, anyhow::Error>(())
+        })
+    }
+
+    fn send_typing(&self, channel: &Channel, action: TypingAction, timestamp: u64) -> SendFuture {
+        let (recipients, group_id) = match (channel.id, channel.group_data.as_ref()) {
+            (ChannelId::User(uuid), _) => (vec![uuid], None),
+            (ChannelId::Group(group_id), Some(group_data)) => {
+                let self_uuid = self.user_id();
+                let members = group_data
+                    .members
+                    .iter()
+                    .copied()
+                    .filter(|&uuid| uuid != self_uuid)
+                    .collect();
+                (members, Some(group_id.to_vec()))
             }
-        });
+            (ChannelId::Group(_), None) => return broken_channel(),
+        };
+        let typing_message = TypingMessage {
+            timestamp: Some(timestamp),
+            action: Some(action.to_i32()),
+            group_id,
+        };
+
+        let manager = self.manager.clone();
+        Box::pin(async move {
+            // typing messages are not group messages, but are sent to each member concurrently
+            let sends: Vec<_> = recipients
+                .into_iter()
+                .map(|uuid| {
+                    let manager = manager.clone();
+                    let body = ContentBody::TypingMessage(typing_message.clone());
+                    tokio::task::spawn_local(async move {
+                        manager.send_message(uuid, body, timestamp).await
+                    })
+                })
+                .collect();
+            for send in sends {
+                send.await??;
+            }
+            Ok::<_, anyhow::Error>(())
+        })
     }
 
-    fn send_text(
+    fn create_text(
         &self,
-        channel: &Channel,
         text: String,
         quote_message: Option<&Message>,
-        attachments: Vec<(AttachmentSpec, Vec<u8>)>,
+        attachments: Vec<Attachment>,
     ) -> Message {
-        let mut message: String = self.emoji_replacer.replace_all(&text).into_owned();
-        let has_attachments = !attachments.is_empty();
-
-        let timestamp = utc_now_timestamp_msec();
-
-        let quote = quote_message.map(|message| Quote {
-            id: Some(message.arrived_at),
-            author_uuid: Some(message.from_id.to_string()),
-            text: message.message.clone(),
-            ..Default::default()
-        });
-        let quote_message = quote.clone().and_then(Message::from_quote).map(Box::new);
+        new_own_message(
+            self.user_id(),
+            &self.emoji_replacer,
+            text,
+            quote_message,
+            attachments,
+        )
+    }
 
+    fn send_text(&self, channel: &Channel, message: &Message) -> SendFuture {
+        let timestamp = message.arrived_at;
         let mut data_message = DataMessage {
-            body: Some(message.clone()),
+            body: message.message.clone(),
             timestamp: Some(timestamp),
-            quote,
+            quote: message.quote.as_deref().map(quote_of),
+            expire_timer: message.expires_in,
+            body_ranges: message
+                .mentions
+                .iter()
+                .copied()
+                .map(Mention::to_body_range)
+                .collect(),
             ..Default::default()
         };
+        let attachments = message.attachments.clone();
 
-        match channel.id {
-            ChannelId::User(uuid) => {
-                let manager = self.manager.clone();
-                tokio::task::spawn_local(async move {
-                    upload_attachments(&manager, attachments, &mut data_message).await;
-
-                    let body = ContentBody::DataMessage(data_message);
-                    if let Err(e) = manager.send_message(uuid, body, timestamp).await {
-                        // TODO: Proper error handling
-                        log::error!("Failed to send message to {}: {}", uuid, e);
-                    }
-                });
-            }
-            ChannelId::Group(_) => {
-                if let Some(group_data) = channel.group_data.as_ref() {
-                    let manager = self.manager.clone();
-                    let self_uuid = self.user_id();
-
-                    data_message.group_v2 = Some(GroupContextV2 {
-                        master_key: Some(group_data.master_key_bytes.to_vec()),
-                        revision: Some(group_data.revision),
-                        ..Default::default()
-                    });
-
-                    let recipients = group_data.members.clone().into_iter();
-
-                    tokio::task::spawn_local(async move {
-                        upload_attachments(&manager, attachments, &mut data_message).await;
-
-                        let recipients =
-                            recipients.filter(|uuid| *uuid != self_uuid).map(Into::into);
-                        if let Err(e) = manager
-                            .send_message_to_group(recipients, data_message, timestamp)
-                            .await
-                        {
-                            // TODO: Proper error handling
-                            log::error!("Failed to send group message: {}", e);
-                        }
-                    });
-                } else {
-                    error!("cannot send to broken channel without group data");
-                }
+        let manager = self.manager.clone();
+        match (channel.id, channel.group_data.as_ref()) {
+            (ChannelId::User(uuid), _) => Box::pin(async move {
+                upload_attachments(&manager, &attachments, &mut data_message).await?;
+                let body = ContentBody::DataMessage(data_message);
+                manager.send_message(uuid, body, timestamp).await?;
+                Ok::<_, anyhow::Error>(())
+            }),
+            (ChannelId::Group(_), Some(group_data)) => {
+                let self_uuid = self.user_id();
+                data_message.group_v2 = Some(group_context(group_data));
+                let members = group_data.members.clone();
+
+                Box::pin(async move {
+                    upload_attachments(&manager, &attachments, &mut data_message).await?;
+                    let recipients = members
+                        .into_iter()
+                        .filter(|uuid| *uuid != self_uuid)
+                        .map(Into::into);
+                    manager
+                        .send_message_to_group(recipients, data_message, timestamp)
+                        .await?;
+                    Ok::<_, anyhow::Error>(())
+                })
             }
-        }
-
-        if has_attachments && message.is_empty() {
-            // TODO: Temporary solution until we start rendering attachments
-            message = "<attachment>".to_string();
-        }
-
-        Message {
-            from_id: self.user_id(),
-            message: Some(message),
-            arrived_at: timestamp,
-            quote: quote_message,
-            attachments: Default::default(),
-            reactions: Default::default(),
-            receipt: Receipt::Sent,
+            (ChannelId::Group(_), None) => broken_channel(),
         }
     }
 
-    fn send_reaction(&self, channel: &Channel, message: &Message, emoji: String, remove: bool) {
+    fn send_reaction(
+        &self,
+        channel: &Channel,
+        target_author: Uuid,
+        target_arrived_at: u64,
+        emoji: String,
+        remove: bool,
+    ) -> SendFuture {
         let timestamp = utc_now_timestamp_msec();
-        let target_author_uuid = message.from_id;
-        let target_sent_timestamp = message.arrived_at;
-
-        let mut data_message = DataMessage {
+        let data_message = DataMessage {
             reaction: Some(Reaction {
-                emoji: Some(emoji.clone()),
+                emoji: Some(emoji),
                 remove: Some(remove),
-                target_author_uuid: Some(target_author_uuid.to_string()),
-                target_sent_timestamp: Some(target_sent_timestamp),
+                target_author_uuid: Some(target_author.to_string()),
+                target_sent_timestamp: Some(target_arrived_at),
             }),
             ..Default::default()
         };
+        self.send_data_message(channel, data_message, timestamp)
+    }
 
-        match (channel.id, channel.group_data.as_ref()) {
-            (ChannelId::User(uuid), _) => {
-                let manager = self.manager.clone();
-                let body = ContentBody::DataMessage(data_message);
-                tokio::task::spawn_local(async move {
-                    if let Err(e) = manager.send_message(uuid, body, timestamp).await {
-                        // TODO: Proper error handling
-                        log::error!("failed to send reaction {} to {}: {}", &emoji, uuid, e);
-                    }
-                });
-            }
-            (ChannelId::Group(_), Some(group_data)) => {
-                let manager = self.manager.clone();
-                let self_uuid = self.user_id();
+    fn send_delete(&self, channel: &Channel, target_arrived_at: u64) -> SendFuture {
+        let timestamp = utc_now_timestamp_msec();
+        let data_message = DataMessage {
+            delete: Some(Delete {
+                target_sent_timestamp: Some(target_arrived_at),
+            }),
+            timestamp: Some(timestamp),
+            ..Default::default()
+        };
+        self.send_data_message(channel, data_message, timestamp)
+    }
 
-                data_message.group_v2 = Some(GroupContextV2 {
-                    master_key: Some(group_data.master_key_bytes.to_vec()),
-                    revision: Some(group_data.revision),
-                    ..Default::default()
-                });
+    fn send_expire_timer(&self, channel: &Channel, timer: Option<u32>) -> SendFuture {
+        let timestamp = utc_now_timestamp_msec();
+        let data_message = DataMessage {
+            flags: Some(Flags::ExpirationTimerUpdate as u32),
+            expire_timer: Some(timer.unwrap_or(0)),
+            timestamp: Some(timestamp),
+            ..Default::default()
+        };
+        self.send_data_message(channel, data_message, timestamp)
+    }
+
+    fn send_profile_key(&self, channel: &Channel) -> SendFuture {
+        let profile_key = match self.manager.profile_key() {
+            Ok(profile_key) => profile_key.get_bytes().to_vec(),
+            Err(e) => return Box::pin(future::ready(Err(e.into()))),
+        };
+        let timestamp = utc_now_timestamp_msec();
+        let data_message = DataMessage {
+            flags: Some(Flags::ProfileKeyUpdate as u32),
+            profile_key: Some(profile_key),
+            timestamp: Some(timestamp),
+            ..Default::default()
+        };
+        self.send_data_message(channel, data_message, timestamp)
+    }
 
-                let recipients = group_data.members.clone().into_iter();
+    fn send_group_change(&self, channel: &Channel, change: GroupChange) -> SendFuture {
+        let mut group_data = match channel.group_data.clone() {
+            Some(group_data) => group_data,
+            None => return broken_channel(),
+        };
+        let title = channel.name.clone();
+        let manager = self.manager.clone();
+        let store = self.store.clone();
+        let self_uuid = self.user_id();
+
+        Box::pin(async move {
+            let mut group_server = GroupServer::new(&store)?;
+            let group_change = match change {
+                GroupChange::Create => {
+                    group_server.create(&title, &group_data).await?;
+                    None
+                }
+                _ => {
+                    let signed_change = group_server.change(&group_data, &change).await?;
+                    group_data.revision += 1;
+                    Some(signed_change)
+                }
+            };
 
-                tokio::task::spawn_local(async move {
-                    let recipients = recipients.filter(|uuid| *uuid != self_uuid).map(Into::into);
-                    if let Err(e) = manager
-                        .send_message_to_group(recipients, data_message, timestamp)
-                        .await
-                    {
-                        // TODO: Proper error handling
-                        log::error!("failed to send group reaction {}: {}", &emoji, e);
-                    }
-                });
-            }
-            _ => {
-                error!("cannot send to broken channel without group data");
+            // the members learn about the change from the group context of the new revision
+            let mut recipients = group_data.members.clone();
+            if let GroupChange::AddMember(uuid) = change {
+                recipients.push(uuid);
             }
-        }
+            let timestamp = utc_now_timestamp_msec();
+            let data_message = DataMessage {
+                group_v2: Some(GroupContextV2 {
+                    group_change,
+                    ..group_context(&group_data)
+                }),
+                timestamp: Some(timestamp),
+                ..Default::default()
+            };
+            let recipients = recipients
+                .into_iter()
+                .filter(|uuid| *uuid != self_uuid)
+                .map(Into::into);
+            manager
+                .send_message_to_group(recipients, data_message, timestamp)
+                .await?;
+            Ok::<_, anyhow::Error>(())
+        })
     }
 
     async fn contact_name(&self, id: Uuid, profile_key: [u8; 32]) -> Option<String> {
@@ -255,6 +474,101 @@ impl SignalManager for PresageManager {
         }
     }
 
+    fn contacts(&self) -> Vec<Contact> {
+        let contacts = match self.manager.get_contacts() {
+            Ok(contacts) => contacts,
+            Err(e) => {
+                error!("failed to load contacts: {}", e);
+                return Vec::new();
+            }
+        };
+        contacts
+            .into_iter()
+            .filter_map(|contact| {
+                Some(Contact {
+                    uuid: contact.address.uuid?,
+                    name: contact.name,
+                    phone_number: contact
+                        .address
+                        .phonenumber
+                        .map(|number| number.format().mode(phonenumber::Mode::E164).to_string()),
+                })
+            })
+            .collect()
+    }
+
+    async fn identity_key(&self, id: Uuid) -> Option<Vec<u8>> {
+        let address = ProtocolAddress::new(id.to_string(), PRIMARY_DEVICE_ID);
+        match self.store.get_identity(&address, None).await {
+            Ok(key) => Some(key?.serialize().into_vec()),
+            Err(e) => {
+                error!("failed to load identity key: {}", e);
+                None
+            }
+        }
+    }
+
+    async fn safety_number(&self, id: Uuid, phone_number: &str) -> anyhow::Result<SafetyNumber> {
+        let address = ProtocolAddress::new(id.to_string(), PRIMARY_DEVICE_ID);
+        let their_key = self
+            .store
+            .get_identity(&address, None)
+            .await?
+            .ok_or_else(|| anyhow!("no identity key, since no message was exchanged"))?;
+        let our_key_pair = self.store.get_identity_key_pair(None).await?;
+        let our_phone_number = self
+            .manager
+            .phone_number()
+            .ok_or_else(|| anyhow!("our phone number is unknown"))?
+            .format()
+            .mode(phonenumber::Mode::E164)
+            .to_string();
+        let fingerprint = Fingerprint::new(
+            SAFETY_NUMBER_VERSION,
+            SAFETY_NUMBER_ITERATIONS,
+            our_phone_number.as_bytes(),
+            our_key_pair.identity_key(),
+            phone_number.as_bytes(),
+            &their_key,
+        )?;
+        Ok(SafetyNumber {
+            digits: fingerprint.display_string()?,
+            scannable: fingerprint.scannable.serialize()?,
+        })
+    }
+
+    fn request_contacts_sync(&self) -> SendFuture {
+        let manager = self.manager.clone();
+        Box::pin(async move {
+            manager.request_contacts_sync().await?;
+            request_groups_sync(&manager).await
+        })
+    }
+
+    async fn synced_groups(
+        &mut self,
+        blob: AttachmentPointer,
+    ) -> anyhow::Result<Vec<GroupMasterKeyBytes>> {
+        let data = self.manager.get_attachment(&blob).await?;
+        let mut buf = &data[..];
+        let mut master_keys = Vec::new();
+        while !buf.is_empty() {
+            let details = GroupDetails::decode_length_delimited(&mut buf)?;
+            // the avatar follows the details of its group
+            let avatar_len = details.avatar.as_ref().and_then(|avatar| avatar.length);
+            buf = buf
+                .get(avatar_len.unwrap_or_default() as usize..)
+                .ok_or_else(|| anyhow!("truncated groups sync"))?;
+            if details.active == Some(false) {
+                continue;
+            }
+            if let Some(id) = details.id {
+                master_keys.push(migrated_master_key(&id)?);
+            }
+        }
+        Ok(master_keys)
+    }
+
     async fn resolve_group(
         &mut self,
         master_key_bytes: GroupMasterKeyBytes,
@@ -291,9 +605,7 @@ impl SignalManager for PresageManager {
         &mut self,
         attachment_pointer: AttachmentPointer,
     ) -> anyhow::Result<Attachment> {
-        let data_dir = dirs::data_dir()
-            .ok_or_else(|| anyhow!("could not find data directory"))?
-            .join("gurk");
+        let data_dir = attachments_dir()?;
         let attachment_data = self.manager.get_attachment(&attachment_pointer).await?;
 
         let date = Utc::now().to_rfc3339();
@@ -301,6 +613,7 @@ impl SignalManager for PresageManager {
             Some("image/jpeg") => format!("signal-{}.jpg", date),
             Some("image/gif") => format!("signal-{}.gif", date),
             Some("image/png") => format!("signal-{}.png", date),
+            Some("image/webp") => format!("signal-{}.webp", date),
             Some(mimetype) => {
                 log::warn!("unsupported attachment mimetype: {}", mimetype);
                 format!("signal-{}", date)
@@ -322,27 +635,134 @@ impl SignalManager for PresageManager {
     }
 }
 
+/// Creates a new own message with a `Queued` receipt.
+fn new_own_message(
+    user_id: Uuid,
+    emoji_replacer: &Replacer,
+    text: String,
+    quote_message: Option<&Message>,
+    attachments: Vec<Attachment>,
+) -> Message {
+    let message: String = emoji_replacer.replace_all(&text).into_owned();
+    let quote_message = quote_message
+        .map(quote_of)
+        .and_then(Message::from_quote)
+        .map(Box::new);
+    Message {
+        from_id: user_id,
+        message: Some(message).filter(|message| !message.is_empty()),
+        arrived_at: utc_now_timestamp_msec(),
+        quote: quote_message,
+        attachments,
+        reactions: Default::default(),
+        receipt: Receipt::Queued,
+        is_deleted: false,
+        expires_in: None,
+        expire_started_at: None,
+        is_system: false,
+        is_warning: false,
+        mentions: Default::default(),
+        sticker: None,
+    }
+}
+
+fn quote_of(message: &Message) -> Quote {
+    Quote {
+        id: Some(message.arrived_at),
+        author_uuid: Some(message.from_id.to_string()),
+        text: message.message.clone(),
+        body_ranges: message
+            .mentions
+            .iter()
+            .copied()
+            .map(Mention::to_body_range)
+            .collect(),
+        ..Default::default()
+    }
+}
+
+fn group_context(group_data: &GroupData) -> GroupContextV2 {
+    GroupContextV2 {
+        master_key: Some(group_data.master_key_bytes.to_vec()),
+        revision: Some(group_data.revision),
+        ..Default::default()
+    }
+}
+
+/// Master key of the group v2 which the group v1 with the given id was migrated to
+fn migrated_master_key(group_v1_id: &[u8]) -> anyhow::Result<GroupMasterKeyBytes> {
+    let mut master_key = [0; GROUP_MASTER_KEY_LEN];
+    Hkdf::<Sha256>::new(None, group_v1_id)
+        .expand(b"GV2 Migration", &mut master_key)
+        .map_err(|_| anyhow!("failed to derive migrated group master key"))?;
+    Ok(master_key)
+}
+
+/// Asks our primary device to sync its groups.
+async fn request_groups_sync(manager: &Manager) -> anyhow::Result<()> {
+    let request = SyncMessage {
+        request: Some(sync_message::Request {
+            r#type: Some(sync_message::request::Type::Groups as i32),
+        }),
+        ..Default::default()
+    };
+    let body = ContentBody::SynchronizeMessage(request);
+    manager
+        .send_message(manager.uuid(), body, utc_now_timestamp_msec())
+        .await?;
+    Ok(())
+}
+
+fn broken_channel() -> SendFuture {
+    Box::pin(future::ready(Err(anyhow!(
+        "cannot send to broken channel without group data"
+    ))))
+}
+
+/// Reads and uploads the attachment files, and adds them to the data message.
+///
+/// Fails if any of the attachments could not be read or uploaded.
 async fn upload_attachments(
-    manager: &presage::Manager<presage::SledConfigStore>,
-    attachments: Vec<(AttachmentSpec, Vec<u8>)>,
+    manager: &Manager,
+    attachments: &[Attachment],
     data_message: &mut DataMessage,
-) {
-    match manager.upload_attachments(attachments).await {
-        Ok(attachment_pointers) => {
-            data_message.attachments = attachment_pointers
-                .into_iter()
-                .filter_map(|res| {
-                    if let Err(e) = res.as_ref() {
-                        error!("failed to upload attachment: {}", e);
-                    }
-                    res.ok()
-                })
-                .collect();
-        }
-        Err(e) => {
-            error!("failed to upload attachments: {}", e);
-        }
-    }
+) -> anyhow::Result<()> {
+    let attachments = attachments
+        .iter()
+        .map(|attachment| {
+            let contents = std::fs::read(&attachment.filename).with_context(|| {
+                format!(
+                    "failed to read attachment '{}'",
+                    attachment.filename.display()
+                )
+            })?;
+            let spec = AttachmentSpec {
+                content_type: attachment.content_type.clone(),
+                length: contents.len(),
+                file_name: attachment
+                    .filename
+                    .file_name()
+                    .map(|f| f.to_string_lossy().into()),
+                preview: None,
+                voice_note: None,
+                borderless: None,
+                width: None,
+                height: None,
+                caption: None,
+                blur_hash: None,
+            };
+            Ok((spec, contents))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let attachment_pointers = manager
+        .upload_attachments(attachments)
+        .await
+        .context("failed to upload attachments")?;
+    data_message.attachments = attachment_pointers
+        .into_iter()
+        .collect::<Result<Vec<_>, _>>()
+        .context("failed to upload attachment")?;
+    Ok(())
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -354,11 +774,94 @@ pub struct Attachment {
     pub size: u32,
 }
 
+/// Sticker of a sticker pack; its image is saved as attachment of the message
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Sticker {
+    /// Hex encoded ID of the sticker pack
+    pub pack_id: String,
+    pub sticker_id: u32,
+    /// Emoji associated with the sticker
+    pub emoji: Option<String>,
+}
+
+impl Sticker {
+    /// Returns `None`, if the pack or sticker ID is missing.
+    pub fn from_proto(sticker: &data_message::Sticker) -> Option<Self> {
+        Some(Self {
+            pack_id: sticker
+                .pack_id
+                .as_ref()?
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect(),
+            sticker_id: sticker.sticker_id?,
+            emoji: sticker.emoji.clone(),
+        })
+    }
+}
+
+impl fmt::Display for Sticker {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.emoji.as_deref() {
+            Some(emoji) => write!(f, "[Sticker {}]", emoji),
+            None => write!(f, "[Sticker]"),
+        }
+    }
+}
+
+/// Character in the message body which is replaced by the name of the mentioned user
+pub const MENTION_PLACEHOLDER: char = '\u{fffc}';
+
+/// Mention of a user, which is a placeholder character in the message body
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Mention {
+    /// Offset of the placeholder in UTF-16 code units, as in the body ranges of the protocol
+    pub start: u32,
+    pub length: u32,
+    pub uuid: Uuid,
+}
+
+impl Mention {
+    /// Mentions in the body ranges; other body ranges are skipped.
+    pub fn from_body_ranges(body_ranges: Vec<BodyRange>) -> Vec<Self> {
+        body_ranges
+            .into_iter()
+            .filter_map(Self::from_body_range)
+            .collect()
+    }
+
+    /// Returns `None`, if the body range is not a mention.
+    fn from_body_range(body_range: BodyRange) -> Option<Self> {
+        match body_range.associated_value? {
+            AssociatedValue::MentionUuid(uuid) => Some(Self {
+                start: body_range.start?,
+                length: body_range.length.unwrap_or(1),
+                uuid: uuid.parse().ok()?,
+            }),
+        }
+    }
+
+    fn to_body_range(self) -> BodyRange {
+        BodyRange {
+            start: Some(self.start),
+            length: Some(self.length),
+            associated_value: Some(AssociatedValue::MentionUuid(self.uuid.to_string())),
+        }
+    }
+}
+
+/// Directory into which the attachments of received messages are saved.
+pub fn attachments_dir() -> anyhow::Result<PathBuf> {
+    Ok(dirs::data_dir()
+        .ok_or_else(|| anyhow!("could not find data directory"))?
+        .join("gurk"))
+}
+
 /// If `db_path` does not exist, it will be created (including parent directories).
-fn get_signal_manager(db_path: PathBuf) -> anyhow::Result<Manager> {
-    let store = presage::SledConfigStore::new(db_path)?;
-    let manager = presage::Manager::with_store(store)?;
-    Ok(manager)
+fn get_signal_manager(db_path: PathBuf) -> anyhow::Result<(Manager, Store)> {
+    let store = Store::new(db_path)?;
+    let manager = presage::Manager::with_store(store.clone())?;
+    Ok((manager, store))
 }
 
 /// Makes sure that we have linked device.
@@ -369,20 +872,22 @@ fn get_signal_manager(db_path: PathBuf) -> anyhow::Result<Manager> {
 ///    and phone number, or
 /// 2. loads the config file and tries to create the Signal manager from configured Signal database
 ///    path.
-pub async fn ensure_linked_device(relink: bool) -> anyhow::Result<(Manager, Config)> {
+///
+/// The store of the manager is returned as well, since it holds the identity keys.
+pub async fn ensure_linked_device(relink: bool) -> anyhow::Result<(Manager, Store, Config)> {
     let config = Config::load_installed()?;
     let db_path = config
         .as_ref()
         .map(|c| c.signal_db_path.clone())
         .unwrap_or_else(config::default_signal_db_path);
 
-    let mut manager = get_signal_manager(db_path)?;
+    let (mut manager, store) = get_signal_manager(db_path)?;
 
     let is_registered = !relink && manager.is_registered();
 
     if is_registered {
         if let Some(config) = config {
-            return Ok((manager, config));
+            return Ok((manager, store, config));
         }
     }
 
@@ -414,6 +919,14 @@ pub async fn ensure_linked_device(relink: bool) -> anyhow::Result<(Manager, Conf
         .retrieve_profile()
         .await
         .context("failed to get the user profile")?;
+    // the contacts and groups arrive with the first received messages; they can be requested
+    // again later
+    if let Err(e) = manager.request_contacts_sync().await {
+        error!("failed to request contacts: {}", e);
+    }
+    if let Err(e) = request_groups_sync(&manager).await {
+        error!("failed to request groups: {}", e);
+    }
     let name = profile
         .name
         .map(|name| name.given_name)
@@ -432,20 +945,38 @@ pub async fn ensure_linked_device(relink: bool) -> anyhow::Result<(Manager, Conf
         config
     };
 
-    Ok((manager, config))
+    Ok((manager, store, config))
 }
 
 #[cfg(test)]
 pub mod test {
     use super::*;
 
-    use std::{cell::RefCell, rc::Rc};
+    use std::cell::{Cell, RefCell};
+    use std::collections::HashMap;
+    use std::rc::Rc;
 
     /// Signal manager mock which does not send any messages.
+    ///
+    /// The messages and deletes which would be sent are recorded in `sent_messages` and
+    /// `sent_deletes`. While `is_failing` is set, sending anything fails.
     pub struct SignalManagerMock {
         user_id: Uuid,
         emoji_replacer: Replacer,
         pub sent_messages: Rc<RefCell<Vec<Message>>>,
+        /// Arrival timestamps of the messages deleted for everyone
+        pub sent_deletes: Rc<RefCell<Vec<u64>>>,
+        pub sent_typing: Rc<RefCell<Vec<(ChannelId, TypingAction)>>>,
+        /// Channels with which our profile key was shared
+        pub sent_profile_keys: Rc<RefCell<Vec<ChannelId>>>,
+        pub contacts: Vec<Contact>,
+        /// Master keys of the groups in any groups sync
+        pub synced_groups: Vec<GroupMasterKeyBytes>,
+        /// Identity keys of contacts, which change when they reinstall Signal
+        pub identity_keys: Rc<RefCell<HashMap<Uuid, Vec<u8>>>>,
+        pub is_failing: Rc<Cell<bool>>,
+        /// Name and data of the group which is returned when resolving any group
+        pub resolved_group: Rc<RefCell<Option<(String, GroupData)>>>,
     }
 
     impl SignalManagerMock {
@@ -454,8 +985,25 @@ pub mod test {
                 user_id: Uuid::new_v4(),
                 emoji_replacer: Replacer::new(),
                 sent_messages: Default::default(),
+                sent_deletes: Default::default(),
+                sent_typing: Default::default(),
+                sent_profile_keys: Default::default(),
+                contacts: Default::default(),
+                synced_groups: Default::default(),
+                identity_keys: Default::default(),
+                is_failing: Default::default(),
+                resolved_group: Default::default(),
             }
         }
+
+        fn send_result(&self) -> SendFuture {
+            let result = if self.is_failing.get() {
+                Err(anyhow!("mocked signal manager is offline"))
+            } else {
+                Ok(())
+            };
+            Box::pin(future::ready(result))
+        }
     }
 
     #[async_trait(?Send)]
@@ -464,57 +1012,127 @@ pub mod test {
             self.user_id
         }
 
-        fn send_receipt(&self, _: Uuid, _: Vec<u64>, _: Receipt) {}
+        fn send_receipt(&self, _: Uuid, _: Vec<u64>, _: Receipt) -> SendFuture {
+            self.send_result()
+        }
+
+        fn send_typing(
+            &self,
+            channel: &Channel,
+            action: TypingAction,
+            _timestamp: u64,
+        ) -> SendFuture {
+            self.sent_typing.borrow_mut().push((channel.id, action));
+            self.send_result()
+        }
 
         async fn contact_name(&self, _id: Uuid, _profile_key: [u8; 32]) -> Option<String> {
             None
         }
 
+        fn contacts(&self) -> Vec<Contact> {
+            self.contacts.clone()
+        }
+
+        fn request_contacts_sync(&self) -> SendFuture {
+            self.send_result()
+        }
+
+        async fn synced_groups(
+            &mut self,
+            _blob: AttachmentPointer,
+        ) -> anyhow::Result<Vec<GroupMasterKeyBytes>> {
+            Ok(self.synced_groups.clone())
+        }
+
+        async fn identity_key(&self, id: Uuid) -> Option<Vec<u8>> {
+            self.identity_keys.borrow().get(&id).cloned()
+        }
+
+        async fn safety_number(
+            &self,
+            id: Uuid,
+            _phone_number: &str,
+        ) -> anyhow::Result<SafetyNumber> {
+            let key = self
+                .identity_key(id)
+                .await
+                .ok_or_else(|| anyhow!("no identity key"))?;
+            Ok(SafetyNumber {
+                digits: "12345".repeat(12),
+                scannable: key,
+            })
+        }
+
         async fn resolve_group(
             &mut self,
             _master_key_bytes: super::GroupMasterKeyBytes,
         ) -> anyhow::Result<super::ResolvedGroup> {
-            bail!("mocked signal manager cannot resolve groups");
+            let (name, group_data) = self
+                .resolved_group
+                .borrow()
+                .clone()
+                .ok_or_else(|| anyhow!("mocked signal manager cannot resolve groups"))?;
+            Ok(super::ResolvedGroup {
+                name,
+                group_data,
+                profile_keys: Vec::new(),
+            })
         }
 
-        fn send_text(
+        fn create_text(
             &self,
-            _channel: &crate::app::Channel,
             text: String,
-            quote_message: Option<&crate::app::Message>,
-            _attachments: Vec<(AttachmentSpec, Vec<u8>)>,
+            quote_message: Option<&Message>,
+            attachments: Vec<Attachment>,
         ) -> Message {
-            let message: String = self.emoji_replacer.replace_all(&text).into_owned();
-            let timestamp = utc_now_timestamp_msec();
-            let quote = quote_message.map(|message| Quote {
-                id: Some(message.arrived_at),
-                author_uuid: Some(message.from_id.to_string()),
-                text: message.message.clone(),
-                ..Default::default()
-            });
-            let quote_message = quote.and_then(Message::from_quote).map(Box::new);
-            let message = Message {
-                from_id: self.user_id(),
-                message: Some(message),
-                arrived_at: timestamp,
-                quote: quote_message,
-                attachments: Default::default(),
-                reactions: Default::default(),
-                // TODO make sure the message sending procedure did not fail
-                receipt: Receipt::Sent,
-            };
-            self.sent_messages.borrow_mut().push(message.clone());
-            println!("sent messages: {:?}", self.sent_messages.borrow());
-            message
+            new_own_message(
+                self.user_id,
+                &self.emoji_replacer,
+                text,
+                quote_message,
+                attachments,
+            )
+        }
+
+        fn send_text(&self, _channel: &Channel, message: &Message) -> SendFuture {
+            if !self.is_failing.get() {
+                self.sent_messages.borrow_mut().push(message.clone());
+            }
+            self.send_result()
         }
 
         fn send_reaction(
             &self,
-            _channel: &crate::app::Channel,
-            _message: &crate::app::Message,
+            _channel: &Channel,
+            _target_author: Uuid,
+            _target_arrived_at: u64,
             _emoji: String,
             _remove: bool,
-        ) {
+        ) -> SendFuture {
+            self.send_result()
+        }
+
+        fn send_delete(&self, _channel: &Channel, target_arrived_at: u64) -> SendFuture {
+            if !self.is_failing.get() {
+                self.sent_deletes.borrow_mut().push(target_arrived_at);
+            }
+            self.send_result()
+        }
+
+        fn send_expire_timer(&self, _channel: &Channel, _timer: Option<u32>) -> SendFuture {
+            self.send_result()
+        }
+
+        fn send_profile_key(&self, channel: &Channel) -> SendFuture {
+            if !self.is_failing.get() {
+                self.sent_profile_keys.borrow_mut().push(channel.id);
+            }
+            self.send_result()
+        }
+
+        fn send_group_change(&self, _channel: &Channel, _change: GroupChange) -> SendFuture {
+            self.send_result()
         }
 
         async fn save_attachment(
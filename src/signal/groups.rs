@@ -0,0 +1,286 @@
+//! Creating and changing groups on the group server
+//!
+//! presage only fetches groups, so creations and changes are built here. The attributes and
+//! members of a group are encrypted with the secret params derived from its master key, and the
+//! requests are authorized with our zero-knowledge auth credential of the day. The group server
+//! returns a change signed, which is then sent to the members with the new revision of the group.
+//!
+//! Members are added as pending members: adding them right away needs a credential of their
+//! profile key, which only they can present. They become members when they accept the invite.
+
+use super::{GroupChange, Store};
+use crate::app::GroupData;
+use crate::util::utc_now_timestamp_msec;
+
+use anyhow::{anyhow, bail};
+use libsignal_service::configuration::{ServiceConfiguration, ServiceCredentials};
+use libsignal_service::groups_v2::{GroupsManager, InMemoryCredentialsCache};
+use libsignal_service::prelude::{GroupMasterKey, GroupSecretParams, ProfileKey};
+use libsignal_service::proto::access_control::AccessRequired;
+use libsignal_service::proto::group_attribute_blob::Content;
+use libsignal_service::proto::group_change::actions::{
+    AddPendingMemberAction, DeleteMemberAction, ModifyDescriptionAction, ModifyTitleAction,
+};
+use libsignal_service::proto::group_change::Actions;
+use libsignal_service::proto::member::Role;
+use libsignal_service::proto::{
+    AccessControl, Group, GroupAttributeBlob, GroupChange as SignedGroupChange, Member,
+    PendingMember,
+};
+use libsignal_service::push_service::{Endpoint, HttpAuth, HttpAuthOverride, PushService};
+use libsignal_service_hyper::push_service::HyperPushService;
+use presage::{ConfigStore, State};
+use prost::Message as _;
+use serde::Deserialize;
+use uuid::Uuid;
+use zkgroup::ServerPublicParams;
+
+const USER_AGENT: &str = concat!("gurk/", env!("CARGO_PKG_VERSION"));
+const GROUPS_PATH: &str = "/v1/groups/";
+
+/// Connection to the group server on behalf of our account
+pub struct GroupServer {
+    push_service: HyperPushService,
+    groups_manager: GroupsManager<HyperPushService, InMemoryCredentialsCache>,
+    server_public_params: ServerPublicParams,
+    uuid: Uuid,
+    profile_key: ProfileKey,
+}
+
+/// Versioned profile with the credential of the requested profile key
+#[derive(Deserialize)]
+struct CredentialProfile {
+    /// Base64 encoded `ProfileKeyCredentialResponse`
+    credential: Option<String>,
+}
+
+impl GroupServer {
+    /// Connects with the credentials of our registered account in the store.
+    pub fn new(store: &Store) -> anyhow::Result<Self> {
+        let (signal_servers, uuid, credentials, profile_key) = match store.state()? {
+            State::Registered {
+                signal_servers,
+                phone_number,
+                uuid,
+                password,
+                signaling_key,
+                device_id,
+                profile_key,
+                ..
+            } => {
+                let credentials = ServiceCredentials {
+                    uuid: Some(uuid),
+                    phonenumber: phone_number,
+                    password: Some(password),
+                    signaling_key: Some(signaling_key),
+                    device_id,
+                };
+                let profile_key = ProfileKey::create(profile_key);
+                (signal_servers, uuid, credentials, profile_key)
+            }
+            _ => bail!("not registered"),
+        };
+        let service_configuration: ServiceConfiguration = signal_servers.into();
+        let server_public_params = service_configuration.zkgroup_server_public_params;
+        let push_service = HyperPushService::new(
+            service_configuration,
+            Some(credentials),
+            USER_AGENT.to_string(),
+        );
+        let groups_manager = GroupsManager::new(
+            push_service.clone(),
+            InMemoryCredentialsCache::default(),
+            server_public_params,
+        );
+        Ok(Self {
+            push_service,
+            groups_manager,
+            server_public_params,
+            uuid,
+            profile_key,
+        })
+    }
+
+    /// Creates the group at revision 0 with us as administrator and the other members as
+    /// pending members.
+    pub async fn create(&mut self, title: &str, group_data: &GroupData) -> anyhow::Result<()> {
+        let secret_params = secret_params(group_data);
+        let member = Member {
+            role: Role::Administrator as i32,
+            presentation: self.profile_key_presentation(secret_params).await?,
+            ..Default::default()
+        };
+        let pending_members = group_data
+            .members
+            .iter()
+            .filter(|&&uuid| uuid != self.uuid)
+            .map(|&uuid| self.pending_member(secret_params, uuid))
+            .collect::<anyhow::Result<_>>()?;
+        let group = Group {
+            public_key: bincode::serialize(&secret_params.get_public_params())?,
+            title: encrypt_attribute(secret_params, Content::Title(title.to_string()))?,
+            access_control: Some(AccessControl {
+                attributes: AccessRequired::Member as i32,
+                members: AccessRequired::Member as i32,
+                add_from_invite_link: AccessRequired::Unsatisfiable as i32,
+            }),
+            revision: group_data.revision,
+            members: vec![member],
+            pending_members,
+            ..Default::default()
+        };
+
+        let auth = self.authorization(secret_params).await?;
+        let _: Group = self
+            .push_service
+            .put_protobuf(
+                Endpoint::Storage,
+                GROUPS_PATH,
+                HttpAuthOverride::Identified(auth),
+                group,
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Applies the change to the group at the next revision.
+    ///
+    /// Returns the serialized change signed by the group server.
+    pub async fn change(
+        &mut self,
+        group_data: &GroupData,
+        change: &GroupChange,
+    ) -> anyhow::Result<Vec<u8>> {
+        let secret_params = secret_params(group_data);
+        let mut actions = Actions {
+            revision: group_data.revision + 1,
+            ..Default::default()
+        };
+        match change {
+            GroupChange::Create => bail!("the group exists already"),
+            GroupChange::Rename(title) => {
+                let title = encrypt_attribute(secret_params, Content::Title(title.clone()))?;
+                actions.modify_title = Some(ModifyTitleAction { title });
+            }
+            GroupChange::Describe(description) => {
+                let description =
+                    encrypt_attribute(secret_params, Content::Description(description.clone()))?;
+                actions.modify_description = Some(ModifyDescriptionAction { description });
+            }
+            GroupChange::AddMember(uuid) => {
+                let added = self.pending_member(secret_params, *uuid)?;
+                actions
+                    .add_pending_members
+                    .push(AddPendingMemberAction { added: Some(added) });
+            }
+            GroupChange::RemoveMember(uuid) => {
+                let deleted_user_id = encrypt_uuid(secret_params, *uuid)?;
+                actions
+                    .delete_members
+                    .push(DeleteMemberAction { deleted_user_id });
+            }
+            GroupChange::Leave => {
+                let deleted_user_id = encrypt_uuid(secret_params, self.uuid)?;
+                actions
+                    .delete_members
+                    .push(DeleteMemberAction { deleted_user_id });
+            }
+        }
+
+        let auth = self.authorization(secret_params).await?;
+        let signed_change: SignedGroupChange = self
+            .push_service
+            .patch_protobuf(
+                Endpoint::Storage,
+                GROUPS_PATH,
+                HttpAuthOverride::Identified(auth),
+                actions,
+            )
+            .await?;
+        Ok(signed_change.encode_to_vec())
+    }
+
+    async fn authorization(
+        &mut self,
+        secret_params: GroupSecretParams,
+    ) -> anyhow::Result<HttpAuth> {
+        Ok(self
+            .groups_manager
+            .get_authorization_for_today(self.uuid, secret_params)
+            .await?)
+    }
+
+    /// Presentation of the credential of our profile key, which proves to the group server that
+    /// the encrypted profile key in it is ours.
+    async fn profile_key_presentation(
+        &mut self,
+        secret_params: GroupSecretParams,
+    ) -> anyhow::Result<Vec<u8>> {
+        let uuid = *self.uuid.as_bytes();
+        let context = self
+            .server_public_params
+            .create_profile_key_credential_request_context(rand::random(), uuid, self.profile_key);
+        let version = self.profile_key.get_profile_key_version(uuid);
+        let path = format!(
+            "/v1/profile/{}/{}/{}",
+            self.uuid,
+            String::from_utf8_lossy(&version.get_bytes()),
+            hex::encode(bincode::serialize(&context.get_request())?),
+        );
+        let profile: CredentialProfile = self
+            .push_service
+            .get_json(Endpoint::Service, &path, HttpAuthOverride::NoOverride)
+            .await?;
+        let response = profile
+            .credential
+            .ok_or_else(|| anyhow!("no profile key credential in our profile"))?;
+        let response = bincode::deserialize(&base64::decode(response)?)?;
+        let credential = self
+            .server_public_params
+            .receive_profile_key_credential(&context, &response)
+            .map_err(|_| anyhow!("invalid profile key credential"))?;
+        let presentation = self
+            .server_public_params
+            .create_profile_key_credential_presentation(rand::random(), secret_params, credential);
+        Ok(bincode::serialize(&presentation)?)
+    }
+
+    /// Member invited by us
+    fn pending_member(
+        &self,
+        secret_params: GroupSecretParams,
+        uuid: Uuid,
+    ) -> anyhow::Result<PendingMember> {
+        Ok(PendingMember {
+            member: Some(Member {
+                user_id: encrypt_uuid(secret_params, uuid)?,
+                role: Role::Default as i32,
+                ..Default::default()
+            }),
+            added_by_user_id: encrypt_uuid(secret_params, self.uuid)?,
+            timestamp: utc_now_timestamp_msec(),
+        })
+    }
+}
+
+fn secret_params(group_data: &GroupData) -> GroupSecretParams {
+    GroupSecretParams::derive_from_master_key(GroupMasterKey::new(group_data.master_key_bytes))
+}
+
+fn encrypt_uuid(secret_params: GroupSecretParams, uuid: Uuid) -> anyhow::Result<Vec<u8>> {
+    Ok(bincode::serialize(
+        &secret_params.encrypt_uuid(*uuid.as_bytes()),
+    )?)
+}
+
+fn encrypt_attribute(
+    secret_params: GroupSecretParams,
+    content: Content,
+) -> anyhow::Result<Vec<u8>> {
+    let blob = GroupAttributeBlob {
+        content: Some(content),
+    };
+    secret_params
+        .encrypt_blob(rand::random(), &blob.encode_to_vec())
+        .map_err(|_| anyhow!("failed to encrypt group attribute"))
+}
@@ -1,21 +1,186 @@
-use crate::app::AppData;
+use crate::app::{AppData, Channel, ChannelId, Identity, Message, Receipt};
 use crate::cursor::Cursor;
+use crate::outbox::Outbox;
+use crate::search::SearchResult;
 
-use anyhow::Context;
-use log::info;
+use anyhow::{bail, Context};
+use log::{info, warn};
+use serde::Serialize;
 use uuid::Uuid;
 
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::ffi::OsString;
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{BufReader, BufWriter, Read as _, Write};
 use std::path::{Path, PathBuf};
 
+mod encrypted;
+mod migration;
+mod sqlite;
+
+pub use encrypted::{read_passphrase, EncryptedStorage};
+pub use sqlite::SqliteStorage;
+
+/// Number of messages per channel which are loaded at once by storages supporting pagination.
+pub const MESSAGES_PAGE_SIZE: usize = 200;
+
 /// Data storage abstraction
 ///
-/// Note: at the moment, we only support the full saving and loading of app data. Later, we plan
-/// to split it in more granular operations.
+/// Every storage supports the full saving and loading of app data. Storages which can persist
+/// single changes cheaply, also implement the granular operations and return `true` from
+/// `is_incremental`. For the other storages, the app data is saved fully on every change instead.
 pub trait Storage {
     fn save_app_data(&self, data: &AppData) -> anyhow::Result<()>;
 
+    /// Whether the granular operations below are supported.
+    ///
+    /// If `false` (default), the granular operations are never called, and `save_app_data` is
+    /// used instead.
+    fn is_incremental(&self) -> bool {
+        false
+    }
+
+    /// Appends a new message to the channel, or updates it if it was already stored.
+    ///
+    /// If the channel is not stored yet, it is stored before the message.
+    fn append_message(&self, _channel: &Channel, _message: &Message) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Updates the receipt of the message identified by its arrival timestamp.
+    fn update_receipt(
+        &self,
+        _channel_id: ChannelId,
+        _arrived_at: u64,
+        _receipt: Receipt,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Removes the message identified by its arrival timestamp, incl. its reactions and
+    /// attachments.
+    fn delete_message(&self, _channel_id: ChannelId, _arrived_at: u64) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Removes all disappearing messages which expired at `now` (in milliseconds), also the ones
+    /// which are not loaded, or which are kept in backups.
+    ///
+    /// Returns the removed messages, s.t. the files of their attachments can be removed.
+    fn delete_expired_messages(&self, _now: u64) -> anyhow::Result<Vec<(ChannelId, Message)>> {
+        Ok(Vec::new())
+    }
+
+    /// Adds or replaces the reaction of `from_id` on the message identified by its arrival
+    /// timestamp.
+    ///
+    /// If `emoji` is `None`, the reaction is removed.
+    fn upsert_reaction(
+        &self,
+        _channel_id: ChannelId,
+        _arrived_at: u64,
+        _from_id: Uuid,
+        _emoji: Option<&str>,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Adds or updates the channel without its messages: name, group data and unread counter.
+    ///
+    /// A new channel is added at the end of the channel list.
+    fn update_channel_meta(&self, _channel: &Channel) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Removes the channel with all its messages, reactions and attachments.
+    fn delete_channel(&self, _channel_id: ChannelId) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Updates the order of channels to the order of the given ids.
+    fn update_channel_order(&self, _channel_ids: &[ChannelId]) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Adds or updates the name of a user.
+    fn upsert_name(&self, _id: Uuid, _name: &str) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Replaces the stored words used in sent messages.
+    fn update_used_words(&self, _used_words: &HashSet<String>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Replaces the stored outbox.
+    fn update_outbox(&self, _outbox: &Outbox) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Replaces the stored blocked contacts and groups, and the synced blocklist.
+    fn update_blocked(
+        &self,
+        _blocked: &BTreeSet<ChannelId>,
+        _synced_blocked: &BTreeSet<ChannelId>,
+    ) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Replaces the stored identities of contacts.
+    fn update_identities(&self, _identities: &BTreeMap<Uuid, Identity>) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Loads up to `limit` messages of the channel which are older than the message that arrived
+    /// at `before`, in chronological order.
+    ///
+    /// Storages which load only the latest messages of each channel in `load_app_data`, use this
+    /// method to load the older history on demand. The default implementation returns no messages,
+    /// which is correct for storages loading the whole history at once.
+    fn load_messages(
+        &self,
+        _channel_id: ChannelId,
+        _before: u64,
+        _limit: usize,
+    ) -> anyhow::Result<Vec<Message>> {
+        Ok(Vec::new())
+    }
+
+    /// Loads the message of the channel identified by its arrival timestamp.
+    ///
+    /// Used to update messages which are not loaded (see `load_messages`). The default
+    /// implementation returns `None`, which is correct for storages loading the whole history at
+    /// once.
+    fn load_message(
+        &self,
+        _channel_id: ChannelId,
+        _arrived_at: u64,
+    ) -> anyhow::Result<Option<Message>> {
+        Ok(None)
+    }
+
+    /// Searches the bodies of all stored messages, and returns up to `limit` matches, newest
+    /// first.
+    ///
+    /// A message matches if every word of the query is a prefix of a word in its body. Storages
+    /// which don't load the whole history with the app data, should implement this method, s.t.
+    /// messages which are not loaded yet are found. The default implementation returns `None`: the
+    /// loaded messages are searched only.
+    fn search_messages(
+        &self,
+        _query: &str,
+        _limit: usize,
+    ) -> anyhow::Result<Option<Vec<SearchResult>>> {
+        Ok(None)
+    }
+
+    /// Securely removes the leftovers of data which was stored unencrypted, e.g. backups.
+    ///
+    /// Called by `EncryptedStorage` once all stored data was encrypted.
+    fn erase_plaintext(&self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
     /// Loads the app data.
     ///
     /// In case, the app data exists, but can't be deserialized/loaded, this method should fail with
@@ -28,14 +193,75 @@ pub trait Storage {
 }
 
 /// Storage based on a single JSON file.
+///
+/// The file contains the schema version of the app data, s.t. data written by an older version
+/// of gurk is migrated when loaded (see `migration`).
+///
+/// The file is never written in place: the data is written into a temporary file first, which
+/// then replaces the data file. The replaced data files are kept as rotated backups
+/// `<data_path>.bak.1` (newest) to `<data_path>.bak.<backups>` (oldest).
 pub struct JsonStorage {
     data_path: PathBuf,
     fallback_data_path: Option<PathBuf>,
+    backups: usize,
+    is_read_only: bool,
 }
 
 impl Storage for JsonStorage {
     fn save_app_data(&self, data: &AppData) -> anyhow::Result<()> {
-        Self::save_to(data, &self.data_path)
+        self.check_writable()?;
+        let tmp_path = Self::write_tmp(data, &self.data_path)?;
+        self.rotate_backups()?;
+        replace_file(&tmp_path, &self.data_path)
+    }
+
+    fn erase_plaintext(&self) -> anyhow::Result<()> {
+        self.check_writable()?;
+        // the data file itself was replaced by the encrypted data
+        for path in self.files() {
+            if path != self.data_path {
+                remove_file_securely(&path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn delete_expired_messages(&self, now: u64) -> anyhow::Result<Vec<(ChannelId, Message)>> {
+        self.check_writable()?;
+        let mut expired: Vec<(ChannelId, Message)> = Vec::new();
+        let paths = std::iter::once(self.data_path.clone())
+            .chain((1..=self.backups).map(|idx| self.backup_path(idx)));
+        for path in paths.filter(|path| path.exists()) {
+            let mut data = match Self::load_app_data_from(&path) {
+                Ok(data) => data,
+                Err(e) => {
+                    warn!("failed to load app data from '{}': {}", path.display(), e);
+                    continue;
+                }
+            };
+            let mut is_changed = false;
+            for channel in &mut data.channels.items {
+                let messages = std::mem::take(&mut channel.messages.items);
+                let (expired_messages, messages): (Vec<_>, Vec<_>) = messages
+                    .into_iter()
+                    .partition(|message| message.expires_at().map_or(false, |at| at <= now));
+                channel.messages.items = messages;
+                is_changed |= !expired_messages.is_empty();
+                for message in expired_messages {
+                    // the same message is usually also contained in the other backups
+                    let is_known = expired.iter().any(|(channel_id, known)| {
+                        *channel_id == channel.id && known.arrived_at == message.arrived_at
+                    });
+                    if !is_known {
+                        expired.push((channel.id, message));
+                    }
+                }
+            }
+            if is_changed {
+                Self::save_to(&data, &path)?;
+            }
+        }
+        Ok(expired)
     }
 
     fn load_app_data(&self, user_id: Uuid, user_name: String) -> anyhow::Result<AppData> {
@@ -63,18 +289,132 @@ impl JsonStorage {
         Self {
             data_path,
             fallback_data_path,
+            backups: 0,
+            is_read_only: false,
         }
     }
 
-    fn save_to(data: &AppData, data_path: impl AsRef<Path>) -> anyhow::Result<()> {
-        let f = std::io::BufWriter::new(File::create(data_path)?);
-        serde_json::to_writer(f, data)?;
+    /// Makes the storage read-only: the app data is loaded without modifying any file, e.g. a
+    /// temporary file left over by an interrupted save is loaded, but neither recovered nor
+    /// removed, and saving fails.
+    pub fn read_only(self) -> Self {
+        Self {
+            is_read_only: true,
+            ..self
+        }
+    }
+
+    fn check_writable(&self) -> anyhow::Result<()> {
+        if self.is_read_only {
+            bail!("storage is read-only");
+        }
         Ok(())
     }
 
+    /// Sets the number of rotated backups of the data file to keep (default: 0).
+    ///
+    /// If the data file can't be loaded, the newest backup which can be loaded is used instead.
+    pub fn with_backups(self, backups: usize) -> Self {
+        Self { backups, ..self }
+    }
+
+    /// Saves the data atomically into the data path, without rotating backups.
+    fn save_to(data: &AppData, data_path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let data_path = data_path.as_ref();
+        let tmp_path = Self::write_tmp(data, data_path)?;
+        replace_file(&tmp_path, data_path)
+    }
+
+    /// Writes the data into a temporary file next to the data path and syncs it to disk.
+    ///
+    /// Returns the path of the temporary file.
+    fn write_tmp(data: &AppData, data_path: &Path) -> anyhow::Result<PathBuf> {
+        let tmp_path = path_with_suffix(data_path, ".tmp");
+        let mut f = BufWriter::new(File::create(&tmp_path)?);
+        let data = VersionedAppData {
+            version: migration::CURRENT_VERSION,
+            data,
+        };
+        serde_json::to_writer(&mut f, &data)?;
+        f.flush()?;
+        f.get_ref().sync_all()?;
+        Ok(tmp_path)
+    }
+
+    /// Paths of all files which may contain app data: the data file, its temporary file, the
+    /// backups and the fallback data file.
+    fn files(&self) -> Vec<PathBuf> {
+        let mut files = vec![
+            self.data_path.clone(),
+            path_with_suffix(&self.data_path, ".tmp"),
+        ];
+        files.extend((1..=self.backups).map(|idx| self.backup_path(idx)));
+        files.extend(self.fallback_data_path.clone());
+        files
+    }
+
+    fn backup_path(&self, idx: usize) -> PathBuf {
+        path_with_suffix(&self.data_path, &format!(".bak.{}", idx))
+    }
+
+    /// Copies the data file to the newest backup, and shifts the older backups.
+    ///
+    /// The data file is copied rather than moved, s.t. a valid data file exists at any time. A data
+    /// file which can't be loaded (e.g. after falling back to a backup on load) is not backed up,
+    /// s.t. it doesn't push the valid backups out.
+    fn rotate_backups(&self) -> anyhow::Result<()> {
+        if self.backups == 0 || !self.data_path.exists() {
+            return Ok(());
+        }
+        if let Err(e) = Self::load_app_data_from(&self.data_path) {
+            warn!("not backing up data file which can't be loaded: {}", e);
+            return Ok(());
+        }
+        for idx in (1..self.backups).rev() {
+            let backup_path = self.backup_path(idx);
+            if backup_path.exists() {
+                std::fs::rename(&backup_path, self.backup_path(idx + 1))?;
+            }
+        }
+        let backup_path = self.backup_path(1);
+        let tmp_backup_path = path_with_suffix(&backup_path, ".tmp");
+        std::fs::copy(&self.data_path, &tmp_backup_path)?;
+        File::open(&tmp_backup_path)?.sync_all()?;
+        replace_file(&tmp_backup_path, &backup_path)
+    }
+
+    /// Loads the newest backup which can be loaded.
+    fn load_newest_backup(&self) -> Option<AppData> {
+        (1..=self.backups)
+            .map(|idx| self.backup_path(idx))
+            .filter(|backup_path| backup_path.exists())
+            .find_map(|backup_path| match Self::load_app_data_from(&backup_path) {
+                Ok(data) => {
+                    warn!("loaded app data from backup: {}", backup_path.display());
+                    Some(data)
+                }
+                Err(e) => {
+                    warn!(
+                        "failed to load backup from '{}': {}",
+                        backup_path.display(),
+                        e
+                    );
+                    None
+                }
+            })
+    }
+
     fn load_app_data_impl(&self) -> anyhow::Result<AppData> {
+        if let Some(data) = self.recover_tmp()? {
+            return Ok(data);
+        }
+
         let mut data_path = &self.data_path;
         if !data_path.exists() {
+            // the data file was removed, but maybe not its backups
+            if let Some(data) = self.load_newest_backup() {
+                return Ok(data);
+            }
             // try also to load from a fallback (legacy) data path
             if let Some(fallback_data_path) = self.fallback_data_path.as_ref() {
                 data_path = fallback_data_path;
@@ -83,33 +423,243 @@ impl JsonStorage {
 
         // if data file exists, be conservative and fail rather than overriding and losing the messages
         if data_path.exists() {
-            Self::load_app_data_from(&data_path).with_context(|| {
-                format!(
-                    "failed to load stored data from '{}':\n\
-            This might happen due to incompatible data model when Gurk is upgraded.\n\
-            Please consider to backup your messages and then remove the store.",
-                    data_path.display()
-                )
-            })
+            Self::load_app_data_from(&data_path)
+                .or_else(|e| {
+                    warn!("failed to load app data: {}", e);
+                    self.load_newest_backup().ok_or(e)
+                })
+                .with_context(|| {
+                    format!(
+                        "failed to load stored data from '{}':\n\
+            The stored data was not modified. If it was written by a newer version of Gurk,\n\
+            please upgrade. Otherwise, please consider to report an issue.",
+                        data_path.display()
+                    )
+                })
         } else {
             Ok(Self::load_app_data_from(data_path).unwrap_or_default())
         }
     }
 
+    /// Checks the temporary file left over by an interrupted save.
+    ///
+    /// The temporary file is synced before it replaces the data file, so if it can be loaded, it
+    /// contains the newest data, and it replaces the data file. Otherwise, it is incomplete and
+    /// removed.
+    fn recover_tmp(&self) -> anyhow::Result<Option<AppData>> {
+        let tmp_path = path_with_suffix(&self.data_path, ".tmp");
+        if !tmp_path.exists() {
+            return Ok(None);
+        }
+        match Self::load_app_data_from(&tmp_path) {
+            Ok(data) if self.is_read_only => Ok(Some(data)),
+            Err(_) if self.is_read_only => Ok(None),
+            Ok(data) => {
+                warn!("recovered app data from: {}", tmp_path.display());
+                replace_file(&tmp_path, &self.data_path)?;
+                Ok(Some(data))
+            }
+            Err(e) => {
+                warn!(
+                    "removing incomplete app data at '{}': {}",
+                    tmp_path.display(),
+                    e
+                );
+                std::fs::remove_file(&tmp_path)?;
+                Ok(None)
+            }
+        }
+    }
+
     fn load_app_data_from(data_path: impl AsRef<Path>) -> anyhow::Result<AppData> {
         info!("loading app data from: {}", data_path.as_ref().display());
         let f = BufReader::new(File::open(data_path)?);
-        let mut data: AppData = serde_json::from_reader(f)?;
+        let data: serde_json::Value = serde_json::from_reader(f)?;
+        let mut data: AppData = serde_json::from_value(migration::migrate(data)?)?;
         data.input.cursor = Cursor::end(&data.input.data);
         Ok(data)
     }
 }
 
+/// Replaces the file at `path` by the file at `tmp_path`.
+fn replace_file(tmp_path: &Path, path: &Path) -> anyhow::Result<()> {
+    std::fs::rename(tmp_path, path)?;
+    sync_dir(path)
+}
+
+/// Syncs the directory containing `path`, which makes a rename in it durable.
+#[cfg(unix)]
+fn sync_dir(path: &Path) -> anyhow::Result<()> {
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_path: &Path) -> anyhow::Result<()> {
+    Ok(())
+}
+
+/// Overwrites the file with zeros before removing it, if it exists.
+///
+/// Note that on copy-on-write file systems and flash storage, the old content might still be
+/// recoverable.
+fn remove_file_securely(path: &Path) -> anyhow::Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    info!("removing unencrypted data: {}", path.display());
+    let mut f = std::fs::OpenOptions::new().write(true).open(path)?;
+    let len = f.metadata()?.len();
+    std::io::copy(&mut std::io::repeat(0).take(len), &mut f)?;
+    f.sync_all()?;
+    drop(f);
+    std::fs::remove_file(path)?;
+    sync_dir(path)
+}
+
+fn path_with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut path = OsString::from(path);
+    path.push(suffix);
+    path.into()
+}
+
+/// App data together with its schema version, as stored in the JSON file
+#[derive(Serialize)]
+struct VersionedAppData<'a> {
+    version: u64,
+    #[serde(flatten)]
+    data: &'a AppData,
+}
+
 #[cfg(test)]
 pub mod test {
     use super::Storage;
 
-    use crate::app::AppData;
+    use crate::app::{AppData, Channel, ChannelId, GroupData, Identity, Message, Receipt};
+    use crate::outbox::{Outbox, OutboxItem, QueuedItem};
+    use crate::signal::{Attachment, GroupChange, Mention, Sticker};
+    use crate::util::{FilteredStatefulList, StatefulList};
+
+    use uuid::Uuid;
+
+    /// App data of the user with a contact, covering every kind of stored data: a channel with
+    /// the contact, a group with both of them, the outbox, blocked channels and identities.
+    pub fn test_app_data(user_id: Uuid, user_name: &str) -> AppData {
+        let contact_id = Uuid::new_v4();
+        let group_id = [42; 32];
+        let hi = Message {
+            attachments: vec![Attachment {
+                id: "some-id".to_string(),
+                content_type: "image/png".to_string(),
+                filename: "/tmp/signal-some-id.png".into(),
+                size: 42,
+            }],
+            reactions: vec![(user_id, "👍".to_string())],
+            receipt: Receipt::Delivered,
+            ..Message::text(contact_id, "hi\nthere", 1)
+        };
+        let hello = Message {
+            quote: Some(Box::new(Message::text(contact_id, "hi\nthere", 1))),
+            expires_in: Some(604800),
+            expire_started_at: Some(2),
+            ..Message::text(user_id, "hello <world>", 2)
+        };
+        let space_monkey = Message {
+            receipt: Receipt::Delivered,
+            mentions: vec![Mention {
+                start: 0,
+                length: 1,
+                uuid: user_id,
+            }],
+            ..Message::text(contact_id, "\u{fffc} is the space monkey", 3)
+        };
+        let sticker = Message {
+            message: None,
+            attachments: vec![Attachment {
+                id: "sticker-id".to_string(),
+                content_type: "image/webp".to_string(),
+                filename: "/tmp/signal-sticker-id.webp".into(),
+                size: 42,
+            }],
+            sticker: Some(Sticker {
+                pack_id: "0a1b".to_string(),
+                sticker_id: 7,
+                emoji: Some("🐒".to_string()),
+            }),
+            ..Message::text(user_id, "", 4)
+        };
+        AppData {
+            names: [
+                (user_id, user_name.to_string()),
+                (contact_id, "Marla Singer".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+            used_words: ["soap".to_string()].into_iter().collect(),
+            channels: FilteredStatefulList::_with_items(vec![
+                Channel {
+                    id: ChannelId::User(contact_id),
+                    name: "Marla Singer".to_string(),
+                    group_data: None,
+                    messages: StatefulList::with_items(vec![hi, hello]),
+                    unread_messages: 1,
+                    expire_timer: Some(604800),
+                    is_message_request: true,
+                    typing: Default::default(),
+                },
+                Channel {
+                    id: ChannelId::Group(group_id),
+                    name: "Project Mayhem".to_string(),
+                    group_data: Some(GroupData {
+                        master_key_bytes: [1; 32],
+                        members: vec![user_id, contact_id],
+                        revision: 3,
+                    }),
+                    messages: StatefulList::with_items(vec![space_monkey, sticker]),
+                    unread_messages: 0,
+                    expire_timer: None,
+                    is_message_request: false,
+                    typing: Default::default(),
+                },
+            ]),
+            outbox: Outbox::with_items(vec![
+                QueuedItem {
+                    id: 3,
+                    item: OutboxItem::Reaction {
+                        channel_id: ChannelId::User(contact_id),
+                        target_author: contact_id,
+                        target_arrived_at: 1,
+                        emoji: "👍".to_string(),
+                        remove: false,
+                    },
+                    attempts: 1,
+                },
+                QueuedItem {
+                    id: 4,
+                    item: OutboxItem::GroupChange {
+                        channel_id: ChannelId::Group(group_id),
+                        change: GroupChange::Rename("Paper Street Soap Company".to_string()),
+                    },
+                    attempts: 0,
+                },
+            ]),
+            blocked: [ChannelId::Group(group_id)].into_iter().collect(),
+            identities: [(
+                contact_id,
+                Identity {
+                    key: vec![5; 33],
+                    is_verified: true,
+                },
+            )]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        }
+    }
 
     /// In-memory storage used for testing.
     pub struct InMemoryStorage {}
@@ -137,11 +687,12 @@ pub mod test {
 #[cfg(test)]
 mod tests {
     use crate::{
-        app::{BoxData, Channel, ChannelId, TypingSet},
+        app::{BoxData, Channel, ChannelId},
         cursor::Cursor,
         util::FilteredStatefulList,
     };
 
+    use super::test::test_app_data;
     use super::*;
     use tempfile::NamedTempFile;
 
@@ -233,8 +784,11 @@ mod tests {
                 group_data: None,
                 messages: Default::default(),
                 unread_messages: 0,
-                typing: TypingSet::SingleTyping(false),
+                expire_timer: None,
+                is_message_request: false,
+                typing: Default::default(),
             }]),
+            ..Default::default()
         };
 
         let file = NamedTempFile::new()?;
@@ -246,4 +800,188 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_json_storage_load_legacy_app_data() -> anyhow::Result<()> {
+        let file = NamedTempFile::new()?;
+        std::fs::write(
+            file.path(),
+            include_str!("storage/fixtures/app_data_v0.json"),
+        )?;
+
+        let storage = JsonStorage::new(file.path().to_owned(), None);
+        let user_id: Uuid = "a955d20f-6b83-4e69-846e-a99b1779ff7a".parse()?;
+        let app_data = storage.load_app_data(user_id, "Alice".to_string())?;
+        assert_eq!(app_data.channels.items.len(), 2);
+        assert_eq!(app_data.channels.items[0].messages.items.len(), 2);
+
+        storage.save_app_data(&app_data)?;
+        let content: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(file.path())?)?;
+        assert_eq!(content["version"], migration::CURRENT_VERSION);
+        assert_eq!(
+            storage.load_app_data(user_id, "Alice".to_string())?,
+            app_data
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_storage_rotate_backups() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let data_path = dir.path().join("gurk.data.json");
+        let storage = JsonStorage::new(data_path.clone(), None).with_backups(2);
+
+        let user_id = Uuid::new_v4();
+        for name in ["Tyler", "Marla", "Robert", "Jack"] {
+            storage.save_app_data(&test_app_data(user_id, name))?;
+        }
+
+        let load = |path: PathBuf| JsonStorage::load_app_data_from(path).unwrap();
+        assert_eq!(load(data_path.clone()).names[&user_id], "Jack");
+        assert_eq!(load(storage.backup_path(1)).names[&user_id], "Robert");
+        assert_eq!(load(storage.backup_path(2)).names[&user_id], "Marla");
+        assert!(!storage.backup_path(3).exists());
+        assert!(!path_with_suffix(&data_path, ".tmp").exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_storage_delete_expired_messages_from_backups() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let data_path = dir.path().join("gurk.data.json");
+        let storage = JsonStorage::new(data_path.clone(), None).with_backups(2);
+
+        let user_id = Uuid::new_v4();
+        let mut app_data = test_app_data(user_id, "Tyler");
+        storage.save_app_data(&app_data)?;
+        storage.save_app_data(&app_data)?;
+        let channel = &mut app_data.channels.items[0];
+        let expiring = channel.messages.items.pop().unwrap();
+        let channel_id = channel.id;
+        storage.save_app_data(&app_data)?;
+
+        let expires_at = 2 + 604800 * 1000;
+        assert!(storage.delete_expired_messages(expires_at - 1)?.is_empty());
+        let expired = storage.delete_expired_messages(expires_at)?;
+        assert_eq!(expired, [(channel_id, expiring)]);
+
+        let load = |path: PathBuf| JsonStorage::load_app_data_from(path).unwrap();
+        for path in [data_path, storage.backup_path(1), storage.backup_path(2)] {
+            assert_eq!(
+                load(path).channels.items[0].messages.items,
+                app_data.channels.items[0].messages.items
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_storage_load_backup_on_corrupted_data() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let data_path = dir.path().join("gurk.data.json");
+        let storage = JsonStorage::new(data_path.clone(), None).with_backups(2);
+
+        let user_id = Uuid::new_v4();
+        storage.save_app_data(&test_app_data(user_id, "Tyler"))?;
+        storage.save_app_data(&test_app_data(user_id, "Marla"))?;
+
+        // truncated data file falls back to the newest backup
+        std::fs::write(&data_path, "{\"channels\":")?;
+        let data = storage.load_app_data(Uuid::new_v4(), "Jack".to_string())?;
+        assert_eq!(data.names[&user_id], "Tyler");
+
+        // corrupted data file is not rotated into the backups
+        storage.save_app_data(&test_app_data(user_id, "Robert"))?;
+        let backup = JsonStorage::load_app_data_from(&storage.backup_path(1))?;
+        assert_eq!(backup.names[&user_id], "Tyler");
+        assert!(!storage.backup_path(2).exists());
+
+        // valid data file is rotated again
+        storage.save_app_data(&test_app_data(user_id, "Bob"))?;
+        let backup = JsonStorage::load_app_data_from(&storage.backup_path(1))?;
+        assert_eq!(backup.names[&user_id], "Robert");
+        let backup = JsonStorage::load_app_data_from(&storage.backup_path(2))?;
+        assert_eq!(backup.names[&user_id], "Tyler");
+
+        // without a loadable backup, loading fails
+        std::fs::write(&data_path, "")?;
+        let storage = JsonStorage::new(data_path, None);
+        assert!(storage
+            .load_app_data(Uuid::new_v4(), "Jack".to_string())
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_storage_load_backup_on_missing_data() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let data_path = dir.path().join("gurk.data.json");
+        let storage = JsonStorage::new(data_path.clone(), None).with_backups(1);
+
+        let user_id = Uuid::new_v4();
+        storage.save_app_data(&test_app_data(user_id, "Tyler"))?;
+        storage.save_app_data(&test_app_data(user_id, "Marla"))?;
+
+        std::fs::remove_file(&data_path)?;
+        let data = storage.load_app_data(Uuid::new_v4(), "Jack".to_string())?;
+        assert_eq!(data.names[&user_id], "Tyler");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_storage_load_leftover_tmp() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let data_path = dir.path().join("gurk.data.json");
+        let tmp_path = path_with_suffix(&data_path, ".tmp");
+        let storage = JsonStorage::new(data_path.clone(), None).with_backups(1);
+
+        let user_id = Uuid::new_v4();
+        storage.save_app_data(&test_app_data(user_id, "Tyler"))?;
+
+        // simulate a crash before the written temporary file replaced the data file
+        JsonStorage::write_tmp(&test_app_data(user_id, "Marla"), &data_path)?;
+        let data = storage.load_app_data(Uuid::new_v4(), "Jack".to_string())?;
+        assert_eq!(data.names[&user_id], "Marla");
+        assert!(!tmp_path.exists());
+        let data = JsonStorage::load_app_data_from(&data_path)?;
+        assert_eq!(data.names[&user_id], "Marla");
+
+        // simulate a crash while writing the temporary file
+        std::fs::write(&tmp_path, "{\"channels\":")?;
+        let data = storage.load_app_data(Uuid::new_v4(), "Jack".to_string())?;
+        assert_eq!(data.names[&user_id], "Marla");
+        assert!(!tmp_path.exists());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_json_storage_read_only() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let data_path = dir.path().join("gurk.data.json");
+        let tmp_path = path_with_suffix(&data_path, ".tmp");
+        let storage = JsonStorage::new(data_path.clone(), None).with_backups(1);
+
+        let user_id = Uuid::new_v4();
+        storage.save_app_data(&test_app_data(user_id, "Tyler"))?;
+        JsonStorage::write_tmp(&test_app_data(user_id, "Marla"), &data_path)?;
+
+        // the leftover temporary file is loaded, but not recovered
+        let storage = storage.read_only();
+        let data = storage.load_app_data(Uuid::new_v4(), "Jack".to_string())?;
+        assert_eq!(data.names[&user_id], "Marla");
+        assert!(tmp_path.exists());
+        let data = JsonStorage::load_app_data_from(&data_path)?;
+        assert_eq!(data.names[&user_id], "Tyler");
+
+        assert!(storage.save_app_data(&data).is_err());
+
+        Ok(())
+    }
 }
@@ -0,0 +1,946 @@
+use super::{Storage, MESSAGES_PAGE_SIZE};
+use crate::app::{AppData, Channel, ChannelId, GroupData, Identity, Message, Receipt};
+use crate::config::Config;
+use crate::outbox::{Outbox, OutboxItem, QueuedItem};
+use crate::signal::GroupChange;
+use crate::util::StatefulList;
+
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockDecrypt, BlockEncrypt, NewBlockCipher};
+use aes::Aes256;
+use anyhow::{anyhow, bail, Context as _};
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::{Aead, NewAead, Payload};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use log::info;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use std::cell::Cell;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Prefix of an encrypted value; followed by base64 encoded nonce and ciphertext.
+const ENCRYPTED_PREFIX: &str = "gurk-enc:";
+const KEY_LEN: usize = 32;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+/// Known plaintext stored encrypted in the key file to detect a wrong passphrase.
+const CHECK_PLAINTEXT: &str = "gurk";
+
+/// Storage which encrypts personal data before passing it to the inner storage.
+///
+/// Message bodies (incl. quotes), sticker emojis, attachment paths, channel names, user names, used
+/// words and the names and descriptions of queued group changes are encrypted with
+/// XChaCha20-Poly1305. Like reactions, the rest of the outbox is stored unencrypted: it refers to
+/// messages by their timestamps only.
+/// Every ciphertext is bound to its table, column and record as associated data, s.t. encrypted
+/// values can't be swapped between records. The UUIDs of mentioned users and group members keep
+/// their type: they are encrypted deterministically as a single AES-256 block, s.t. equal UUIDs
+/// stay equal. The keys are derived from a passphrase with Argon2id. The salt and the key
+/// derivation parameters are stored in a separate key file.
+///
+/// Values which are not encrypted yet (e.g. when the encryption was turned on for existing data)
+/// are loaded as is, and then the whole stored history is saved encrypted page by page, and the
+/// plaintext leftovers of the inner storage are erased. From then on, values which are not
+/// encrypted are rejected. Until then, the UUIDs of a record are decrypted only if its texts are
+/// encrypted, since they are written together, but can't be told apart from plaintext UUIDs.
+///
+/// The inner storage can't search the encrypted message bodies, so only the loaded messages are
+/// searched.
+pub struct EncryptedStorage {
+    inner: Box<dyn Storage>,
+    cipher: XChaCha20Poly1305,
+    uuid_cipher: Aes256,
+    key_path: PathBuf,
+    key_file: KeyFile,
+    /// Whether all stored data was encrypted; set in the key file
+    is_data_encrypted: Cell<bool>,
+    /// Whether unencrypted stored data is loaded as is, without encrypting it
+    is_read_only: bool,
+}
+
+/// Key derivation parameters stored in the key file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct KeyFile {
+    salt: String,
+    m_cost: u32,
+    t_cost: u32,
+    p_cost: u32,
+    /// Encrypted `CHECK_PLAINTEXT`
+    check: String,
+    /// Set after all stored data was encrypted
+    #[serde(default)]
+    is_data_encrypted: bool,
+}
+
+impl KeyFile {
+    fn new(m_cost: u32, t_cost: u32, p_cost: u32) -> Self {
+        let mut salt = [0; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        Self {
+            salt: base64::encode(salt),
+            m_cost,
+            t_cost,
+            p_cost,
+            check: String::new(),
+            is_data_encrypted: false,
+        }
+    }
+
+    /// Derives the keys of the cipher of values and of the cipher of UUIDs.
+    fn derive_ciphers(&self, passphrase: &str) -> anyhow::Result<(XChaCha20Poly1305, Aes256)> {
+        let salt = base64::decode(&self.salt).context("invalid salt")?;
+        let params = Params::new(self.m_cost, self.t_cost, self.p_cost, Some(2 * KEY_LEN))
+            .map_err(|e| anyhow!("invalid key derivation parameters: {}", e))?;
+        let mut keys = [0; 2 * KEY_LEN];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut keys)
+            .map_err(|e| anyhow!("failed to derive key: {}", e))?;
+        let (key, uuid_key) = keys.split_at(KEY_LEN);
+        Ok((
+            XChaCha20Poly1305::new(Key::from_slice(key)),
+            Aes256::new(GenericArray::from_slice(uuid_key)),
+        ))
+    }
+}
+
+impl EncryptedStorage {
+    /// Creates a new encrypted storage wrapping `inner`.
+    ///
+    /// If the key file at `key_path` does not exist, a new one is created. Otherwise, the
+    /// passphrase is checked against the existing key file.
+    pub fn new(
+        inner: Box<dyn Storage>,
+        key_path: impl AsRef<Path>,
+        passphrase: &str,
+    ) -> anyhow::Result<Self> {
+        let default_params = Params::default();
+        Self::with_params(
+            inner,
+            key_path,
+            passphrase,
+            default_params.m_cost(),
+            default_params.t_cost(),
+            default_params.p_cost(),
+        )
+    }
+
+    fn with_params(
+        inner: Box<dyn Storage>,
+        key_path: impl AsRef<Path>,
+        passphrase: &str,
+        m_cost: u32,
+        t_cost: u32,
+        p_cost: u32,
+    ) -> anyhow::Result<Self> {
+        let key_path = key_path.as_ref();
+        if key_path.exists() {
+            let content = std::fs::read_to_string(key_path)
+                .with_context(|| format!("failed to read key file '{}'", key_path.display()))?;
+            let key_file: KeyFile = serde_json::from_str(&content)
+                .with_context(|| format!("invalid key file '{}'", key_path.display()))?;
+            let (cipher, uuid_cipher) = key_file.derive_ciphers(passphrase)?;
+            let storage = Self {
+                inner,
+                cipher,
+                uuid_cipher,
+                key_path: key_path.to_owned(),
+                is_data_encrypted: Cell::new(key_file.is_data_encrypted),
+                is_read_only: false,
+                key_file,
+            };
+            match storage.decrypt(&storage.key_file.check, &check_aad()) {
+                Ok(check) if check == CHECK_PLAINTEXT => Ok(storage),
+                _ => bail!("wrong passphrase"),
+            }
+        } else {
+            info!("creating new storage key file at: {}", key_path.display());
+            let key_file = KeyFile::new(m_cost, t_cost, p_cost);
+            let (cipher, uuid_cipher) = key_file.derive_ciphers(passphrase)?;
+            let mut storage = Self {
+                inner,
+                cipher,
+                uuid_cipher,
+                key_path: key_path.to_owned(),
+                is_data_encrypted: Cell::new(false),
+                is_read_only: false,
+                key_file,
+            };
+            storage.key_file.check = storage.encrypt(CHECK_PLAINTEXT, &check_aad())?;
+            if let Some(parent) = key_path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            storage.write_key_file()?;
+            Ok(storage)
+        }
+    }
+
+    /// Opens the encrypted storage read-only with the existing key file at `key_path`.
+    ///
+    /// Stored values which are not encrypted yet are loaded as is, but neither encrypted nor
+    /// erased.
+    pub fn open_read_only(
+        inner: Box<dyn Storage>,
+        key_path: impl AsRef<Path>,
+        passphrase: &str,
+    ) -> anyhow::Result<Self> {
+        let key_path = key_path.as_ref();
+        if !key_path.exists() {
+            bail!("no key file at '{}'", key_path.display());
+        }
+        Ok(Self {
+            is_read_only: true,
+            ..Self::new(inner, key_path, passphrase)?
+        })
+    }
+
+    /// Path of the key file for the given config.
+    pub fn key_path(config: &Config) -> PathBuf {
+        config.data_path.with_extension("key")
+    }
+
+    /// Writes the key file atomically: losing it makes all stored data unrecoverable.
+    fn write_key_file(&self) -> anyhow::Result<()> {
+        let key_file = KeyFile {
+            is_data_encrypted: self.is_data_encrypted.get(),
+            ..self.key_file.clone()
+        };
+        let tmp_path = super::path_with_suffix(&self.key_path, ".tmp");
+        let write = || -> anyhow::Result<()> {
+            let mut f = File::create(&tmp_path)?;
+            f.write_all(serde_json::to_string(&key_file)?.as_bytes())?;
+            f.sync_all()?;
+            super::replace_file(&tmp_path, &self.key_path)
+        };
+        write().with_context(|| format!("failed to write key file '{}'", self.key_path.display()))
+    }
+
+    /// Encrypts the value bound to the associated data `aad` (see `aad`).
+    fn encrypt(&self, plaintext: &str, aad: &str) -> anyhow::Result<String> {
+        let mut nonce = [0; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        let payload = Payload {
+            msg: plaintext.as_bytes(),
+            aad: aad.as_bytes(),
+        };
+        let ciphertext = self
+            .cipher
+            .encrypt(XNonce::from_slice(&nonce), payload)
+            .map_err(|_| anyhow!("failed to encrypt"))?;
+        let mut data = nonce.to_vec();
+        data.extend(ciphertext);
+        Ok(format!("{}{}", ENCRYPTED_PREFIX, base64::encode(data)))
+    }
+
+    /// Decrypts the value, which must have been encrypted with the same associated data `aad`.
+    ///
+    /// Values which are not encrypted are returned as is, until all stored data was encrypted.
+    fn decrypt(&self, value: &str, aad: &str) -> anyhow::Result<String> {
+        let encoded = match value.strip_prefix(ENCRYPTED_PREFIX) {
+            Some(encoded) => encoded,
+            None if self.is_data_encrypted.get() => {
+                bail!("unencrypted value in encrypted storage: {}", aad)
+            }
+            None => return Ok(value.to_string()),
+        };
+        let data = base64::decode(encoded).context("invalid encrypted value")?;
+        if data.len() < NONCE_LEN {
+            bail!("invalid encrypted value: too short");
+        }
+        let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: aad.as_bytes(),
+        };
+        let plaintext = self
+            .cipher
+            .decrypt(XNonce::from_slice(nonce), payload)
+            .map_err(|_| anyhow!("failed to decrypt {}: wrong key or corrupted data", aad))?;
+        Ok(String::from_utf8(plaintext)?)
+    }
+
+    fn encrypt_uuid(&self, uuid: Uuid) -> Uuid {
+        let mut bytes = *uuid.as_bytes();
+        self.uuid_cipher
+            .encrypt_block(GenericArray::from_mut_slice(&mut bytes));
+        Uuid::from_bytes(bytes)
+    }
+
+    /// Decrypts the UUID of a record, if the record is encrypted.
+    fn decrypt_uuid(&self, uuid: Uuid, is_encrypted_record: bool) -> Uuid {
+        if !is_encrypted_record {
+            return uuid;
+        }
+        let mut bytes = *uuid.as_bytes();
+        self.uuid_cipher
+            .decrypt_block(GenericArray::from_mut_slice(&mut bytes));
+        Uuid::from_bytes(bytes)
+    }
+
+    fn encrypt_message(&self, channel_id: ChannelId, message: &Message) -> anyhow::Result<Message> {
+        let record = message_record(channel_id, message);
+        self.map_message(
+            message,
+            "",
+            &record,
+            |s, aad| self.encrypt(s, aad),
+            |uuid| self.encrypt_uuid(uuid),
+        )
+    }
+
+    fn decrypt_message(&self, channel_id: ChannelId, message: &Message) -> anyhow::Result<Message> {
+        let record = message_record(channel_id, message);
+        let is_encrypted_record = self.is_data_encrypted.get() || has_encrypted_text(message);
+        self.map_message(
+            message,
+            "",
+            &record,
+            |s, aad| self.decrypt(s, aad),
+            |uuid| self.decrypt_uuid(uuid, is_encrypted_record),
+        )
+    }
+
+    /// Maps the encrypted values of the message stored in the record `record`.
+    ///
+    /// The values of a quote are stored in the record of the quoting message, in the columns
+    /// prefixed with `prefix`.
+    fn map_message(
+        &self,
+        message: &Message,
+        prefix: &str,
+        record: &str,
+        f: impl Fn(&str, &str) -> anyhow::Result<String> + Copy,
+        map_uuid: impl Fn(Uuid) -> Uuid + Copy,
+    ) -> anyhow::Result<Message> {
+        let mut message = message.clone();
+        if let Some(text) = message.message.as_mut() {
+            *text = f(text, &aad("messages", format!("{}body", prefix), record))?;
+        }
+        if let Some(quote) = message.quote.as_mut() {
+            let prefix = format!("{}quote.", prefix);
+            **quote = self.map_message(quote, &prefix, record, f, map_uuid)?;
+        }
+        for mention in &mut message.mentions {
+            mention.uuid = map_uuid(mention.uuid);
+        }
+        if let Some(emoji) = message.sticker.as_mut().and_then(|s| s.emoji.as_mut()) {
+            *emoji = f(
+                emoji,
+                &aad("messages", format!("{}sticker", prefix), record),
+            )?;
+        }
+        for attachment in &mut message.attachments {
+            let column = format!("{}filename", prefix);
+            let record = format!("{}/{}", record, attachment.id);
+            let filename = attachment.filename.to_string_lossy();
+            attachment.filename = f(&filename, &aad("attachments", column, record))?.into();
+        }
+        Ok(message)
+    }
+
+    /// Copies the channel metadata and messages with encrypted or decrypted values.
+    fn map_channel(
+        &self,
+        channel: &Channel,
+        messages: &[Message],
+        map_message: impl Fn(ChannelId, &Message) -> anyhow::Result<Message>,
+        f: impl Fn(&str, &str) -> anyhow::Result<String>,
+        map_uuid: impl Fn(Uuid) -> Uuid,
+    ) -> anyhow::Result<Channel> {
+        Ok(Channel {
+            id: channel.id,
+            name: f(
+                &channel.name,
+                &aad("channels", "name", channel_record(channel.id)),
+            )?,
+            group_data: channel.group_data.as_ref().map(|group_data| GroupData {
+                members: group_data.members.iter().copied().map(&map_uuid).collect(),
+                ..group_data.clone()
+            }),
+            messages: StatefulList::with_items(
+                messages
+                    .iter()
+                    .map(|message| map_message(channel.id, message))
+                    .collect::<Result<_, _>>()?,
+            ),
+            unread_messages: channel.unread_messages,
+            expire_timer: channel.expire_timer,
+            is_message_request: channel.is_message_request,
+            typing: Default::default(),
+        })
+    }
+
+    /// Copies the outbox with encrypted or decrypted texts of the queued group changes.
+    fn map_outbox(
+        &self,
+        outbox: &Outbox,
+        f: impl Fn(&str, &str) -> anyhow::Result<String>,
+    ) -> anyhow::Result<Outbox> {
+        let items = outbox
+            .items()
+            .map(|queued| {
+                let mut queued = queued.clone();
+                match &mut queued.item {
+                    OutboxItem::GroupChange {
+                        change: GroupChange::Rename(text) | GroupChange::Describe(text),
+                        ..
+                    } => *text = f(text, &aad("outbox", "item", queued.id))?,
+                    _ => (),
+                }
+                Ok(queued)
+            })
+            .collect::<anyhow::Result<Vec<QueuedItem>>>()?;
+        Ok(Outbox::with_items(items))
+    }
+
+    fn encrypt_channel(&self, channel: &Channel, messages: &[Message]) -> anyhow::Result<Channel> {
+        self.map_channel(
+            channel,
+            messages,
+            |channel_id, m| self.encrypt_message(channel_id, m),
+            |s, aad| self.encrypt(s, aad),
+            |uuid| self.encrypt_uuid(uuid),
+        )
+    }
+
+    fn decrypt_channel(&self, channel: &Channel) -> anyhow::Result<Channel> {
+        let is_encrypted_record = self.is_data_encrypted.get() || is_encrypted(&channel.name);
+        self.map_channel(
+            channel,
+            &channel.messages.items,
+            |channel_id, m| self.decrypt_message(channel_id, m),
+            |s, aad| self.decrypt(s, aad),
+            |uuid| self.decrypt_uuid(uuid, is_encrypted_record),
+        )
+    }
+
+    /// Encrypts the stored messages of the channel which are older than its loaded messages, page
+    /// by page.
+    fn encrypt_older_messages(&self, channel: &Channel) -> anyhow::Result<()> {
+        let mut before = match channel.messages.items.first() {
+            Some(oldest) => oldest.arrived_at,
+            None => return Ok(()),
+        };
+        loop {
+            let messages = self.load_messages(channel.id, before, MESSAGES_PAGE_SIZE)?;
+            match messages.first() {
+                Some(oldest) => before = oldest.arrived_at,
+                None => return Ok(()),
+            }
+            for message in &messages {
+                self.append_message(channel, message)?;
+            }
+        }
+    }
+
+    fn encrypt_name(&self, id: Uuid, name: &str) -> anyhow::Result<String> {
+        self.encrypt(name, &aad("names", "name", id))
+    }
+
+    fn decrypt_name(&self, id: Uuid, name: &str) -> anyhow::Result<String> {
+        self.decrypt(name, &aad("names", "name", id))
+    }
+
+    fn encrypt_app_data(&self, data: &AppData) -> anyhow::Result<AppData> {
+        let channels = data
+            .channels
+            .items
+            .iter()
+            .map(|channel| self.encrypt_channel(channel, &channel.messages.items))
+            .collect::<Result<_, _>>()?;
+        let names = data
+            .names
+            .iter()
+            .map(|(&id, name)| Ok((id, self.encrypt_name(id, name)?)))
+            .collect::<anyhow::Result<_>>()?;
+        let used_words = data
+            .used_words
+            .iter()
+            .map(|word| self.encrypt(word, &used_word_aad()))
+            .collect::<Result<_, _>>()?;
+        let mut encrypted = AppData {
+            names,
+            used_words,
+            outbox: self.map_outbox(&data.outbox, |s, aad| self.encrypt(s, aad))?,
+            blocked: data.blocked.clone(),
+            synced_blocked: data.synced_blocked.clone(),
+            identities: data.identities.clone(),
+            ..Default::default()
+        };
+        encrypted.channels.items = channels;
+        Ok(encrypted)
+    }
+}
+
+impl Storage for EncryptedStorage {
+    fn save_app_data(&self, data: &AppData) -> anyhow::Result<()> {
+        self.inner.save_app_data(&self.encrypt_app_data(data)?)
+    }
+
+    fn load_app_data(&self, user_id: Uuid, user_name: String) -> anyhow::Result<AppData> {
+        let mut data = self.inner.load_app_data(user_id, user_name.clone())?;
+
+        for channel in &mut data.channels.items {
+            *channel = self.decrypt_channel(channel)?;
+        }
+        // note: the inner storage inserted our name unencrypted
+        for (&id, name) in data.names.iter_mut() {
+            if id != user_id {
+                *name = self.decrypt_name(id, name)?;
+            }
+        }
+        data.used_words = data
+            .used_words
+            .iter()
+            .map(|word| self.decrypt(word, &used_word_aad()))
+            .collect::<Result<_, _>>()?;
+        data.names.insert(user_id, user_name);
+        data.outbox = self.map_outbox(&data.outbox, |s, aad| self.decrypt(s, aad))?;
+
+        if !self.is_data_encrypted.get() && !self.is_read_only {
+            info!("encrypting unencrypted stored data");
+            self.save_app_data(&data)?;
+            for channel in &data.channels.items {
+                self.encrypt_older_messages(channel)?;
+            }
+            self.inner.erase_plaintext()?;
+            self.is_data_encrypted.set(true);
+            self.write_key_file()?;
+        }
+
+        Ok(data)
+    }
+
+    fn is_incremental(&self) -> bool {
+        self.inner.is_incremental()
+    }
+
+    fn append_message(&self, channel: &Channel, message: &Message) -> anyhow::Result<()> {
+        let channel = self.encrypt_channel(channel, &[])?;
+        self.inner
+            .append_message(&channel, &self.encrypt_message(channel.id, message)?)
+    }
+
+    fn update_receipt(
+        &self,
+        channel_id: ChannelId,
+        arrived_at: u64,
+        receipt: Receipt,
+    ) -> anyhow::Result<()> {
+        self.inner.update_receipt(channel_id, arrived_at, receipt)
+    }
+
+    fn delete_message(&self, channel_id: ChannelId, arrived_at: u64) -> anyhow::Result<()> {
+        self.inner.delete_message(channel_id, arrived_at)
+    }
+
+    fn delete_expired_messages(&self, now: u64) -> anyhow::Result<Vec<(ChannelId, Message)>> {
+        self.inner
+            .delete_expired_messages(now)?
+            .into_iter()
+            .map(|(channel_id, message)| {
+                self.decrypt_message(channel_id, &message)
+                    .map(|message| (channel_id, message))
+            })
+            .collect()
+    }
+
+    fn upsert_reaction(
+        &self,
+        channel_id: ChannelId,
+        arrived_at: u64,
+        from_id: Uuid,
+        emoji: Option<&str>,
+    ) -> anyhow::Result<()> {
+        self.inner
+            .upsert_reaction(channel_id, arrived_at, from_id, emoji)
+    }
+
+    fn update_channel_meta(&self, channel: &Channel) -> anyhow::Result<()> {
+        let channel = self.encrypt_channel(channel, &[])?;
+        self.inner.update_channel_meta(&channel)
+    }
+
+    fn delete_channel(&self, channel_id: ChannelId) -> anyhow::Result<()> {
+        self.inner.delete_channel(channel_id)
+    }
+
+    fn update_channel_order(&self, channel_ids: &[ChannelId]) -> anyhow::Result<()> {
+        self.inner.update_channel_order(channel_ids)
+    }
+
+    fn upsert_name(&self, id: Uuid, name: &str) -> anyhow::Result<()> {
+        self.inner.upsert_name(id, &self.encrypt_name(id, name)?)
+    }
+
+    fn update_used_words(&self, used_words: &HashSet<String>) -> anyhow::Result<()> {
+        let used_words = used_words
+            .iter()
+            .map(|word| self.encrypt(word, &used_word_aad()))
+            .collect::<Result<_, _>>()?;
+        self.inner.update_used_words(&used_words)
+    }
+
+    fn update_outbox(&self, outbox: &Outbox) -> anyhow::Result<()> {
+        self.inner
+            .update_outbox(&self.map_outbox(outbox, |s, aad| self.encrypt(s, aad))?)
+    }
+
+    fn update_blocked(
+        &self,
+        blocked: &BTreeSet<ChannelId>,
+        synced_blocked: &BTreeSet<ChannelId>,
+    ) -> anyhow::Result<()> {
+        self.inner.update_blocked(blocked, synced_blocked)
+    }
+
+    fn update_identities(&self, identities: &BTreeMap<Uuid, Identity>) -> anyhow::Result<()> {
+        self.inner.update_identities(identities)
+    }
+
+    fn load_messages(
+        &self,
+        channel_id: ChannelId,
+        before: u64,
+        limit: usize,
+    ) -> anyhow::Result<Vec<Message>> {
+        self.inner
+            .load_messages(channel_id, before, limit)?
+            .iter()
+            .map(|message| self.decrypt_message(channel_id, message))
+            .collect()
+    }
+
+    fn load_message(
+        &self,
+        channel_id: ChannelId,
+        arrived_at: u64,
+    ) -> anyhow::Result<Option<Message>> {
+        self.inner
+            .load_message(channel_id, arrived_at)?
+            .map(|message| self.decrypt_message(channel_id, &message))
+            .transpose()
+    }
+}
+
+fn is_encrypted(value: &str) -> bool {
+    value.starts_with(ENCRYPTED_PREFIX)
+}
+
+/// Associated data of an encrypted value stored in the given table, column and record.
+fn aad(table: &str, column: impl fmt::Display, record: impl fmt::Display) -> String {
+    format!("{}.{}:{}", table, column, record)
+}
+
+/// Whether the texts of the stored message are encrypted
+fn has_encrypted_text(message: &Message) -> bool {
+    message.message.as_deref().map_or(false, is_encrypted)
+        || message.quote.as_deref().map_or(false, has_encrypted_text)
+}
+
+fn check_aad() -> String {
+    aad("key_file", "check", "")
+}
+
+/// A used word is its own record.
+fn used_word_aad() -> String {
+    aad("used_words", "word", "")
+}
+
+fn channel_record(channel_id: ChannelId) -> String {
+    match channel_id {
+        ChannelId::User(uuid) => uuid.to_string(),
+        ChannelId::Group(group_id) => base64::encode(group_id),
+    }
+}
+
+fn message_record(channel_id: ChannelId, message: &Message) -> String {
+    format!(
+        "{}/{}/{}",
+        channel_record(channel_id),
+        message.arrived_at,
+        message.from_id
+    )
+}
+
+/// Reads the storage passphrase.
+///
+/// If `passphrase_command` is configured, the passphrase is the first line of its output.
+/// Otherwise, the passphrase is asked for on the terminal; if `confirm` is set, it is asked for
+/// twice.
+pub fn read_passphrase(config: &Config, confirm: bool) -> anyhow::Result<String> {
+    if let Some(command) = config.passphrase_command.as_ref() {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(command)
+            .output()
+            .with_context(|| format!("failed to run passphrase command '{}'", command))?;
+        if !output.status.success() {
+            bail!(
+                "passphrase command '{}' failed: {}",
+                command,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        let output = String::from_utf8(output.stdout).context("invalid passphrase")?;
+        let passphrase = output.lines().next().unwrap_or_default();
+        if passphrase.is_empty() {
+            bail!(
+                "passphrase command '{}' returned an empty passphrase",
+                command
+            );
+        }
+        return Ok(passphrase.to_string());
+    }
+
+    let passphrase = rpassword::read_password_from_tty(Some("Storage passphrase: "))?;
+    if confirm {
+        let confirmation = rpassword::read_password_from_tty(Some("Repeat storage passphrase: "))?;
+        if passphrase != confirmation {
+            bail!("passphrases do not match");
+        }
+    }
+    if passphrase.is_empty() {
+        bail!("empty passphrase");
+    }
+    Ok(passphrase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::storage::test::test_app_data;
+    use crate::storage::{JsonStorage, SqliteStorage};
+
+    use tempfile::tempdir;
+
+    // cheap key derivation for tests
+    const M_COST: u32 = 8;
+    const T_COST: u32 = 1;
+    const P_COST: u32 = 1;
+
+    fn encrypted_json_storage(
+        data_path: &Path,
+        key_path: &Path,
+        passphrase: &str,
+    ) -> anyhow::Result<EncryptedStorage> {
+        let inner = JsonStorage::new(data_path.to_owned(), None);
+        EncryptedStorage::with_params(
+            Box::new(inner),
+            key_path,
+            passphrase,
+            M_COST,
+            T_COST,
+            P_COST,
+        )
+    }
+
+    #[test]
+    fn test_encrypted_storage_save_and_load() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let data_path = dir.path().join("gurk.data.json");
+        let key_path = dir.path().join("gurk.data.key");
+
+        let user_id = Uuid::new_v4();
+        let user_name = "Tyler Durden".to_string();
+        let app_data = test_app_data(user_id, &user_name);
+
+        let storage = encrypted_json_storage(&data_path, &key_path, "secret")?;
+        storage.save_app_data(&app_data)?;
+
+        let content = std::fs::read_to_string(&data_path)?;
+        assert!(!content.contains("space monkey"));
+        assert!(!content.contains("Project Mayhem"));
+        assert!(!content.contains("Marla Singer"));
+        assert!(!content.contains("signal-some-id.png"));
+        assert!(!content.contains("soap"));
+        assert!(!content.contains("Paper Street"));
+        assert!(!content.contains("🐒"));
+
+        // UUIDs are encrypted as UUIDs
+        let stored = JsonStorage::new(data_path.clone(), None).load_app_data(user_id, "".into())?;
+        let channel = &stored.channels.items[1];
+        let members = &channel.group_data.as_ref().unwrap().members;
+        assert_eq!(members.len(), 2);
+        assert!(!members.contains(&user_id));
+        let mention = &channel.messages.items[0].mentions[0];
+        assert_ne!(mention.uuid, user_id);
+
+        let storage = encrypted_json_storage(&data_path, &key_path, "secret")?;
+        let loaded_app_data = storage.load_app_data(user_id, user_name)?;
+        assert_eq!(loaded_app_data, app_data);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_encrypted_storage_wrong_passphrase() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let data_path = dir.path().join("gurk.data.json");
+        let key_path = dir.path().join("gurk.data.key");
+
+        encrypted_json_storage(&data_path, &key_path, "secret")?;
+        assert!(encrypted_json_storage(&data_path, &key_path, "not so secret").is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_encrypted_storage_load_plaintext_data() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let data_path = dir.path().join("gurk.data.json");
+        let key_path = dir.path().join("gurk.data.key");
+
+        let user_id = Uuid::new_v4();
+        let user_name = "Tyler Durden".to_string();
+        let app_data = test_app_data(user_id, &user_name);
+        JsonStorage::new(data_path.clone(), None).save_app_data(&app_data)?;
+
+        let storage = encrypted_json_storage(&data_path, &key_path, "secret")?;
+        let loaded_app_data = storage.load_app_data(user_id, user_name)?;
+        assert_eq!(loaded_app_data, app_data);
+
+        // plaintext data was encrypted on load
+        let content = std::fs::read_to_string(&data_path)?;
+        assert!(!content.contains("space monkey"));
+
+        // from now on, plaintext data is rejected
+        JsonStorage::new(data_path.clone(), None).save_app_data(&app_data)?;
+        let storage = encrypted_json_storage(&data_path, &key_path, "secret")?;
+        assert!(storage
+            .load_app_data(user_id, "Tyler Durden".to_string())
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_encrypted_storage_encrypts_whole_history() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let db_path = dir.path().join("gurk.sqlite");
+        let key_path = dir.path().join("gurk.data.key");
+
+        let user_id = Uuid::new_v4();
+        let user_name = "Tyler Durden".to_string();
+        let channel = Channel {
+            id: ChannelId::User(user_id),
+            name: user_name.clone(),
+            group_data: None,
+            messages: Default::default(),
+            unread_messages: 0,
+            expire_timer: None,
+            is_message_request: false,
+            typing: Default::default(),
+        };
+        let messages: Vec<Message> = (0..MESSAGES_PAGE_SIZE as u64 + 10)
+            .map(|arrived_at| Message::text(user_id, &format!("rule {}", arrived_at), arrived_at))
+            .collect();
+        let sqlite_storage = SqliteStorage::open(&db_path)?;
+        for message in &messages {
+            sqlite_storage.append_message(&channel, message)?;
+        }
+        drop(sqlite_storage);
+
+        let storage = EncryptedStorage::with_params(
+            Box::new(SqliteStorage::open(&db_path)?),
+            &key_path,
+            "secret",
+            M_COST,
+            T_COST,
+            P_COST,
+        )?;
+        let data = storage.load_app_data(user_id, user_name.clone())?;
+        let latest = &data.channels.items[0].messages.items;
+        assert_eq!(latest[..], messages[10..]);
+        // the messages which were not loaded were encrypted as well
+        let older = storage.load_messages(channel.id, latest[0].arrived_at, MESSAGES_PAGE_SIZE)?;
+        assert_eq!(older[..], messages[..10]);
+        drop(storage);
+
+        let content = std::fs::read(&db_path)?;
+        assert!(!content.windows(6).any(|bytes| bytes == b"rule 1"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_encrypted_storage_read_only() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let data_path = dir.path().join("gurk.data.json");
+        let key_path = dir.path().join("gurk.data.key");
+
+        let user_id = Uuid::new_v4();
+        let user_name = "Tyler Durden".to_string();
+        let app_data = test_app_data(user_id, &user_name);
+        let json_storage = || Box::new(JsonStorage::new(data_path.clone(), None).read_only());
+        assert!(EncryptedStorage::open_read_only(json_storage(), &key_path, "secret").is_err());
+
+        // plaintext data is loaded, but not encrypted
+        encrypted_json_storage(&data_path, &key_path, "secret")?;
+        JsonStorage::new(data_path.clone(), None).save_app_data(&app_data)?;
+        let content = std::fs::read_to_string(&data_path)?;
+        let key_content = std::fs::read_to_string(&key_path)?;
+        let storage = EncryptedStorage::open_read_only(json_storage(), &key_path, "secret")?;
+        assert_eq!(storage.load_app_data(user_id, user_name)?, app_data);
+        assert_eq!(std::fs::read_to_string(&data_path)?, content);
+        assert_eq!(std::fs::read_to_string(&key_path)?, key_content);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_encrypted_storage_erases_plaintext_backups() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let data_path = dir.path().join("gurk.data.json");
+        let key_path = dir.path().join("gurk.data.key");
+
+        let user_id = Uuid::new_v4();
+        let user_name = "Tyler Durden".to_string();
+        let app_data = test_app_data(user_id, &user_name);
+        let json_storage = JsonStorage::new(data_path.clone(), None).with_backups(2);
+        json_storage.save_app_data(&app_data)?;
+        json_storage.save_app_data(&app_data)?;
+        assert!(json_storage.backup_path(1).exists());
+
+        let storage = EncryptedStorage::with_params(
+            Box::new(json_storage),
+            &key_path,
+            "secret",
+            M_COST,
+            T_COST,
+            P_COST,
+        )?;
+        storage.load_app_data(user_id, user_name)?;
+
+        for entry in std::fs::read_dir(dir.path())? {
+            let content = std::fs::read_to_string(entry?.path())?;
+            assert!(!content.contains("space monkey"));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_encrypted_value_is_bound_to_its_record() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let data_path = dir.path().join("gurk.data.json");
+        let key_path = dir.path().join("gurk.data.key");
+        let storage = encrypted_json_storage(&data_path, &key_path, "secret")?;
+
+        let marla = Uuid::new_v4();
+        let robert = Uuid::new_v4();
+        let encrypted = storage.encrypt_name(marla, "Marla Singer")?;
+        assert_eq!(storage.decrypt_name(marla, &encrypted)?, "Marla Singer");
+        assert!(storage.decrypt_name(robert, &encrypted).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,399 @@
+//! Schema migrations of the app data stored as JSON
+//!
+//! The stored app data contains a `version` field; data without this field has version 0. When
+//! loading, the JSON representation of the data is migrated step by step to the current version
+//! before it is deserialized. A migration step must preserve all data it does not touch.
+
+use crate::app::ChannelId;
+
+use anyhow::{anyhow, bail, Context};
+use log::info;
+use serde_json::{json, Map, Value};
+
+type MigrationStep = fn(&mut Value) -> anyhow::Result<()>;
+
+/// Migration steps: the step at index `i` migrates the data from version `i` to `i + 1`.
+///
+/// Append new steps at the end; never change existing ones.
+const MIGRATIONS: &[MigrationStep] = &[
+    v0_group_identifiers,
+    v1_defaults,
+    v2_outbox,
+    v3_expire_timer,
+    v4_blocked,
+    v5_message_requests,
+    v6_identities,
+    v7_send_receipts,
+];
+
+/// The version of the app data which is written by this version of gurk.
+pub const CURRENT_VERSION: u64 = MIGRATIONS.len() as u64;
+
+const VERSION_KEY: &str = "version";
+
+/// Migrates the JSON representation of the app data to the current version.
+pub fn migrate(mut data: Value) -> anyhow::Result<Value> {
+    let version = version(&data)?;
+    if version > CURRENT_VERSION {
+        bail!(
+            "stored data has version {}, but at most version {} is supported: \
+            please upgrade gurk",
+            version,
+            CURRENT_VERSION
+        );
+    }
+
+    for (from_version, step) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        info!(
+            "migrating app data from version {} to {}",
+            from_version,
+            from_version + 1
+        );
+        step(&mut data).with_context(|| {
+            format!(
+                "failed to migrate app data from version {} to {}",
+                from_version,
+                from_version + 1
+            )
+        })?;
+    }
+
+    data.as_object_mut()
+        .ok_or_else(|| anyhow!("app data is not an object"))?
+        .insert(VERSION_KEY.to_string(), CURRENT_VERSION.into());
+    Ok(data)
+}
+
+fn version(data: &Value) -> anyhow::Result<u64> {
+    match data.get(VERSION_KEY) {
+        Some(version) => version
+            .as_u64()
+            .ok_or_else(|| anyhow!("invalid app data version: {}", version)),
+        None => Ok(0),
+    }
+}
+
+fn channels_mut(data: &mut Value) -> impl Iterator<Item = &mut Value> {
+    data.pointer_mut("/channels/items")
+        .and_then(Value::as_array_mut)
+        .into_iter()
+        .flatten()
+}
+
+/// v0 -> v1: The master key in `ChannelId::Group` was replaced by the group identifier; the
+/// master key is stored in the group data.
+fn v0_group_identifiers(data: &mut Value) -> anyhow::Result<()> {
+    for channel in channels_mut(data) {
+        let master_key = match channel.pointer("/id/Group") {
+            Some(master_key) => master_key.clone(),
+            None => continue,
+        };
+        if !channel.get("group_data").map_or(false, Value::is_object) {
+            continue;
+        }
+        let has_master_key = channel
+            .pointer("/group_data/master_key_bytes")
+            .and_then(Value::as_array)
+            .map_or(false, |bytes| bytes.iter().any(|b| b.as_u64() != Some(0)));
+        if has_master_key {
+            continue;
+        }
+
+        let master_key_bytes: Vec<u8> = serde_json::from_value(master_key.clone())?;
+        channel["id"] = serde_json::to_value(ChannelId::from_master_key_bytes(master_key_bytes)?)?;
+        channel["group_data"]["master_key_bytes"] = master_key;
+    }
+    Ok(())
+}
+
+/// v1 -> v2: All fields of the app data, channels and messages are stored explicitly, unknown
+/// receipts are reset, and the typing state matches the channel kind.
+fn v1_defaults(data: &mut Value) -> anyhow::Result<()> {
+    let object = data
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("app data is not an object"))?;
+    insert_default(object, "channels", json!({ "items": [] }));
+    insert_default(object, "names", json!({}));
+    insert_default(object, "used_words", json!([]));
+
+    for channel in channels_mut(data) {
+        let channel = channel
+            .as_object_mut()
+            .ok_or_else(|| anyhow!("channel is not an object"))?;
+        insert_default(channel, "group_data", Value::Null);
+        insert_default(channel, "messages", json!([]));
+        insert_default(channel, "unread_messages", json!(0));
+
+        // typing is transient and is reset on load
+        let typing = if channel["group_data"].is_null() {
+            json!({ "SingleTyping": false })
+        } else {
+            json!({ "GroupTyping": [] })
+        };
+        channel.insert("typing".to_string(), typing);
+
+        for message in channel["messages"].as_array_mut().into_iter().flatten() {
+            message_defaults(message)?;
+        }
+    }
+    Ok(())
+}
+
+/// v2 -> v3: Outgoing messages, reactions and receipts are queued in the outbox.
+fn v2_outbox(data: &mut Value) -> anyhow::Result<()> {
+    let object = data
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("app data is not an object"))?;
+    insert_default(object, "outbox", json!({ "items": [] }));
+    Ok(())
+}
+
+/// v3 -> v4: Channels store the expiration timer of disappearing messages.
+fn v3_expire_timer(data: &mut Value) -> anyhow::Result<()> {
+    for channel in channels_mut(data) {
+        let channel = channel
+            .as_object_mut()
+            .ok_or_else(|| anyhow!("channel is not an object"))?;
+        insert_default(channel, "expire_timer", Value::Null);
+    }
+    Ok(())
+}
+
+/// v4 -> v5: Blocked contacts and groups, and the synced blocklist of the primary device, are
+/// stored by their channel id.
+fn v4_blocked(data: &mut Value) -> anyhow::Result<()> {
+    let object = data
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("app data is not an object"))?;
+    insert_default(object, "blocked", json!([]));
+    insert_default(object, "synced_blocked", json!([]));
+    Ok(())
+}
+
+/// v5 -> v6: Channels started by unknown senders are message requests.
+fn v5_message_requests(data: &mut Value) -> anyhow::Result<()> {
+    for channel in channels_mut(data) {
+        let channel = channel
+            .as_object_mut()
+            .ok_or_else(|| anyhow!("channel is not an object"))?;
+        insert_default(channel, "is_message_request", Value::Bool(false));
+    }
+    Ok(())
+}
+
+/// v6 -> v7: Identity keys of contacts are stored to detect changes of their safety numbers.
+fn v6_identities(data: &mut Value) -> anyhow::Result<()> {
+    let object = data
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("app data is not an object"))?;
+    insert_default(object, "identities", json!({}));
+    Ok(())
+}
+
+/// v7 -> v8: Own messages have the receipts `Queued`, `Pending` and `Failed` while they are
+/// sent; receipts which are unknown to this version are reset.
+fn v7_send_receipts(data: &mut Value) -> anyhow::Result<()> {
+    for channel in channels_mut(data) {
+        let messages = channel
+            .get_mut("messages")
+            .and_then(Value::as_array_mut)
+            .into_iter()
+            .flatten();
+        for message in messages {
+            reset_unknown_receipt(message, KNOWN_RECEIPTS)?;
+        }
+    }
+    Ok(())
+}
+
+/// Receipts which are known since v8
+const KNOWN_RECEIPTS: &[&str] = &[
+    "Nothing",
+    "Queued",
+    "Pending",
+    "Failed",
+    "Sent",
+    "Received",
+    "Delivered",
+];
+
+/// Resets the receipt of the message and its quote to `Nothing`, unless it is one of `known`.
+fn reset_unknown_receipt(message: &mut Value, known: &[&str]) -> anyhow::Result<()> {
+    let message = message
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("message is not an object"))?;
+    let is_known_receipt = message
+        .get("receipt")
+        .and_then(Value::as_str)
+        .map_or(false, |receipt| known.contains(&receipt));
+    if !is_known_receipt {
+        message.insert("receipt".to_string(), json!("Nothing"));
+    }
+
+    match message.get_mut("quote") {
+        Some(quote) if !quote.is_null() => reset_unknown_receipt(quote, known),
+        _ => Ok(()),
+    }
+}
+
+fn message_defaults(message: &mut Value) -> anyhow::Result<()> {
+    let message = message
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("message is not an object"))?;
+    insert_default(message, "quote", Value::Null);
+    insert_default(message, "attachments", json!([]));
+    insert_default(message, "reactions", json!([]));
+
+    let is_known_receipt = matches!(
+        message.get("receipt").and_then(Value::as_str),
+        Some("Nothing" | "Sent" | "Received" | "Delivered")
+    );
+    if !is_known_receipt {
+        message.insert("receipt".to_string(), json!("Nothing"));
+    }
+
+    match message.get_mut("quote") {
+        Some(quote) if !quote.is_null() => message_defaults(quote),
+        _ => Ok(()),
+    }
+}
+
+fn insert_default(object: &mut Map<String, Value>, key: &str, value: Value) {
+    object.entry(key).or_insert(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::app::AppData;
+
+    fn fixture(version: u64) -> Value {
+        let content = match version {
+            0 => include_str!("fixtures/app_data_v0.json"),
+            1 => include_str!("fixtures/app_data_v1.json"),
+            2 => include_str!("fixtures/app_data_v2.json"),
+            3 => include_str!("fixtures/app_data_v3.json"),
+            4 => include_str!("fixtures/app_data_v4.json"),
+            5 => include_str!("fixtures/app_data_v5.json"),
+            6 => include_str!("fixtures/app_data_v6.json"),
+            7 => include_str!("fixtures/app_data_v7.json"),
+            8 => include_str!("fixtures/app_data_v8.json"),
+            _ => panic!("no fixture for version {}", version),
+        };
+        serde_json::from_str(content).unwrap()
+    }
+
+    #[test]
+    fn test_migrate_v0_to_v1() -> anyhow::Result<()> {
+        let mut data = fixture(0);
+        let master_key = data["channels"]["items"][1]["id"]["Group"].clone();
+        let contact_channel = data["channels"]["items"][0].clone();
+
+        v0_group_identifiers(&mut data)?;
+
+        let channels = &data["channels"]["items"];
+        assert_eq!(channels[0], contact_channel);
+        assert_eq!(channels[1]["group_data"]["master_key_bytes"], master_key);
+        let master_key_bytes: Vec<u8> = serde_json::from_value(master_key)?;
+        assert_eq!(
+            channels[1]["id"],
+            serde_json::to_value(ChannelId::from_master_key_bytes(master_key_bytes)?)?
+        );
+
+        // already migrated group channels are not changed
+        let migrated = data.clone();
+        v0_group_identifiers(&mut data)?;
+        assert_eq!(data, migrated);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate_v1_to_v2() -> anyhow::Result<()> {
+        let mut data = fixture(1);
+        v1_defaults(&mut data)?;
+        data[VERSION_KEY] = 2.into();
+        assert_eq!(data, fixture(2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate_v2_to_v3() -> anyhow::Result<()> {
+        let mut data = fixture(2);
+        v2_outbox(&mut data)?;
+        data[VERSION_KEY] = 3.into();
+        assert_eq!(data, fixture(3));
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate_v3_to_v4() -> anyhow::Result<()> {
+        assert_eq!(migrate(fixture(3))?, fixture(4));
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate_v4_to_v5() -> anyhow::Result<()> {
+        assert_eq!(migrate(fixture(4))?, fixture(5));
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate_v5_to_v6() -> anyhow::Result<()> {
+        assert_eq!(migrate(fixture(5))?, fixture(6));
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate_v6_to_v7() -> anyhow::Result<()> {
+        assert_eq!(migrate(fixture(6))?, fixture(7));
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate_v7_to_v8() -> anyhow::Result<()> {
+        assert_eq!(migrate(fixture(7))?, fixture(8));
+
+        let mut data = fixture(7);
+        let messages = &mut data["channels"]["items"][0]["messages"];
+        messages[0]["receipt"] = json!("Failed");
+        messages[1]["receipt"] = json!("Read");
+        messages[1]["quote"]["receipt"] = json!("Sending");
+        let data = migrate(data)?;
+        let messages = &data["channels"]["items"][0]["messages"];
+        assert_eq!(messages[0]["receipt"], "Failed");
+        assert_eq!(messages[1]["receipt"], "Nothing");
+        assert_eq!(messages[1]["quote"]["receipt"], "Nothing");
+        serde_json::from_value::<AppData>(data)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate_current_version() -> anyhow::Result<()> {
+        assert_eq!(CURRENT_VERSION, 8);
+        assert_eq!(migrate(fixture(CURRENT_VERSION))?, fixture(CURRENT_VERSION));
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate_all_versions() -> anyhow::Result<()> {
+        for version in 0..=CURRENT_VERSION {
+            let data = migrate(fixture(version))?;
+            assert_eq!(data[VERSION_KEY], CURRENT_VERSION);
+
+            let app_data: AppData = serde_json::from_value(data)?;
+            assert_eq!(app_data.channels.items.len(), 2);
+            assert_eq!(app_data.channels.items[0].messages.items.len(), 2);
+            assert_eq!(app_data.channels.items[1].messages.items.len(), 1);
+            assert_eq!(app_data.names.len(), 2);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_migrate_newer_version_fails() {
+        let data = json!({ "version": CURRENT_VERSION + 1, "channels": { "items": [] } });
+        assert!(migrate(data).is_err());
+    }
+}
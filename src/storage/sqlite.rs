@@ -0,0 +1,1606 @@
+use super::{JsonStorage, Storage, MESSAGES_PAGE_SIZE};
+use crate::app::{AppData, Channel, ChannelId, GroupData, Identity, Message, Receipt};
+use crate::outbox::{Outbox, QueuedItem};
+use crate::search::{self, SearchResult};
+use crate::signal::Attachment;
+use crate::util::StatefulList;
+
+use anyhow::{anyhow, bail, Context as _};
+use itertools::{Itertools, MinMaxResult};
+use log::info;
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use uuid::Uuid;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::convert::TryInto;
+use std::path::{Path, PathBuf};
+
+/// Schema migrations: the migration at index `i` migrates the schema from version `i` to `i + 1`.
+///
+/// The schema version is stored in the `user_version` pragma of the database. Append new
+/// migrations at the end; never change existing ones.
+const MIGRATIONS: &[&str] = &[
+    SCHEMA_V1, SCHEMA_V2, SCHEMA_V3, SCHEMA_V4, SCHEMA_V5, SCHEMA_V6, SCHEMA_V7, SCHEMA_V8,
+    SCHEMA_V9, SCHEMA_V10, SCHEMA_V11,
+];
+
+/// Initial schema: messages have explicit ids, which unlike implicit rowids are not changed by
+/// `VACUUM`; the full-text index and the insertion order of messages refer to them.
+const SCHEMA_V1: &str = "
+CREATE TABLE IF NOT EXISTS meta (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS channels (
+    id BLOB PRIMARY KEY,
+    name TEXT NOT NULL,
+    group_master_key BLOB,
+    group_revision INTEGER,
+    unread_messages INTEGER NOT NULL DEFAULT 0,
+    position INTEGER NOT NULL DEFAULT 0
+);
+CREATE TABLE IF NOT EXISTS group_members (
+    channel_id BLOB NOT NULL REFERENCES channels(id) ON DELETE CASCADE,
+    member BLOB NOT NULL,
+    position INTEGER NOT NULL,
+    PRIMARY KEY (channel_id, member)
+);
+CREATE TABLE IF NOT EXISTS messages (
+    id INTEGER PRIMARY KEY,
+    channel_id BLOB NOT NULL REFERENCES channels(id) ON DELETE CASCADE,
+    arrived_at INTEGER NOT NULL,
+    from_id BLOB NOT NULL,
+    body TEXT,
+    quote TEXT,
+    receipt TEXT NOT NULL,
+    UNIQUE (channel_id, arrived_at, from_id)
+);
+CREATE TABLE IF NOT EXISTS reactions (
+    channel_id BLOB NOT NULL,
+    arrived_at INTEGER NOT NULL,
+    from_id BLOB NOT NULL,
+    emoji TEXT NOT NULL,
+    PRIMARY KEY (channel_id, arrived_at, from_id)
+);
+CREATE TABLE IF NOT EXISTS attachments (
+    channel_id BLOB NOT NULL,
+    arrived_at INTEGER NOT NULL,
+    id TEXT NOT NULL,
+    content_type TEXT NOT NULL,
+    filename TEXT NOT NULL,
+    size INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS attachments_by_message ON attachments (channel_id, arrived_at);
+CREATE TABLE IF NOT EXISTS names (
+    id BLOB PRIMARY KEY,
+    name TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS used_words (
+    word TEXT PRIMARY KEY
+);
+";
+
+/// Index for loading the latest messages of a channel in insertion order.
+const SCHEMA_V2: &str = "
+CREATE INDEX IF NOT EXISTS messages_by_channel ON messages (channel_id);
+";
+
+/// Full-text index of the message bodies, kept up to date by triggers.
+const SCHEMA_V3: &str = "
+CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts USING fts5 (
+    body,
+    content = 'messages',
+    content_rowid = 'id'
+);
+CREATE TRIGGER IF NOT EXISTS messages_fts_insert AFTER INSERT ON messages BEGIN
+    INSERT INTO messages_fts (rowid, body) VALUES (new.id, new.body);
+END;
+CREATE TRIGGER IF NOT EXISTS messages_fts_delete AFTER DELETE ON messages BEGIN
+    INSERT INTO messages_fts (messages_fts, rowid, body) VALUES ('delete', old.id, old.body);
+END;
+CREATE TRIGGER IF NOT EXISTS messages_fts_update AFTER UPDATE OF body ON messages BEGIN
+    INSERT INTO messages_fts (messages_fts, rowid, body) VALUES ('delete', old.id, old.body);
+    INSERT INTO messages_fts (rowid, body) VALUES (new.id, new.body);
+END;
+INSERT INTO messages_fts (messages_fts) VALUES ('rebuild');
+";
+
+/// Outbox items in queue order, stored as JSON.
+const SCHEMA_V4: &str = "
+CREATE TABLE IF NOT EXISTS outbox (
+    id INTEGER PRIMARY KEY,
+    item TEXT NOT NULL,
+    attempts INTEGER NOT NULL
+);
+";
+
+/// Tombstones of messages deleted for everyone.
+const SCHEMA_V5: &str = "
+ALTER TABLE messages ADD COLUMN is_deleted INTEGER NOT NULL DEFAULT 0;
+";
+
+/// Disappearing messages and system lines.
+const SCHEMA_V6: &str = "
+ALTER TABLE channels ADD COLUMN expire_timer INTEGER;
+ALTER TABLE messages ADD COLUMN expires_in INTEGER;
+ALTER TABLE messages ADD COLUMN expire_started_at INTEGER;
+ALTER TABLE messages ADD COLUMN is_system INTEGER NOT NULL DEFAULT 0;
+";
+
+/// Mentions in message bodies, stored as JSON array.
+const SCHEMA_V7: &str = "
+ALTER TABLE messages ADD COLUMN mentions TEXT;
+";
+
+/// Stickers, stored as JSON object; the sticker image is stored as attachment.
+const SCHEMA_V8: &str = "
+ALTER TABLE messages ADD COLUMN sticker TEXT;
+";
+
+/// Blocked contacts and groups, and the blocklist of the primary device as of its last sync.
+const SCHEMA_V9: &str = "
+CREATE TABLE IF NOT EXISTS blocked (
+    channel_id BLOB PRIMARY KEY
+);
+CREATE TABLE IF NOT EXISTS synced_blocked (
+    channel_id BLOB PRIMARY KEY
+);
+";
+
+/// Message requests from unknown senders.
+const SCHEMA_V10: &str = "
+ALTER TABLE channels ADD COLUMN is_message_request INTEGER NOT NULL DEFAULT 0;
+";
+
+/// Identity keys of contacts and whether we verified them, and warnings about changed ones.
+const SCHEMA_V11: &str = "
+CREATE TABLE IF NOT EXISTS identities (
+    id BLOB PRIMARY KEY,
+    key BLOB NOT NULL,
+    is_verified INTEGER NOT NULL
+);
+ALTER TABLE messages ADD COLUMN is_warning INTEGER NOT NULL DEFAULT 0;
+";
+
+/// Meta key which is set after the legacy JSON data was migrated into the database.
+const JSON_MIGRATED_KEY: &str = "json_migrated";
+
+/// Storage based on a SQLite database with a row per channel, message, reaction, attachment
+/// and name.
+///
+/// Only the latest `MESSAGES_PAGE_SIZE` messages of each channel are loaded with the app data;
+/// older messages are loaded page-wise with `load_messages`.
+pub struct SqliteStorage {
+    conn: Connection,
+    /// Files of the legacy JSON storage, which was migrated into the database
+    legacy_json_files: Vec<PathBuf>,
+}
+
+impl Storage for SqliteStorage {
+    fn save_app_data(&self, data: &AppData) -> anyhow::Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        write_app_data(&tx, data)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn is_incremental(&self) -> bool {
+        true
+    }
+
+    fn append_message(&self, channel: &Channel, message: &Message) -> anyhow::Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        let is_stored: bool = tx.query_row(
+            "SELECT EXISTS (SELECT 1 FROM channels WHERE id = ?1)",
+            params![channel_id_to_bytes(channel.id)],
+            |row| row.get(0),
+        )?;
+        if !is_stored {
+            upsert_channel(&tx, channel, None)?;
+        }
+        upsert_message(&tx, channel.id, message)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn update_receipt(
+        &self,
+        channel_id: ChannelId,
+        arrived_at: u64,
+        receipt: Receipt,
+    ) -> anyhow::Result<()> {
+        self.conn.execute(
+            "UPDATE messages SET receipt = ?3 WHERE channel_id = ?1 AND arrived_at = ?2",
+            params![
+                channel_id_to_bytes(channel_id),
+                arrived_at as i64,
+                receipt_to_str(receipt)
+            ],
+        )?;
+        Ok(())
+    }
+
+    fn delete_message(&self, channel_id: ChannelId, arrived_at: u64) -> anyhow::Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        delete_message_rows(&tx, &channel_id_to_bytes(channel_id), arrived_at as i64)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn delete_expired_messages(&self, now: u64) -> anyhow::Result<Vec<(ChannelId, Message)>> {
+        let tx = self.conn.unchecked_transaction()?;
+        let expired = {
+            let mut stmt = tx.prepare(
+                "SELECT DISTINCT channel_id, arrived_at FROM messages
+                WHERE expire_started_at + expires_in * 1000 <= ?1",
+            )?;
+            let rows = stmt.query_map(params![now as i64], |row| {
+                Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, i64>(1)?))
+            })?;
+            rows.collect::<Result<Vec<_>, _>>()?
+        };
+        let mut messages = Vec::new();
+        for (channel_id, arrived_at) in expired {
+            let expired_messages = self
+                .query_messages(
+                    &channel_id,
+                    None,
+                    Some(arrived_at as u64),
+                    i64::MAX as usize,
+                )?
+                .into_iter()
+                .filter(|message| message.expires_at().map_or(false, |at| at <= now));
+            for message in expired_messages {
+                delete_message_rows(&tx, &channel_id, arrived_at)?;
+                messages.push((channel_id_from_bytes(&channel_id)?, message));
+            }
+        }
+        tx.commit()?;
+        Ok(messages)
+    }
+
+    fn upsert_reaction(
+        &self,
+        channel_id: ChannelId,
+        arrived_at: u64,
+        from_id: Uuid,
+        emoji: Option<&str>,
+    ) -> anyhow::Result<()> {
+        let channel_id = channel_id_to_bytes(channel_id);
+        let from_id = &from_id.as_bytes()[..];
+        match emoji {
+            Some(emoji) => self.conn.execute(
+                "INSERT INTO reactions (channel_id, arrived_at, from_id, emoji)
+                VALUES (?1, ?2, ?3, ?4)
+                ON CONFLICT (channel_id, arrived_at, from_id) DO UPDATE SET emoji = excluded.emoji",
+                params![channel_id, arrived_at as i64, from_id, emoji],
+            )?,
+            None => self.conn.execute(
+                "DELETE FROM reactions WHERE channel_id = ?1 AND arrived_at = ?2 AND from_id = ?3",
+                params![channel_id, arrived_at as i64, from_id],
+            )?,
+        };
+        Ok(())
+    }
+
+    fn update_channel_meta(&self, channel: &Channel) -> anyhow::Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        upsert_channel(&tx, channel, None)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn delete_channel(&self, channel_id: ChannelId) -> anyhow::Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        delete_channel_rows(&tx, &channel_id_to_bytes(channel_id))?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn update_channel_order(&self, channel_ids: &[ChannelId]) -> anyhow::Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        for (position, &channel_id) in channel_ids.iter().enumerate() {
+            tx.execute(
+                "UPDATE channels SET position = ?2 WHERE id = ?1",
+                params![channel_id_to_bytes(channel_id), position as i64],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn upsert_name(&self, id: Uuid, name: &str) -> anyhow::Result<()> {
+        upsert_name(&self.conn, id, name)
+    }
+
+    fn update_used_words(&self, used_words: &HashSet<String>) -> anyhow::Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        replace_used_words(&tx, used_words)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn update_outbox(&self, outbox: &Outbox) -> anyhow::Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        replace_outbox(&tx, outbox)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn update_blocked(
+        &self,
+        blocked: &BTreeSet<ChannelId>,
+        synced_blocked: &BTreeSet<ChannelId>,
+    ) -> anyhow::Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        replace_blocked(&tx, blocked, synced_blocked)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn update_identities(&self, identities: &BTreeMap<Uuid, Identity>) -> anyhow::Result<()> {
+        let tx = self.conn.unchecked_transaction()?;
+        replace_identities(&tx, identities)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn erase_plaintext(&self) -> anyhow::Result<()> {
+        for path in &self.legacy_json_files {
+            super::remove_file_securely(path)?;
+        }
+        // rebuild the database and truncate the WAL, which still contain the replaced values
+        self.conn
+            .execute_batch("VACUUM; PRAGMA wal_checkpoint(TRUNCATE);")?;
+        Ok(())
+    }
+
+    fn load_messages(
+        &self,
+        channel_id: ChannelId,
+        before: u64,
+        limit: usize,
+    ) -> anyhow::Result<Vec<Message>> {
+        self.query_messages(&channel_id_to_bytes(channel_id), Some(before), None, limit)
+    }
+
+    fn load_message(
+        &self,
+        channel_id: ChannelId,
+        arrived_at: u64,
+    ) -> anyhow::Result<Option<Message>> {
+        let messages =
+            self.query_messages(&channel_id_to_bytes(channel_id), None, Some(arrived_at), 1)?;
+        Ok(messages.into_iter().next())
+    }
+
+    fn search_messages(
+        &self,
+        query: &str,
+        limit: usize,
+    ) -> anyhow::Result<Option<Vec<SearchResult>>> {
+        // every word is matched as a quoted prefix, s.t. the query can't contain FTS5 syntax
+        let query = search::tokenize(query)
+            .map(|word| format!("\"{}\"*", word))
+            .join(" ");
+        if query.is_empty() {
+            return Ok(Some(Vec::new()));
+        }
+        let mut stmt = self.conn.prepare(
+            "SELECT messages.channel_id, messages.arrived_at, messages.from_id, messages.body
+            FROM messages_fts JOIN messages ON messages.id = messages_fts.rowid
+            WHERE messages_fts MATCH ?1
+            ORDER BY messages.arrived_at DESC LIMIT ?2",
+        )?;
+        let rows = stmt.query_map(params![query, limit as i64], |row| {
+            Ok((
+                row.get::<_, Vec<u8>>(0)?,
+                row.get::<_, i64>(1)?,
+                row.get::<_, Vec<u8>>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?;
+        let mut results = Vec::new();
+        for row in rows {
+            let (channel_id, arrived_at, from_id, text) = row?;
+            results.push(SearchResult {
+                channel_id: channel_id_from_bytes(&channel_id)?,
+                from_id: Uuid::from_slice(&from_id)?,
+                arrived_at: arrived_at as u64,
+                text,
+            });
+        }
+        Ok(Some(results))
+    }
+
+    fn load_app_data(&self, user_id: Uuid, user_name: String) -> anyhow::Result<AppData> {
+        let mut data = AppData {
+            channels: Default::default(),
+            names: self.load_names()?,
+            used_words: self.load_used_words()?,
+            outbox: self.load_outbox()?,
+            blocked: self.load_blocked("blocked")?,
+            synced_blocked: self.load_blocked("synced_blocked")?,
+            identities: self.load_identities()?,
+            ..Default::default()
+        };
+        data.channels.items = self.load_channels()?;
+
+        // ensure that our name is up to date
+        data.names.insert(user_id, user_name);
+
+        // select the first channel if none is selected
+        if data.channels.state.selected().is_none() && !data.channels.items.is_empty() {
+            data.channels.state.select(Some(0));
+        }
+
+        Ok(data)
+    }
+}
+
+impl SqliteStorage {
+    /// Opens (or creates) the SQLite database at the given path.
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        info!("opening sqlite database at: {}", path.display());
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let conn = Connection::open(path)
+            .with_context(|| format!("failed to open database at '{}'", path.display()))?;
+        Self::init(conn)
+    }
+
+    /// Opens the existing SQLite database at the given path read-only.
+    ///
+    /// Neither the schema nor the JSON data can be migrated without writing, so they must be up
+    /// to date already.
+    pub fn open_read_only(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        info!("opening sqlite database read-only at: {}", path.display());
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .with_context(|| format!("failed to open database at '{}'", path.display()))?;
+        let version: u32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version as usize != MIGRATIONS.len() {
+            bail!(
+                "database has schema version {}, but version {} is required: \
+                please start gurk once to migrate it",
+                version,
+                MIGRATIONS.len()
+            );
+        }
+        let is_migrated: Option<String> = conn
+            .query_row(
+                "SELECT value FROM meta WHERE key = ?1",
+                params![JSON_MIGRATED_KEY],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if is_migrated.is_none() {
+            bail!("JSON data is not migrated yet: please start gurk once to migrate it");
+        }
+        Ok(Self {
+            conn,
+            legacy_json_files: Vec::new(),
+        })
+    }
+
+    #[cfg(test)]
+    fn open_in_memory() -> anyhow::Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(mut conn: Connection) -> anyhow::Result<Self> {
+        // deleted messages, e.g. expired disappearing ones, are overwritten in the database file
+        conn.execute_batch(
+            "PRAGMA foreign_keys = ON; PRAGMA journal_mode = WAL; PRAGMA secure_delete = ON;",
+        )?;
+        Self::migrate(&mut conn)?;
+        Ok(Self {
+            conn,
+            legacy_json_files: Vec::new(),
+        })
+    }
+
+    /// Migrates the database schema to the latest version.
+    fn migrate(conn: &mut Connection) -> anyhow::Result<()> {
+        let version: u32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version as usize > MIGRATIONS.len() {
+            bail!(
+                "database has schema version {}, but at most version {} is supported: \
+                please upgrade gurk",
+                version,
+                MIGRATIONS.len()
+            );
+        }
+
+        let tx = conn.transaction()?;
+        for (from_version, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            info!(
+                "migrating database schema from version {} to {}",
+                from_version,
+                from_version + 1
+            );
+            tx.execute_batch(migration)?;
+            tx.pragma_update(None, "user_version", from_version as u32 + 1)?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Migrates the data from the legacy JSON storage into the database.
+    ///
+    /// The migration is done only once. The JSON file is not modified, s.t. it can be used as a
+    /// backup or to switch back to the JSON storage.
+    pub fn migrate_from_json(&mut self, json_storage: &JsonStorage) -> anyhow::Result<()> {
+        self.legacy_json_files = json_storage.files();
+        let tx = self.conn.unchecked_transaction()?;
+        let is_migrated: Option<String> = tx
+            .query_row(
+                "SELECT value FROM meta WHERE key = ?1",
+                params![JSON_MIGRATED_KEY],
+                |row| row.get(0),
+            )
+            .optional()?;
+        if is_migrated.is_some() {
+            return Ok(());
+        }
+
+        let data = json_storage
+            .load_app_data_impl()
+            .context("failed to migrate JSON storage into SQLite")?;
+        info!(
+            "migrating {} channels from JSON storage into SQLite",
+            data.channels.items.len()
+        );
+        // the data and the flag are written at once, s.t. an interrupted migration is redone
+        write_app_data(&tx, &data)?;
+        tx.execute(
+            "INSERT INTO meta (key, value) VALUES (?1, ?2)",
+            params![JSON_MIGRATED_KEY, chrono::Utc::now().to_rfc3339()],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    fn load_names(&self) -> anyhow::Result<HashMap<Uuid, String>> {
+        let mut stmt = self.conn.prepare("SELECT id, name FROM names")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, String>(1)?))
+        })?;
+        let mut names = HashMap::new();
+        for row in rows {
+            let (id, name) = row?;
+            names.insert(Uuid::from_slice(&id)?, name);
+        }
+        Ok(names)
+    }
+
+    fn load_used_words(&self) -> anyhow::Result<HashSet<String>> {
+        let mut stmt = self.conn.prepare("SELECT word FROM used_words")?;
+        let words = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        Ok(words)
+    }
+
+    /// Loads the channel ids of the table `blocked` or `synced_blocked`.
+    fn load_blocked(&self, table: &str) -> anyhow::Result<BTreeSet<ChannelId>> {
+        let mut stmt = self
+            .conn
+            .prepare(&format!("SELECT channel_id FROM {}", table))?;
+        let rows = stmt.query_map([], |row| row.get::<_, Vec<u8>>(0))?;
+        let mut blocked = BTreeSet::new();
+        for row in rows {
+            blocked.insert(channel_id_from_bytes(&row?)?);
+        }
+        Ok(blocked)
+    }
+
+    fn load_identities(&self) -> anyhow::Result<BTreeMap<Uuid, Identity>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, key, is_verified FROM identities")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, Vec<u8>>(0)?,
+                Identity {
+                    key: row.get(1)?,
+                    is_verified: row.get(2)?,
+                },
+            ))
+        })?;
+        let mut identities = BTreeMap::new();
+        for row in rows {
+            let (id, identity) = row?;
+            identities.insert(Uuid::from_slice(&id)?, identity);
+        }
+        Ok(identities)
+    }
+
+    fn load_outbox(&self) -> anyhow::Result<Outbox> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT id, item, attempts FROM outbox ORDER BY id")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, u32>(2)?,
+            ))
+        })?;
+        let mut items = Vec::new();
+        for row in rows {
+            let (id, item, attempts) = row?;
+            items.push(QueuedItem {
+                id: id as u64,
+                item: serde_json::from_str(&item).context("invalid outbox item")?,
+                attempts,
+            });
+        }
+        Ok(Outbox::with_items(items))
+    }
+
+    fn load_channels(&self) -> anyhow::Result<Vec<Channel>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT id, name, group_master_key, group_revision, unread_messages, expire_timer,
+                is_message_request
+            FROM channels ORDER BY position",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, Vec<u8>>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, Option<Vec<u8>>>(2)?,
+                row.get::<_, Option<u32>>(3)?,
+                row.get::<_, i64>(4)?,
+                row.get::<_, Option<u32>>(5)?,
+                row.get::<_, bool>(6)?,
+            ))
+        })?;
+
+        let mut channels = Vec::new();
+        for row in rows {
+            let (
+                id_bytes,
+                name,
+                master_key,
+                revision,
+                unread_messages,
+                expire_timer,
+                is_message_request,
+            ) = row?;
+            let id = channel_id_from_bytes(&id_bytes)?;
+            let group_data = match (master_key, revision) {
+                (Some(master_key), Some(revision)) => Some(GroupData {
+                    master_key_bytes: master_key
+                        .try_into()
+                        .map_err(|_| anyhow!("invalid group master key"))?,
+                    members: self.load_group_members(&id_bytes)?,
+                    revision,
+                }),
+                _ => None,
+            };
+            channels.push(Channel {
+                id,
+                name,
+                group_data,
+                messages: StatefulList::with_items(self.query_messages(
+                    &id_bytes,
+                    None,
+                    None,
+                    MESSAGES_PAGE_SIZE,
+                )?),
+                unread_messages: unread_messages as usize,
+                expire_timer,
+                is_message_request,
+                typing: Default::default(),
+            });
+        }
+        Ok(channels)
+    }
+
+    fn load_group_members(&self, channel_id: &[u8]) -> anyhow::Result<Vec<Uuid>> {
+        let mut stmt = self
+            .conn
+            .prepare("SELECT member FROM group_members WHERE channel_id = ?1 ORDER BY position")?;
+        let rows = stmt.query_map(params![channel_id], |row| row.get::<_, Vec<u8>>(0))?;
+        let mut members = Vec::new();
+        for row in rows {
+            members.push(Uuid::from_slice(&row?)?);
+        }
+        Ok(members)
+    }
+
+    /// Loads up to `limit` latest messages of the channel in insertion order.
+    ///
+    /// If `before` is given, only messages inserted before the message arrived at `before` are
+    /// loaded. If `at` is given, only messages arrived at `at` are loaded.
+    fn query_messages(
+        &self,
+        channel_id: &[u8],
+        before: Option<u64>,
+        at: Option<u64>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<Message>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT arrived_at, from_id, body, quote, receipt, is_deleted,
+                expires_in, expire_started_at, is_system, is_warning, mentions, sticker
+            FROM messages
+            WHERE channel_id = ?1 AND (?2 IS NULL OR id < (
+                SELECT MIN(id) FROM messages WHERE channel_id = ?1 AND arrived_at = ?2
+            )) AND (?4 IS NULL OR arrived_at = ?4)
+            ORDER BY id DESC LIMIT ?3",
+        )?;
+        let rows = stmt.query_map(
+            params![
+                channel_id,
+                before.map(|before| before as i64),
+                limit as i64,
+                at.map(|at| at as i64)
+            ],
+            |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    row.get::<_, Vec<u8>>(1)?,
+                    row.get::<_, Option<String>>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                    row.get::<_, String>(4)?,
+                    row.get::<_, bool>(5)?,
+                    row.get::<_, Option<u32>>(6)?,
+                    row.get::<_, Option<i64>>(7)?,
+                    row.get::<_, bool>(8)?,
+                    row.get::<_, bool>(9)?,
+                    row.get::<_, Option<String>>(10)?,
+                    row.get::<_, Option<String>>(11)?,
+                ))
+            },
+        )?;
+        let mut rows = rows.collect::<Result<Vec<_>, _>>()?;
+        rows.reverse();
+
+        let (min_arrived_at, max_arrived_at) = match rows.iter().map(|row| row.0).minmax() {
+            MinMaxResult::NoElements => return Ok(Vec::new()),
+            MinMaxResult::OneElement(arrived_at) => (arrived_at, arrived_at),
+            MinMaxResult::MinMax(min, max) => (min, max),
+        };
+        let mut reactions = self.load_reactions(channel_id, min_arrived_at, max_arrived_at)?;
+        let mut attachments = self.load_attachments(channel_id, min_arrived_at, max_arrived_at)?;
+
+        let mut messages = Vec::with_capacity(rows.len());
+        for (
+            arrived_at,
+            from_id,
+            body,
+            quote,
+            receipt,
+            is_deleted,
+            expires_in,
+            expire_started_at,
+            is_system,
+            is_warning,
+            mentions,
+            sticker,
+        ) in rows
+        {
+            let arrived_at = arrived_at as u64;
+            let quote = quote
+                .map(|quote| serde_json::from_str(&quote))
+                .transpose()?;
+            let mentions = mentions
+                .map(|mentions| serde_json::from_str(&mentions))
+                .transpose()?
+                .unwrap_or_default();
+            let sticker = sticker
+                .map(|sticker| serde_json::from_str(&sticker))
+                .transpose()?;
+            messages.push(Message {
+                from_id: Uuid::from_slice(&from_id)?,
+                message: body,
+                arrived_at,
+                quote,
+                attachments: attachments.remove(&arrived_at).unwrap_or_default(),
+                reactions: reactions.remove(&arrived_at).unwrap_or_default(),
+                receipt: receipt_from_str(&receipt)?,
+                is_deleted,
+                expires_in,
+                expire_started_at: expire_started_at.map(|at| at as u64),
+                is_system,
+                is_warning,
+                mentions,
+                sticker,
+            });
+        }
+        Ok(messages)
+    }
+
+    /// Loads the reactions of the messages arrived in the given (inclusive) time range.
+    fn load_reactions(
+        &self,
+        channel_id: &[u8],
+        from_arrived_at: i64,
+        to_arrived_at: i64,
+    ) -> anyhow::Result<HashMap<u64, Vec<(Uuid, String)>>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT arrived_at, from_id, emoji FROM reactions
+            WHERE channel_id = ?1 AND arrived_at BETWEEN ?2 AND ?3 ORDER BY rowid",
+        )?;
+        let rows = stmt.query_map(params![channel_id, from_arrived_at, to_arrived_at], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, Vec<u8>>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?;
+        let mut reactions: HashMap<u64, Vec<(Uuid, String)>> = HashMap::new();
+        for row in rows {
+            let (arrived_at, from_id, emoji) = row?;
+            reactions
+                .entry(arrived_at as u64)
+                .or_default()
+                .push((Uuid::from_slice(&from_id)?, emoji));
+        }
+        Ok(reactions)
+    }
+
+    /// Loads the attachments of the messages arrived in the given (inclusive) time range.
+    fn load_attachments(
+        &self,
+        channel_id: &[u8],
+        from_arrived_at: i64,
+        to_arrived_at: i64,
+    ) -> anyhow::Result<HashMap<u64, Vec<Attachment>>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT arrived_at, id, content_type, filename, size
+            FROM attachments
+            WHERE channel_id = ?1 AND arrived_at BETWEEN ?2 AND ?3 ORDER BY rowid",
+        )?;
+        let rows = stmt.query_map(params![channel_id, from_arrived_at, to_arrived_at], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                Attachment {
+                    id: row.get(1)?,
+                    content_type: row.get(2)?,
+                    filename: row.get::<_, String>(3)?.into(),
+                    size: row.get(4)?,
+                },
+            ))
+        })?;
+        let mut attachments: HashMap<u64, Vec<Attachment>> = HashMap::new();
+        for row in rows {
+            let (arrived_at, attachment) = row?;
+            attachments
+                .entry(arrived_at as u64)
+                .or_default()
+                .push(attachment);
+        }
+        Ok(attachments)
+    }
+}
+
+/// Writes the full app data.
+///
+/// Channels and messages which are not in the app data anymore are deleted. Messages older
+/// than the oldest loaded message of a channel are not in the app data and are kept.
+fn write_app_data(conn: &Connection, data: &AppData) -> anyhow::Result<()> {
+    let channel_ids: HashSet<Vec<u8>> = data
+        .channels
+        .items
+        .iter()
+        .map(|channel| channel_id_to_bytes(channel.id))
+        .collect();
+    let stored_channel_ids: Vec<Vec<u8>> = conn
+        .prepare("SELECT id FROM channels")?
+        .query_map([], |row| row.get(0))?
+        .collect::<Result<_, _>>()?;
+    for channel_id in stored_channel_ids {
+        if !channel_ids.contains(&channel_id) {
+            delete_channel_rows(conn, &channel_id)?;
+        }
+    }
+
+    for (position, channel) in data.channels.items.iter().enumerate() {
+        upsert_channel(conn, channel, Some(position))?;
+        for message in &channel.messages.items {
+            upsert_message(conn, channel.id, message)?;
+        }
+        delete_missing_messages(conn, channel)?;
+    }
+    for (id, name) in &data.names {
+        upsert_name(conn, *id, name)?;
+    }
+    replace_used_words(conn, &data.used_words)?;
+    replace_outbox(conn, &data.outbox)?;
+    replace_blocked(conn, &data.blocked, &data.synced_blocked)?;
+    replace_identities(conn, &data.identities)?;
+    Ok(())
+}
+
+/// Deletes the stored messages of the channel which are not among its loaded messages.
+fn delete_missing_messages(conn: &Connection, channel: &Channel) -> anyhow::Result<()> {
+    let channel_id = channel_id_to_bytes(channel.id);
+    let oldest_id: Option<i64> = match channel.messages.items.first() {
+        Some(oldest) => conn.query_row(
+            "SELECT MIN(id) FROM messages WHERE channel_id = ?1 AND arrived_at = ?2",
+            params![channel_id, oldest.arrived_at as i64],
+            |row| row.get(0),
+        )?,
+        None => None,
+    };
+    let loaded: HashSet<i64> = channel
+        .messages
+        .items
+        .iter()
+        .map(|message| message.arrived_at as i64)
+        .collect();
+    let missing: Vec<i64> = conn
+        .prepare("SELECT arrived_at FROM messages WHERE channel_id = ?1 AND id >= ?2")?
+        .query_map(params![channel_id, oldest_id.unwrap_or(i64::MIN)], |row| {
+            row.get(0)
+        })?
+        .filter_ok(|arrived_at| !loaded.contains(arrived_at))
+        .collect::<Result<_, _>>()?;
+    for arrived_at in missing {
+        delete_message_rows(conn, &channel_id, arrived_at)?;
+    }
+    Ok(())
+}
+
+fn delete_message_rows(
+    conn: &Connection,
+    channel_id: &[u8],
+    arrived_at: i64,
+) -> anyhow::Result<()> {
+    for table in ["messages", "reactions", "attachments"] {
+        conn.execute(
+            &format!(
+                "DELETE FROM {} WHERE channel_id = ?1 AND arrived_at = ?2",
+                table
+            ),
+            params![channel_id, arrived_at],
+        )?;
+    }
+    Ok(())
+}
+
+fn delete_channel_rows(conn: &Connection, channel_id: &[u8]) -> anyhow::Result<()> {
+    for table in ["reactions", "attachments", "messages"] {
+        conn.execute(
+            &format!("DELETE FROM {} WHERE channel_id = ?1", table),
+            params![channel_id],
+        )?;
+    }
+    // group members are deleted by cascade
+    conn.execute("DELETE FROM channels WHERE id = ?1", params![channel_id])?;
+    Ok(())
+}
+
+/// Inserts or updates the channel.
+///
+/// If no position is given, a new channel is put at the end of the list, and the position of an
+/// existing channel is kept.
+fn upsert_channel(
+    conn: &Connection,
+    channel: &Channel,
+    position: Option<usize>,
+) -> anyhow::Result<()> {
+    let id = channel_id_to_bytes(channel.id);
+    let (master_key, revision) = match channel.group_data.as_ref() {
+        Some(group_data) => (
+            Some(group_data.master_key_bytes.to_vec()),
+            Some(group_data.revision),
+        ),
+        None => (None, None),
+    };
+    conn.execute(
+        "INSERT INTO channels
+            (id, name, group_master_key, group_revision, unread_messages, position, expire_timer,
+                is_message_request)
+        VALUES (?1, ?2, ?3, ?4, ?5, COALESCE(?6, (SELECT MAX(position) + 1 FROM channels), 0), ?7,
+            ?8)
+        ON CONFLICT (id) DO UPDATE SET
+            name = excluded.name,
+            group_master_key = excluded.group_master_key,
+            group_revision = excluded.group_revision,
+            unread_messages = excluded.unread_messages,
+            position = COALESCE(?6, position),
+            expire_timer = excluded.expire_timer,
+            is_message_request = excluded.is_message_request",
+        params![
+            id,
+            channel.name,
+            master_key,
+            revision,
+            channel.unread_messages as i64,
+            position.map(|position| position as i64),
+            channel.expire_timer,
+            channel.is_message_request,
+        ],
+    )?;
+
+    conn.execute(
+        "DELETE FROM group_members WHERE channel_id = ?1",
+        params![id],
+    )?;
+    if let Some(group_data) = channel.group_data.as_ref() {
+        for (position, member) in group_data.members.iter().enumerate() {
+            conn.execute(
+                "INSERT OR IGNORE INTO group_members (channel_id, member, position)
+                VALUES (?1, ?2, ?3)",
+                params![id, &member.as_bytes()[..], position as i64],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn upsert_message(
+    conn: &Connection,
+    channel_id: ChannelId,
+    message: &Message,
+) -> anyhow::Result<()> {
+    let channel_id = channel_id_to_bytes(channel_id);
+    let arrived_at = message.arrived_at as i64;
+    let quote = message
+        .quote
+        .as_ref()
+        .map(serde_json::to_string)
+        .transpose()?;
+    let mentions = Some(&message.mentions)
+        .filter(|mentions| !mentions.is_empty())
+        .map(serde_json::to_string)
+        .transpose()?;
+    let sticker = message
+        .sticker
+        .as_ref()
+        .map(serde_json::to_string)
+        .transpose()?;
+    conn.execute(
+        "INSERT INTO messages (
+            channel_id, arrived_at, from_id, body, quote, receipt, is_deleted,
+            expires_in, expire_started_at, is_system, is_warning, mentions, sticker
+        )
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)
+        ON CONFLICT (channel_id, arrived_at, from_id) DO UPDATE SET
+            body = excluded.body,
+            quote = excluded.quote,
+            receipt = excluded.receipt,
+            is_deleted = excluded.is_deleted,
+            expires_in = excluded.expires_in,
+            expire_started_at = excluded.expire_started_at,
+            is_system = excluded.is_system,
+            is_warning = excluded.is_warning,
+            mentions = excluded.mentions,
+            sticker = excluded.sticker",
+        params![
+            channel_id,
+            arrived_at,
+            &message.from_id.as_bytes()[..],
+            message.message,
+            quote,
+            receipt_to_str(message.receipt),
+            message.is_deleted,
+            message.expires_in,
+            message.expire_started_at.map(|at| at as i64),
+            message.is_system,
+            message.is_warning,
+            mentions,
+            sticker,
+        ],
+    )?;
+
+    conn.execute(
+        "DELETE FROM reactions WHERE channel_id = ?1 AND arrived_at = ?2",
+        params![channel_id, arrived_at],
+    )?;
+    for (from_id, emoji) in &message.reactions {
+        conn.execute(
+            "INSERT OR REPLACE INTO reactions (channel_id, arrived_at, from_id, emoji)
+            VALUES (?1, ?2, ?3, ?4)",
+            params![channel_id, arrived_at, &from_id.as_bytes()[..], emoji],
+        )?;
+    }
+
+    conn.execute(
+        "DELETE FROM attachments WHERE channel_id = ?1 AND arrived_at = ?2",
+        params![channel_id, arrived_at],
+    )?;
+    for attachment in &message.attachments {
+        conn.execute(
+            "INSERT INTO attachments (channel_id, arrived_at, id, content_type, filename, size)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                channel_id,
+                arrived_at,
+                attachment.id,
+                attachment.content_type,
+                attachment.filename.to_string_lossy().into_owned(),
+                attachment.size,
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+fn replace_outbox(conn: &Connection, outbox: &Outbox) -> anyhow::Result<()> {
+    conn.execute("DELETE FROM outbox", [])?;
+    for queued in outbox.items() {
+        conn.execute(
+            "INSERT INTO outbox (id, item, attempts) VALUES (?1, ?2, ?3)",
+            params![
+                queued.id as i64,
+                serde_json::to_string(&queued.item)?,
+                queued.attempts
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+fn replace_used_words(conn: &Connection, used_words: &HashSet<String>) -> anyhow::Result<()> {
+    conn.execute("DELETE FROM used_words", [])?;
+    for word in used_words {
+        conn.execute("INSERT INTO used_words (word) VALUES (?1)", params![word])?;
+    }
+    Ok(())
+}
+
+fn replace_blocked(
+    conn: &Connection,
+    blocked: &BTreeSet<ChannelId>,
+    synced_blocked: &BTreeSet<ChannelId>,
+) -> anyhow::Result<()> {
+    for (table, channel_ids) in [("blocked", blocked), ("synced_blocked", synced_blocked)] {
+        conn.execute(&format!("DELETE FROM {}", table), [])?;
+        for &channel_id in channel_ids {
+            conn.execute(
+                &format!("INSERT INTO {} (channel_id) VALUES (?1)", table),
+                params![channel_id_to_bytes(channel_id)],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn replace_identities(
+    conn: &Connection,
+    identities: &BTreeMap<Uuid, Identity>,
+) -> anyhow::Result<()> {
+    conn.execute("DELETE FROM identities", [])?;
+    for (id, identity) in identities {
+        conn.execute(
+            "INSERT INTO identities (id, key, is_verified) VALUES (?1, ?2, ?3)",
+            params![&id.as_bytes()[..], identity.key, identity.is_verified],
+        )?;
+    }
+    Ok(())
+}
+
+fn upsert_name(conn: &Connection, id: Uuid, name: &str) -> anyhow::Result<()> {
+    conn.execute(
+        "INSERT INTO names (id, name) VALUES (?1, ?2)
+        ON CONFLICT (id) DO UPDATE SET name = excluded.name",
+        params![&id.as_bytes()[..], name],
+    )?;
+    Ok(())
+}
+
+/// User channels are stored as 16 bytes uuids, group channels as 32 bytes group identifiers.
+fn channel_id_to_bytes(id: ChannelId) -> Vec<u8> {
+    match id {
+        ChannelId::User(uuid) => uuid.as_bytes().to_vec(),
+        ChannelId::Group(group_id) => group_id.to_vec(),
+    }
+}
+
+fn channel_id_from_bytes(bytes: &[u8]) -> anyhow::Result<ChannelId> {
+    match bytes.len() {
+        16 => Ok(ChannelId::User(Uuid::from_slice(bytes)?)),
+        _ => Ok(ChannelId::Group(bytes.try_into().map_err(|_| {
+            anyhow!("invalid channel id of length {}", bytes.len())
+        })?)),
+    }
+}
+
+fn receipt_to_str(receipt: Receipt) -> &'static str {
+    match receipt {
+        Receipt::Nothing => "nothing",
+        Receipt::Queued => "queued",
+        Receipt::Pending => "pending",
+        Receipt::Failed => "failed",
+        Receipt::Sent => "sent",
+        Receipt::Received => "received",
+        Receipt::Delivered => "delivered",
+    }
+}
+
+fn receipt_from_str(s: &str) -> anyhow::Result<Receipt> {
+    Ok(match s {
+        "nothing" => Receipt::Nothing,
+        "queued" => Receipt::Queued,
+        "pending" => Receipt::Pending,
+        "failed" => Receipt::Failed,
+        "sent" => Receipt::Sent,
+        "received" => Receipt::Received,
+        "delivered" => Receipt::Delivered,
+        _ => return Err(anyhow!("invalid receipt: {}", s)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::outbox::OutboxItem;
+    use crate::storage::test::test_app_data;
+
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_sqlite_storage_save_and_load() -> anyhow::Result<()> {
+        let user_id = Uuid::new_v4();
+        let user_name = "Tyler Durden".to_string();
+        let app_data = test_app_data(user_id, &user_name);
+
+        let storage = SqliteStorage::open_in_memory()?;
+        storage.save_app_data(&app_data)?;
+        // saving twice must not duplicate anything
+        storage.save_app_data(&app_data)?;
+        let loaded_app_data = storage.load_app_data(user_id, user_name)?;
+
+        assert_eq!(loaded_app_data, app_data);
+        assert_eq!(loaded_app_data.channels.state.selected(), Some(0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sqlite_storage_save_deletes_removed_data() -> anyhow::Result<()> {
+        let user_id = Uuid::new_v4();
+        let user_name = "Tyler Durden".to_string();
+        let mut app_data = test_app_data(user_id, &user_name);
+
+        let storage = SqliteStorage::open_in_memory()?;
+        storage.save_app_data(&app_data)?;
+        app_data.channels.items.pop();
+        app_data.channels.items[0].messages.items.remove(0);
+        storage.save_app_data(&app_data)?;
+        let loaded_app_data = storage.load_app_data(user_id, user_name)?;
+
+        assert_eq!(loaded_app_data.channels.items, app_data.channels.items);
+        let num_attachments: i64 =
+            storage
+                .conn
+                .query_row("SELECT COUNT(*) FROM attachments", [], |row| row.get(0))?;
+        assert_eq!(num_attachments, 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sqlite_storage_granular_operations() -> anyhow::Result<()> {
+        let user_id = Uuid::new_v4();
+        let user_name = "Tyler Durden".to_string();
+        let mut app_data = test_app_data(user_id, &user_name);
+
+        let storage = SqliteStorage::open_in_memory()?;
+        storage.save_app_data(&app_data)?;
+
+        // new message in the user channel
+        let message = Message::text(user_id, "How's the soap business?", 3);
+        let user_channel = &mut app_data.channels.items[0];
+        user_channel.messages.items.push(message.clone());
+        storage.append_message(user_channel, &message)?;
+
+        // receipt and reactions
+        user_channel.messages.items[2].receipt = Receipt::Received;
+        storage.update_receipt(user_channel.id, 3, Receipt::Received)?;
+        user_channel.messages.items[0].reactions.clear();
+        storage.upsert_reaction(user_channel.id, 1, user_id, None)?;
+        let contact_id = user_channel.messages.items[0].from_id;
+        user_channel.messages.items[1]
+            .reactions
+            .push((contact_id, "🧼".to_string()));
+        storage.upsert_reaction(user_channel.id, 2, contact_id, Some("🧼"))?;
+
+        // message deleted for everyone
+        user_channel.messages.items[0].delete();
+        storage.append_message(user_channel, &user_channel.messages.items[0])?;
+
+        // discarded message
+        let failed_message = Message {
+            arrived_at: 4,
+            reactions: vec![(contact_id, "👎".to_string())],
+            receipt: Receipt::Failed,
+            ..message
+        };
+        storage.append_message(user_channel, &failed_message)?;
+        storage.delete_message(user_channel.id, 4)?;
+
+        // channel meta and order
+        user_channel.unread_messages = 0;
+        user_channel.name = "Marla".to_string();
+        storage.update_channel_meta(user_channel)?;
+        app_data.channels.items.swap(0, 1);
+        let channel_ids: Vec<_> = app_data.channels.items.iter().map(|c| c.id).collect();
+        storage.update_channel_order(&channel_ids)?;
+
+        // names
+        app_data.names.insert(contact_id, "Marla".to_string());
+        storage.upsert_name(contact_id, "Marla")?;
+
+        // used words
+        app_data.used_words.insert("business".to_string());
+        storage.update_used_words(&app_data.used_words)?;
+
+        // outbox
+        app_data.outbox.push(OutboxItem::Message {
+            channel_id: ChannelId::User(contact_id),
+            arrived_at: 3,
+        });
+        storage.update_outbox(&app_data.outbox)?;
+
+        // blocked contacts and groups
+        app_data.blocked.insert(ChannelId::User(contact_id));
+        app_data.synced_blocked.insert(ChannelId::Group([42; 32]));
+        storage.update_blocked(&app_data.blocked, &app_data.synced_blocked)?;
+
+        // identities
+        app_data.identities.insert(
+            user_id,
+            Identity {
+                key: vec![7; 33],
+                is_verified: false,
+            },
+        );
+        storage.update_identities(&app_data.identities)?;
+
+        // deleted channel
+        let deleted_channel = app_data.channels.items.remove(0);
+        storage.delete_channel(deleted_channel.id)?;
+
+        let loaded_app_data = storage.load_app_data(user_id, user_name)?;
+        assert_eq!(loaded_app_data, app_data);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sqlite_storage_append_message_to_new_channel() -> anyhow::Result<()> {
+        let user_id = Uuid::new_v4();
+        let user_name = "Tyler Durden".to_string();
+        let app_data = test_app_data(user_id, &user_name);
+        let channel = &app_data.channels.items[0];
+
+        let storage = SqliteStorage::open_in_memory()?;
+        for message in &channel.messages.items {
+            storage.append_message(channel, message)?;
+        }
+        let loaded_app_data = storage.load_app_data(user_id, user_name)?;
+
+        assert_eq!(
+            loaded_app_data.channels.items[..],
+            app_data.channels.items[..1]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sqlite_storage_load_message() -> anyhow::Result<()> {
+        let user_id = Uuid::new_v4();
+        let app_data = test_app_data(user_id, "Tyler Durden");
+        let storage = SqliteStorage::open_in_memory()?;
+        storage.save_app_data(&app_data)?;
+
+        let channel = &app_data.channels.items[0];
+        let message = storage.load_message(channel.id, 1)?;
+        assert_eq!(message.as_ref(), Some(&channel.messages.items[0]));
+        assert_eq!(storage.load_message(channel.id, 42)?, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sqlite_storage_delete_expired_messages() -> anyhow::Result<()> {
+        let user_id = Uuid::new_v4();
+        let user_name = "Tyler Durden".to_string();
+        let mut app_data = test_app_data(user_id, &user_name);
+        // the timer of an unread message did not start yet
+        app_data.channels.items[0].messages.items[0].expires_in = Some(1);
+
+        let storage = SqliteStorage::open_in_memory()?;
+        storage.save_app_data(&app_data)?;
+
+        assert!(storage
+            .delete_expired_messages(2 + 604800 * 1000 - 1)?
+            .is_empty());
+        let loaded_app_data = storage.load_app_data(user_id, user_name.clone())?;
+        assert_eq!(loaded_app_data, app_data);
+
+        let expired = storage.delete_expired_messages(2 + 604800 * 1000)?;
+        let channel = &mut app_data.channels.items[0];
+        let message = channel.messages.items.pop().unwrap();
+        assert_eq!(expired, [(channel.id, message)]);
+        let loaded_app_data = storage.load_app_data(user_id, user_name)?;
+        assert_eq!(loaded_app_data, app_data);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sqlite_storage_load_empty() -> anyhow::Result<()> {
+        let user_id = Uuid::new_v4();
+        let user_name = "Tyler Durden".to_string();
+
+        let storage = SqliteStorage::open_in_memory()?;
+        let app_data = storage.load_app_data(user_id, user_name.clone())?;
+
+        assert_eq!(
+            app_data,
+            AppData {
+                names: [(user_id, user_name)].iter().cloned().collect(),
+                ..Default::default()
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sqlite_storage_migrate_from_json() -> anyhow::Result<()> {
+        let user_id = Uuid::new_v4();
+        let user_name = "Tyler Durden".to_string();
+        let app_data = test_app_data(user_id, &user_name);
+
+        let dir = tempdir()?;
+        let json_path = dir.path().join("gurk.data.json");
+        JsonStorage::save_to(&app_data, &json_path)?;
+        let json_storage = JsonStorage::new(json_path, None);
+
+        let mut storage = SqliteStorage::open(dir.path().join("gurk.sqlite"))?;
+        storage.migrate_from_json(&json_storage)?;
+        assert_eq!(storage.load_app_data(user_id, user_name.clone())?, app_data);
+
+        // second migration is a no-op and does not override newer data
+        let mut newer_app_data = test_app_data(user_id, &user_name);
+        newer_app_data.channels.items.remove(0);
+        storage.save_app_data(&newer_app_data)?;
+        storage.migrate_from_json(&json_storage)?;
+        let loaded_app_data = storage.load_app_data(user_id, user_name)?;
+        assert_eq!(
+            loaded_app_data.channels.items,
+            newer_app_data.channels.items
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sqlite_storage_read_only() -> anyhow::Result<()> {
+        let user_id = Uuid::new_v4();
+        let user_name = "Tyler Durden".to_string();
+        let app_data = test_app_data(user_id, &user_name);
+
+        let dir = tempdir()?;
+        let path = dir.path().join("gurk.sqlite");
+        assert!(SqliteStorage::open_read_only(&path).is_err());
+
+        // the JSON data is not migrated yet
+        let mut storage = SqliteStorage::open(&path)?;
+        assert!(SqliteStorage::open_read_only(&path).is_err());
+
+        let json_storage = JsonStorage::new(dir.path().join("gurk.data.json"), None);
+        storage.migrate_from_json(&json_storage)?;
+        storage.save_app_data(&app_data)?;
+        drop(storage);
+
+        let storage = SqliteStorage::open_read_only(&path)?;
+        assert_eq!(storage.load_app_data(user_id, user_name)?, app_data);
+        assert!(storage.save_app_data(&app_data).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sqlite_storage_schema_version() -> anyhow::Result<()> {
+        let dir = tempdir()?;
+        let path = dir.path().join("gurk.sqlite");
+
+        let storage = SqliteStorage::open(&path)?;
+        let version: u32 = storage
+            .conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        assert_eq!(version as usize, MIGRATIONS.len());
+
+        // reopening does not migrate again
+        drop(storage);
+        SqliteStorage::open(&path)?;
+
+        // newer schema is rejected
+        let conn = Connection::open(&path)?;
+        conn.pragma_update(None, "user_version", MIGRATIONS.len() as u32 + 1)?;
+        drop(conn);
+        assert!(SqliteStorage::open(&path).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sqlite_storage_load_messages_paginated() -> anyhow::Result<()> {
+        let user_id = Uuid::new_v4();
+        let user_name = "Tyler Durden".to_string();
+        let channel_id = ChannelId::User(user_id);
+        let num_messages = MESSAGES_PAGE_SIZE + 10;
+
+        let storage = SqliteStorage::open_in_memory()?;
+        let channel = Channel {
+            id: channel_id,
+            name: user_name.clone(),
+            group_data: None,
+            messages: Default::default(),
+            unread_messages: 0,
+            expire_timer: None,
+            is_message_request: false,
+            typing: Default::default(),
+        };
+        storage.update_channel_meta(&channel)?;
+        let messages: Vec<Message> = (0..num_messages as u64)
+            .map(|arrived_at| Message {
+                reactions: if arrived_at % 2 == 0 {
+                    vec![(user_id, "👍".to_string())]
+                } else {
+                    Default::default()
+                },
+                ..Message::text(user_id, &format!("message {}", arrived_at), arrived_at)
+            })
+            .collect();
+        for message in &messages {
+            storage.append_message(&channel, message)?;
+        }
+
+        // only the latest page is loaded on start
+        let data = storage.load_app_data(user_id, user_name)?;
+        let latest = &data.channels.items[0].messages.items;
+        assert_eq!(latest[..], messages[10..]);
+
+        // the rest is loaded on demand
+        let older = storage.load_messages(channel_id, latest[0].arrived_at, MESSAGES_PAGE_SIZE)?;
+        assert_eq!(older[..], messages[..10]);
+        let oldest = storage.load_messages(channel_id, older[0].arrived_at, MESSAGES_PAGE_SIZE)?;
+        assert!(oldest.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sqlite_storage_search_messages() -> anyhow::Result<()> {
+        let user_id = Uuid::new_v4();
+        let storage = SqliteStorage::open_in_memory()?;
+        let data = test_app_data(user_id, "Tyler Durden");
+        storage.save_app_data(&data)?;
+
+        let channel = &data.channels.items[0];
+        let results = storage.search_messages("HEL", 10)?.unwrap();
+        assert_eq!(
+            results,
+            [SearchResult::new(channel.id, &channel.messages.items[1]).unwrap()]
+        );
+
+        // changed bodies are reindexed
+        let mut message = channel.messages.items[1].clone();
+        message.message = Some("goodbye".to_string());
+        storage.append_message(channel, &message)?;
+        assert!(storage.search_messages("hello", 10)?.unwrap().is_empty());
+        assert_eq!(storage.search_messages("good", 10)?.unwrap().len(), 1);
+
+        // FTS5 syntax is not interpreted
+        assert!(storage
+            .search_messages("hi OR goodbye", 10)?
+            .unwrap()
+            .is_empty());
+        assert!(storage.search_messages("", 10)?.unwrap().is_empty());
+
+        Ok(())
+    }
+}
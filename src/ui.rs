@@ -1,4 +1,4 @@
-use crate::app::ReceiptEvent;
+use crate::app::{PendingChange, ReceiptEvent};
 use crate::cursor::Cursor;
 use crate::shortcuts::{ShortCut, SHORTCUTS};
 use crate::util;
@@ -7,19 +7,25 @@ use app::Receipt;
 
 use chrono::{Datelike, Timelike};
 use itertools::Itertools;
+use qrcode::render::unicode::Dense1x2;
+use qrcode::QrCode;
 use tui::backend::Backend;
-use tui::layout::{Constraint, Corner, Direction, Layout, Rect};
-use tui::style::{Color, Style};
+use tui::layout::{Alignment, Constraint, Corner, Direction, Layout, Rect};
+use tui::style::{Color, Modifier, Style};
 use tui::text::{Span, Spans, Text};
-use tui::widgets::{Block, Borders, List, ListItem, Paragraph};
+use tui::widgets::{Block, Borders, List, ListItem, Paragraph, Wrap};
 use tui::Frame;
 use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 use uuid::Uuid;
 
+use std::cmp::Reverse;
 use std::fmt;
 
 pub const CHANNEL_VIEW_RATIO: u32 = 4;
 
+/// Text shown instead of a message which was deleted for everyone
+const DELETED_MESSAGE_TEXT: &str = "This message was deleted.";
+
 pub fn coords_within_channels_view<B: Backend>(
     f: &Frame<B>,
     app: &App,
@@ -76,6 +82,18 @@ pub fn draw<B: Backend>(f: &mut Frame<B>, app: &mut App) {
         draw_help(f, app, chunks[1]);
         return;
     }
+    if app.safety_number().is_some() {
+        let chunks = Layout::default()
+            .constraints([
+                Constraint::Percentage(15),
+                Constraint::Percentage(70),
+                Constraint::Percentage(15),
+            ])
+            .direction(Direction::Horizontal)
+            .split(f.size());
+        draw_safety_number(f, app, chunks[1]);
+        return;
+    }
     let chunks = Layout::default()
         .constraints(
             [
@@ -133,14 +151,17 @@ fn draw_channels<B: Backend>(f: &mut Frame<B>, app: &mut App, area: Rect) {
         .channels
         .iter()
         .map(|channel| {
-            let unread_messages_label = if channel.unread_messages != 0 {
+            let mut label_suffix = if channel.unread_messages != 0 {
                 format!(" ({})", channel.unread_messages)
             } else {
                 String::new()
             };
-            let label = format!("{}{}", channel.name, unread_messages_label);
+            if channel.is_message_request {
+                label_suffix.push_str(" [request]");
+            }
+            let label = format!("{}{}", channel.name, label_suffix);
             let label_width = label.width();
-            let label = if label.width() <= channel_list_width || unread_messages_label.is_empty() {
+            let label = if label.width() <= channel_list_width || label_suffix.is_empty() {
                 label
             } else {
                 let diff = label_width - channel_list_width;
@@ -148,9 +169,16 @@ fn draw_channels<B: Backend>(f: &mut Frame<B>, app: &mut App, area: Rect) {
                 while !channel.name.is_char_boundary(end) {
                     end += 1;
                 }
-                format!("{}{}", &channel.name[0..end], unread_messages_label)
+                format!("{}{}", &channel.name[0..end], label_suffix)
+            };
+            let style = if app.data.blocked.contains(&channel.id) {
+                Style::default()
+                    .fg(Color::DarkGray)
+                    .add_modifier(Modifier::CROSSED_OUT)
+            } else {
+                Style::default()
             };
-            ListItem::new(vec![Spans::from(Span::raw(label))])
+            ListItem::new(vec![Spans::from(Span::styled(label, style))])
         })
         .collect();
     let channels = List::new(channels)
@@ -224,13 +252,58 @@ fn draw_chat<B: Backend>(f: &mut Frame<B>, app: &mut App, area: Rect) {
         .direction(Direction::Vertical)
         .split(area);
 
-    draw_messages(f, app, chunks[0]);
+    if app.is_message_search() {
+        app.update_search_results();
+        draw_search_results(f, app, chunks[0]);
+    } else {
+        draw_messages(f, app, chunks[0]);
+    }
 
     let title = if app.data.is_multiline_input {
         "Input (Multiline)"
     } else {
         "Input"
     };
+    let mut title = vec![Span::raw(title)];
+    let is_message_request = app
+        .data
+        .channels
+        .state
+        .selected()
+        .and_then(|idx| app.data.channels.filtered_items.get(idx))
+        .and_then(|&idx| app.data.channels.items.get(idx))
+        .map_or(false, |channel| channel.is_message_request);
+    if is_message_request {
+        title.push(Span::styled(
+            " | Message request: /accept, /delete or /block",
+            Style::default().fg(Color::Cyan),
+        ));
+    }
+    if !app.is_online {
+        title.push(Span::styled(
+            " | Offline",
+            Style::default().fg(Color::Yellow),
+        ));
+    }
+    if !app.data.outbox.is_empty() {
+        title.push(Span::styled(
+            format!(" | {} queued", app.data.outbox.len()),
+            Style::default().fg(Color::Yellow),
+        ));
+    }
+    if let Some(error) = app.command_error.as_ref() {
+        title.push(Span::styled(
+            format!(" | {}", error),
+            Style::default().fg(Color::Red),
+        ));
+    }
+    if let Some(error) = app.storage_error.as_ref() {
+        title.push(Span::styled(
+            format!(" | {}", error),
+            Style::default().fg(Color::Red),
+        ));
+    }
+    let title = Spans::from(title);
 
     let input = Paragraph::new(Text::from(wrapped_input))
         .block(Block::default().borders(Borders::ALL).title(title));
@@ -255,7 +328,8 @@ fn prepare_receipts(app: &mut App, height: usize) {
         .selected()
         .and_then(|idx| app.data.channels.items.get_mut(idx));
     let channel = match channel {
-        Some(c) if !c.messages.items.is_empty() => c,
+        // the sender of a message request doesn't learn that we read it
+        Some(c) if !c.messages.items.is_empty() && !c.is_message_request => c,
         _ => return,
     };
 
@@ -270,6 +344,7 @@ fn prepare_receipts(app: &mut App, height: usize) {
         channel.messages.rendered.offset
     };
 
+    let channel_id = channel.id;
     let messages = &mut channel.messages.items[..];
 
     let _ = messages
@@ -277,7 +352,12 @@ fn prepare_receipts(app: &mut App, height: usize) {
         .rev()
         .skip(offset)
         .for_each(|msg| match msg.receipt {
-            Receipt::Delivered | Receipt::Nothing | Receipt::Sent => (),
+            Receipt::Delivered
+            | Receipt::Nothing
+            | Receipt::Queued
+            | Receipt::Pending
+            | Receipt::Failed
+            | Receipt::Sent => (),
             Receipt::Received => {
                 if msg.from_id != user_id {
                     to_send.push((msg.from_id, msg.arrived_at));
@@ -286,6 +366,12 @@ fn prepare_receipts(app: &mut App, height: usize) {
             }
         });
     if !to_send.is_empty() {
+        for &(_, arrived_at) in &to_send {
+            app.mark_dirty(PendingChange::Receipt {
+                channel_id,
+                arrived_at,
+            });
+        }
         to_send
             .into_iter()
             .for_each(|(u, t)| app.add_receipt_event(ReceiptEvent::new(u, t, Receipt::Delivered)))
@@ -408,6 +494,59 @@ fn draw_messages<B: Backend>(f: &mut Frame<B>, app: &mut App, area: Rect) {
     channel.messages.rendered.offset = offset;
 }
 
+fn draw_search_results<B: Backend>(f: &mut Frame<B>, app: &mut App, area: Rect) {
+    let width = area.width.saturating_sub(2) as usize;
+    let first_name_only = app.config.first_name_only;
+    let items: Vec<ListItem> = app
+        .search_results
+        .items
+        .iter()
+        .map(|result| {
+            let channel_name = app
+                .data
+                .channels
+                .items
+                .iter()
+                .find(|channel| channel.id == result.channel_id)
+                .map(|channel| channel.name.as_str())
+                .unwrap_or_default();
+            let name = app.name_by_id(result.from_id);
+            let dt = util::utc_timestamp_msec_to_local(result.arrived_at);
+            let header = Spans::from(vec![
+                Span::styled(
+                    format!("{} ", dt.format("%Y-%m-%d %H:%M")),
+                    Style::default().fg(Color::Yellow),
+                ),
+                Span::raw(format!("{} | ", channel_name)),
+                Span::styled(
+                    displayed_name(name, first_name_only).to_string(),
+                    Style::default().fg(user_color(name)),
+                ),
+            ]);
+            // show the first line of the message only
+            let mut line_width = 0;
+            let text: String = result
+                .text
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .chars()
+                .take_while(|c| {
+                    line_width += c.width().unwrap_or(0);
+                    line_width <= width
+                })
+                .collect();
+            ListItem::new(vec![header, Spans::from(text)])
+        })
+        .collect();
+
+    let title = format!("Search results ({})", items.len());
+    let list = List::new(items)
+        .block(Block::default().title(title).borders(Borders::ALL))
+        .highlight_style(Style::default().fg(Color::Black).bg(Color::Gray));
+    f.render_stateful_widget(list, area, &mut app.search_results.state);
+}
+
 fn display_datetime(timestamp: u64) -> String {
     let dt = util::utc_timestamp_msec_to_local(timestamp);
     format!("{} {:02}:{:02} ", dt.weekday(), dt.hour(), dt.minute())
@@ -426,6 +565,9 @@ fn display_message(
         display_datetime(msg.arrived_at),
         Style::default().fg(Color::Yellow),
     );
+    if msg.is_system {
+        return Some(display_system_message(msg, time, prefix, width));
+    }
 
     let (from, from_color) = names.resolve(msg.from_id);
 
@@ -447,8 +589,34 @@ fn display_message(
         .initial_indent(prefix)
         .subsequent_indent(prefix);
 
+    // names with non-breaking spaces, s.t. mentions are not wrapped over multiple lines
+    let mention_names: Vec<(Uuid, String)> = msg
+        .mentions
+        .iter()
+        .map(|mention| {
+            let (name, _) = names.resolve(mention.uuid);
+            (mention.uuid, name.replace(' ', "\u{a0}"))
+        })
+        .collect();
+    let mentions: Vec<(String, Style)> = mention_names
+        .iter()
+        .map(|(uuid, name)| (format!("@{}", name), mention_style(*uuid == names.user_id)))
+        .collect();
+
     // collect message text
-    let mut text = msg.message.clone().unwrap_or_default();
+    let mut text = if msg.is_deleted {
+        DELETED_MESSAGE_TEXT.to_string()
+    } else {
+        msg.body_with_mentions(|id| {
+            mention_names
+                .iter()
+                .find(|(uuid, _)| *uuid == id)
+                .map(|(_, name)| name.as_str())
+                .unwrap_or_default()
+        })
+        .unwrap_or_default()
+    };
+    add_sticker(msg, &mut text);
     add_attachments(msg, &mut text);
     if text.is_empty() {
         return None; // no text => nothing to render
@@ -490,6 +658,13 @@ fn display_message(
             .collect();
     }
 
+    let text_style = if msg.receipt == Receipt::Failed {
+        Style::default().fg(Color::Red)
+    } else if msg.is_deleted {
+        dimmed_style()
+    } else {
+        Style::default()
+    };
     let add_time = spans.is_empty();
     spans.extend(
         textwrap::wrap(&text, &wrap_opts)
@@ -497,14 +672,12 @@ fn display_message(
             .enumerate()
             .map(|(idx, line)| {
                 let res = if add_time && idx == 0 {
-                    vec![
-                        time.clone(),
-                        from.clone(),
-                        delimiter.clone(),
-                        Span::from(line.strip_prefix(prefix).unwrap().to_string()),
-                    ]
+                    let mut res = vec![time.clone(), from.clone(), delimiter.clone()];
+                    let line = line.strip_prefix(prefix).unwrap();
+                    res.extend(styled_line(line, &mentions, text_style));
+                    res
                 } else {
-                    vec![Span::from(line.to_string())]
+                    styled_line(&line, &mentions, text_style)
                 };
                 Spans::from(res)
             }),
@@ -518,6 +691,89 @@ fn display_message(
     Some(ListItem::new(Text::from(spans)))
 }
 
+/// Renders the system line aligned with the message texts, without name and receipt.
+fn display_system_message(
+    msg: &app::Message,
+    time: Span<'static>,
+    prefix: &str,
+    width: usize,
+) -> ListItem<'static> {
+    let wrap_opts = textwrap::Options::new(width)
+        .initial_indent(prefix)
+        .subsequent_indent(prefix);
+    let padding = " ".repeat(prefix.width().saturating_sub(time.width()));
+    let text = msg.message.as_deref().unwrap_or_default();
+    let style = if msg.is_warning {
+        Style::default().fg(Color::Red).add_modifier(Modifier::BOLD)
+    } else {
+        dimmed_style()
+    };
+    let spans: Vec<Spans> = textwrap::wrap(text, &wrap_opts)
+        .into_iter()
+        .enumerate()
+        .map(|(idx, line)| {
+            if idx == 0 {
+                Spans::from(vec![
+                    time.clone(),
+                    Span::raw(padding.clone()),
+                    Span::styled(line.strip_prefix(prefix).unwrap().to_string(), style),
+                ])
+            } else {
+                Spans::from(Span::styled(line.to_string(), style))
+            }
+        })
+        .collect();
+    ListItem::new(Text::from(spans))
+}
+
+/// Splits the line into spans, s.t. the mentions in it are highlighted.
+fn styled_line(line: &str, mentions: &[(String, Style)], style: Style) -> Vec<Span<'static>> {
+    let mut spans = Vec::new();
+    let mut rest = line;
+    // next mention in the rest of the line, the longest if several start at the same position
+    while let Some((idx, mention, mention_style)) = mentions
+        .iter()
+        .filter_map(|(mention, mention_style)| Some((rest.find(mention)?, mention, mention_style)))
+        .min_by_key(|(idx, mention, _)| (*idx, Reverse(mention.len())))
+    {
+        if idx > 0 {
+            spans.push(Span::styled(rest[..idx].to_string(), style));
+        }
+        spans.push(Span::styled(mention.clone(), style.patch(*mention_style)));
+        rest = &rest[idx + mention.len()..];
+    }
+    if !rest.is_empty() || spans.is_empty() {
+        spans.push(Span::styled(rest.to_string(), style));
+    }
+    spans
+}
+
+/// Style of mentions, which stand out even more if they mention us
+fn mention_style(is_us: bool) -> Style {
+    let style = Style::default().add_modifier(Modifier::BOLD);
+    if is_us {
+        style.fg(Color::Black).bg(Color::Yellow)
+    } else {
+        style
+    }
+}
+
+/// Style of deleted messages and system lines
+fn dimmed_style() -> Style {
+    Style::default()
+        .fg(Color::Rgb(150, 150, 150))
+        .add_modifier(Modifier::ITALIC)
+}
+
+fn add_sticker(msg: &app::Message, out: &mut String) {
+    if let Some(sticker) = msg.sticker.as_ref() {
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        fmt::write(out, format_args!("{}", sticker)).expect("formatting sticker failed");
+    }
+}
+
 fn add_attachments(msg: &app::Message, out: &mut String) {
     if !msg.attachments.is_empty() {
         if !out.is_empty() {
@@ -629,6 +885,65 @@ fn draw_help<B: Backend>(f: &mut Frame<B>, app: &mut App, area: Rect) {
     f.render_stateful_widget(shorts_widget, area, &mut app.data.channels.state);
 }
 
+fn draw_safety_number<B: Backend>(f: &mut Frame<B>, app: &App, area: Rect) {
+    let (uuid, safety_number, is_verified) = match app.safety_number() {
+        Some(safety_number) => safety_number,
+        None => return,
+    };
+    let name = app.name_by_id(uuid);
+
+    // 12 groups of 5 digits in 3 lines, like in the Signal apps
+    let digits: Vec<char> = safety_number.digits.chars().collect();
+    let mut lines: Vec<Spans> = digits
+        .chunks(20)
+        .map(|line| {
+            let groups = line.chunks(5).map(|group| group.iter().collect::<String>());
+            Spans::from(Span::styled(
+                groups.join(" "),
+                Style::default().add_modifier(Modifier::BOLD),
+            ))
+        })
+        .collect();
+    lines.push(Spans::default());
+
+    match QrCode::new(&safety_number.scannable) {
+        Ok(code) => {
+            let qr_code = code
+                .render::<Dense1x2>()
+                .dark_color(Dense1x2::Light)
+                .light_color(Dense1x2::Dark)
+                .build();
+            lines.extend(qr_code.lines().map(|line| Spans::from(line.to_string())));
+        }
+        Err(e) => lines.push(Spans::from(format!("failed to render QR code: {}", e))),
+    }
+    lines.push(Spans::default());
+
+    if is_verified {
+        lines.push(Spans::from(Span::styled(
+            "Verified",
+            Style::default().fg(Color::Green),
+        )));
+    } else {
+        lines.push(Spans::from(format!(
+            "Not verified: compare the numbers with {}, or scan the code with their phone; \
+            then close this panel and mark it as verified with /verify",
+            name
+        )));
+    }
+    lines.push(Spans::from(Span::styled("f2: close", dimmed_style())));
+
+    let paragraph = Paragraph::new(Text::from(lines))
+        .alignment(Alignment::Center)
+        .wrap(Wrap { trim: false })
+        .block(
+            Block::default()
+                .borders(Borders::ALL)
+                .title(format!("Safety number with {}", name)),
+        );
+    f.render_widget(paragraph, area);
+}
+
 fn displayed_name(name: &str, first_name_only: bool) -> &str {
     if first_name_only {
         let space_pos = name.find(' ').unwrap_or_else(|| name.len());
@@ -659,6 +974,8 @@ fn user_color(username: &str) -> Color {
 /// Resolves names in a channel
 struct NameResolver<'a> {
     app: Option<&'a App>,
+    /// Our user, e.g. to highlight mentions of us
+    user_id: Uuid,
     names_and_colors: Vec<(Uuid, &'a str, Color)>,
     max_name_width: usize,
 }
@@ -718,6 +1035,7 @@ impl<'a> NameResolver<'a> {
 
         Self {
             app: Some(app),
+            user_id: app.user_id,
             names_and_colors,
             max_name_width,
         }
@@ -746,13 +1064,14 @@ impl<'a> NameResolver<'a> {
 
 fn displayed_quote(names: &NameResolver, quote: &app::Message) -> Option<String> {
     let (name, _) = names.resolve(quote.from_id);
-    Some(format!("({}) {}", name, quote.message.as_ref()?))
+    let text = quote.body_with_mentions(|id| names.resolve(id).0)?;
+    Some(format!("({}) {}", name, text))
 }
 
 #[cfg(test)]
 mod tests {
     use crate::app::{Message, Receipt};
-    use crate::signal::Attachment;
+    use crate::signal::{Attachment, Mention, Sticker};
 
     use super::*;
 
@@ -764,13 +1083,8 @@ mod tests {
 
     fn test_message() -> Message {
         Message {
-            from_id: Uuid::nil(),
             message: None,
-            arrived_at: 1642334397421,
-            quote: None,
-            attachments: vec![],
-            reactions: vec![],
-            receipt: Receipt::Sent,
+            ..Message::text(Uuid::nil(), "", 1642334397421)
         }
     }
 
@@ -787,6 +1101,7 @@ mod tests {
     fn test_display_attachment_only_message() {
         let names = NameResolver {
             app: None,
+            user_id: Uuid::nil(),
             names_and_colors: vec![(Uuid::nil(), "boxdot", Color::Green)],
             max_name_width: 6,
         };
@@ -818,6 +1133,7 @@ mod tests {
     fn test_display_text_and_attachment_message() {
         let names = NameResolver {
             app: None,
+            user_id: Uuid::nil(),
             names_and_colors: vec![(Uuid::nil(), "boxdot", Color::Green)],
             max_name_width: 6,
         };
@@ -848,4 +1164,194 @@ mod tests {
         ]));
         assert_eq!(rendered, Some(expected));
     }
+
+    #[test]
+    fn test_display_failed_message() {
+        let names = NameResolver {
+            app: None,
+            user_id: Uuid::nil(),
+            names_and_colors: vec![(Uuid::nil(), "boxdot", Color::Green)],
+            max_name_width: 6,
+        };
+
+        let msg = Message {
+            message: Some("Hello".to_string()),
+            receipt: Receipt::Failed,
+            ..test_message()
+        };
+        let rendered = display_message(&names, &msg, PREFIX, WIDTH, HEIGHT, PRINT_RECEIPT);
+
+        let expected = ListItem::new(Text::from(vec![Spans(vec![
+            Span::styled(
+                display_datetime(msg.arrived_at),
+                Style::default().fg(Color::Yellow),
+            ),
+            Span::styled("boxdot", Style::default().fg(Color::Green)),
+            Span::raw(": "),
+            Span::styled("Hello (failed)", Style::default().fg(Color::Red)),
+        ])]));
+        assert_eq!(rendered, Some(expected));
+    }
+
+    #[test]
+    fn test_display_message_with_mentions() {
+        let tyler = Uuid::from_u128(1);
+        let names = NameResolver {
+            app: None,
+            user_id: tyler,
+            names_and_colors: vec![
+                (Uuid::nil(), "boxdot", Color::Green),
+                (tyler, "Tyler Durden", Color::Red),
+            ],
+            max_name_width: 12,
+        };
+
+        let msg = Message {
+            message: Some("Hello \u{fffc}, meet \u{fffc}!".to_string()),
+            mentions: vec![
+                Mention {
+                    start: 6,
+                    length: 1,
+                    uuid: tyler,
+                },
+                Mention {
+                    start: 14,
+                    length: 1,
+                    uuid: Uuid::nil(),
+                },
+            ],
+            ..test_message()
+        };
+        let rendered = display_message(&names, &msg, PREFIX, WIDTH, HEIGHT, PRINT_RECEIPT);
+
+        let expected = ListItem::new(Text::from(vec![Spans(vec![
+            Span::styled(
+                display_datetime(msg.arrived_at),
+                Style::default().fg(Color::Yellow),
+            ),
+            Span::styled("      boxdot", Style::default().fg(Color::Green)),
+            Span::raw(": "),
+            Span::raw("Hello "),
+            Span::styled(
+                "@Tyler\u{a0}Durden",
+                Style::default()
+                    .fg(Color::Black)
+                    .bg(Color::Yellow)
+                    .add_modifier(Modifier::BOLD),
+            ),
+            Span::raw(", meet "),
+            Span::styled("@boxdot", Style::default().add_modifier(Modifier::BOLD)),
+            Span::raw("! (x)"),
+        ])]));
+        assert_eq!(rendered, Some(expected));
+    }
+
+    #[test]
+    fn test_display_sticker_message() {
+        let names = NameResolver {
+            app: None,
+            user_id: Uuid::nil(),
+            names_and_colors: vec![(Uuid::nil(), "boxdot", Color::Green)],
+            max_name_width: 6,
+        };
+
+        let msg = Message {
+            attachments: vec![Attachment {
+                id: "sticker".to_string(),
+                content_type: "image/webp".to_string(),
+                filename: "/tmp/gurk/sticker.webp".into(),
+                size: 1337,
+            }],
+            sticker: Some(Sticker {
+                pack_id: "0a1b".to_string(),
+                sticker_id: 7,
+                emoji: Some("👍".to_string()),
+            }),
+            ..test_message()
+        };
+        let rendered = display_message(&names, &msg, PREFIX, WIDTH, HEIGHT, PRINT_RECEIPT);
+
+        let expected = ListItem::new(Text::from(vec![
+            Spans(vec![
+                Span::styled(
+                    display_datetime(msg.arrived_at),
+                    Style::default().fg(Color::Yellow),
+                ),
+                Span::styled("boxdot", Style::default().fg(Color::Green)),
+                Span::raw(": "),
+                Span::raw("[Sticker 👍]"),
+            ]),
+            Spans(vec![Span::raw(
+                "                  <file:///tmp/gurk/sticker.webp> (x)",
+            )]),
+        ]));
+        assert_eq!(rendered, Some(expected));
+    }
+
+    #[test]
+    fn test_display_system_message() {
+        let names = NameResolver {
+            app: None,
+            user_id: Uuid::nil(),
+            names_and_colors: vec![(Uuid::nil(), "boxdot", Color::Green)],
+            max_name_width: 6,
+        };
+
+        let msg = Message::system(
+            Uuid::nil(),
+            "boxdot set the disappearing message time to 1 hour".to_string(),
+            1642334397421,
+        );
+        let rendered = display_message(&names, &msg, PREFIX, WIDTH, HEIGHT, PRINT_RECEIPT);
+
+        let style = Style::default()
+            .fg(Color::Rgb(150, 150, 150))
+            .add_modifier(Modifier::ITALIC);
+        let expected = ListItem::new(Text::from(vec![
+            Spans(vec![
+                Span::styled(
+                    display_datetime(msg.arrived_at),
+                    Style::default().fg(Color::Yellow),
+                ),
+                Span::raw("        "),
+                Span::styled("boxdot set the disappearing message time", style),
+            ]),
+            Spans(vec![Span::styled("                  to 1 hour", style)]),
+        ]));
+        assert_eq!(rendered, Some(expected));
+    }
+
+    #[test]
+    fn test_display_deleted_message() {
+        let names = NameResolver {
+            app: None,
+            user_id: Uuid::nil(),
+            names_and_colors: vec![(Uuid::nil(), "boxdot", Color::Green)],
+            max_name_width: 6,
+        };
+
+        let mut msg = Message {
+            message: Some("Hello".to_string()),
+            reactions: vec![(Uuid::nil(), "👍".to_string())],
+            ..test_message()
+        };
+        msg.delete();
+        let rendered = display_message(&names, &msg, PREFIX, WIDTH, HEIGHT, false);
+
+        let expected = ListItem::new(Text::from(vec![Spans(vec![
+            Span::styled(
+                display_datetime(msg.arrived_at),
+                Style::default().fg(Color::Yellow),
+            ),
+            Span::styled("boxdot", Style::default().fg(Color::Green)),
+            Span::raw(": "),
+            Span::styled(
+                "This message was deleted.",
+                Style::default()
+                    .fg(Color::Rgb(150, 150, 150))
+                    .add_modifier(Modifier::ITALIC),
+            ),
+        ])]));
+        assert_eq!(rendered, Some(expected));
+    }
 }